@@ -1,3 +1,325 @@
+#[cfg(feature = "generate")]
+extern crate bindgen;
+extern crate pkg_config;
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// the major versions of libalpm ffi.rs and ffi_git.rs are for
+const ABI: u32 = 12;
+const GIT_ABI: u32 = 13;
+
+fn feature(name: &str) -> bool {
+    env::var_os(format!("CARGO_FEATURE_{}", name)).is_some()
+}
+
+fn probe(link: bool) -> Option<pkg_config::Library> {
+    let statik = feature("STATIC");
+
+    match pkg_config::Config::new()
+        .statik(statik)
+        .cargo_metadata(link)
+        .probe("libalpm")
+    {
+        Ok(lib) => Some(lib),
+        // libalpm is not needed to check or document the crate, so fall back
+        // to linking by name and let the linker complain if it is missing
+        Err(e) => {
+            // pkg-config is not installed or disabled with LIBALPM_NO_PKG_CONFIG
+            let quiet = matches!(
+                e,
+                pkg_config::Error::Command { .. } | pkg_config::Error::EnvNoPkgConfig(_)
+            );
+            if !quiet {
+                // cargo warnings are a single line
+                let e = e
+                    .to_string()
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                println!(
+                    "cargo:warning=could not find libalpm with pkg-config, set \
+                     PKG_CONFIG_PATH if it is installed in a custom prefix: {}",
+                    e
+                );
+            }
+
+            if link {
+                let kind = if statik { "static=" } else { "" };
+                println!("cargo:rustc-link-lib={}alpm", kind);
+            }
+            None
+        }
+    }
+}
+
+fn abi(version: &str, git: bool) -> u32 {
+    let major = version.split('.').next().and_then(|v| v.parse().ok());
+
+    match major {
+        Some(GIT_ABI) => GIT_ABI,
+        Some(ABI) if !git => ABI,
+        Some(ABI) => panic!(
+            "the git feature needs libalpm {}.x but libalpm {} was found",
+            GIT_ABI, version
+        ),
+        _ => panic!(
+            "libalpm {} is not supported, only libalpm {}.x and {}.x are",
+            version, ABI, GIT_ABI
+        ),
+    }
+}
+
+#[cfg(feature = "generate")]
+fn generate(lib: &pkg_config::Library) -> Option<String> {
+    // the alpm.h of the libalpm the abi was taken from
+    let includedir = pkg_config::get_variable("libalpm", "includedir")
+        .unwrap_or_else(|e| panic!("could not get the includedir of libalpm: {}", e));
+    let header = Path::new(&includedir).join("alpm.h");
+    let include_paths = &lib.include_paths;
+    println!("cargo:rerun-if-changed={}", header.display());
+
+    // the same options as the bindgen script
+    let bindings = bindgen::Builder::default()
+        .header(header.to_string_lossy())
+        .clang_args(include_paths.iter().map(|p| format!("-I{}", p.display())))
+        .allowlist_type("(alpm|ALPM).*")
+        .allowlist_function("(alpm|ALPM).*")
+        .rustified_enum("_alpm_[a-z_]+_t")
+        .constified_enum_module("_alpm_siglevel_t")
+        .constified_enum_module("_alpm_pkgvalidation_t")
+        .constified_enum_module("_alpm_loglevel_t")
+        .constified_enum_module("_alpm_question_type_t")
+        .constified_enum_module("_alpm_transflag_t")
+        .constified_enum_module("_alpm_db_usage_")
+        .constified_enum_module("_alpm_db_usage_t")
+        .constified_enum_module("alpm_caps")
+        .opaque_type("alpm_handle_t")
+        .opaque_type("alpm_db_t")
+        .opaque_type("alpm_pkg_t")
+        .opaque_type("alpm_trans_t")
+        .generate()
+        .unwrap_or_else(|_| panic!("failed to generate bindings for {}", header.display()));
+
+    Some(bindings.to_string())
+}
+
+#[cfg(not(feature = "generate"))]
+fn generate(_lib: &pkg_config::Library) -> Option<String> {
+    None
+}
+
 fn main() {
-    println!("cargo:rustc-link-lib=alpm");
+    let dynamic = feature("DYNAMIC");
+    let git = feature("GIT");
+
+    let generate_bindings = feature("GENERATE");
+
+    // when loading libalpm at runtime it does not have to exist at build time,
+    // unless generating bindings which have to match the abi
+    let lib = if dynamic && !generate_bindings {
+        None
+    } else {
+        probe(!dynamic)
+    };
+    if generate_bindings && lib.is_none() {
+        panic!(
+            "the generate feature needs libalpm to be found with pkg-config to know \
+             which version its alpm.h is for"
+        );
+    }
+    let abi = match lib {
+        Some(ref lib) => abi(&lib.version, git),
+        None if git => GIT_ABI,
+        None => ABI,
+    };
+    // loading libalpm at runtime with the bundled bindings supports both abis
+    let multi_abi = dynamic && !generate_bindings;
+
+    println!("cargo:rustc-check-cfg=cfg(alpm_git)");
+    println!("cargo:rustc-check-cfg=cfg(alpm_generated)");
+    println!("cargo:rustc-check-cfg=cfg(alpm_multi_abi)");
+    if multi_abi {
+        println!("cargo:abi={},{}", ABI, GIT_ABI);
+        println!("cargo:rustc-cfg=alpm_multi_abi");
+    } else {
+        println!("cargo:abi={}", abi);
+        if abi == GIT_ABI {
+            println!("cargo:rustc-cfg=alpm_git");
+        }
+    }
+
+    if dynamic {
+        // dlopen is only in libdl before glibc 2.34
+        println!("cargo:rustc-link-lib=dl");
+    }
+
+    let bindings = match lib.as_ref().and_then(generate) {
+        Some(bindings) if dynamic => dynamic_ffi(&bindings),
+        Some(bindings) => bindings,
+        // the abi 12 bindings, with the functions that changed in abi 13 in
+        // the abi13 module
+        None if dynamic => {
+            println!("cargo:rerun-if-changed=src/ffi.rs");
+            println!("cargo:rerun-if-changed=src/ffi_git.rs");
+            let ffi = fs::read_to_string("src/ffi.rs").unwrap();
+            let ffi_git = fs::read_to_string("src/ffi_git.rs").unwrap();
+            let mut bindings = dynamic_ffi(&ffi);
+            bindings.push_str(&changed_ffi(&ffi, &ffi_git, GIT_ABI));
+            bindings
+        }
+        None => return,
+    };
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("ffi.rs");
+    fs::write(out, bindings).unwrap();
+    println!("cargo:rustc-cfg=alpm_generated");
+}
+
+// split the bindgen output into everything but the extern blocks and the
+// bodies of the extern blocks, which hold a single function each
+fn split_ffi(src: &str) -> (String, Vec<String>) {
+    let mut out = String::new();
+    let mut blocks = Vec::new();
+    let mut block = None;
+
+    for line in src.lines() {
+        match block {
+            None if line == "extern \"C\" {" => block = Some(String::new()),
+            None => {
+                out.push_str(line);
+                out.push('\n');
+            }
+            Some(body) if line == "}" => {
+                blocks.push(body);
+                block = None;
+            }
+            Some(ref mut body) => {
+                body.push_str(line.trim());
+                body.push('\n');
+            }
+        }
+    }
+
+    (out, blocks)
+}
+
+// replace every extern block of the bindgen output with wrappers that call
+// through symbols looked up at runtime
+fn dynamic_ffi(src: &str) -> String {
+    let (mut out, blocks) = split_ffi(src);
+    for body in &blocks {
+        out.push_str(&wrapper(body));
+    }
+    out
+}
+
+// wrappers for the functions of `new` that are declared differently in
+// `old`, in a module named after the abi of `new`
+fn changed_ffi(old: &str, new: &str, abi: u32) -> String {
+    // ignore changes to the docs
+    let decl = |body: &String| {
+        body.lines()
+            .filter(|l| !l.starts_with("#["))
+            .collect::<String>()
+    };
+    let old = split_ffi(old).1.iter().map(decl).collect::<HashSet<_>>();
+
+    let mut out = format!("pub mod abi{} {{\nuse super::*;\n", abi);
+    for body in split_ffi(new).1.iter().filter(|b| !old.contains(&decl(b))) {
+        out.push_str(&wrapper(body));
+    }
+    out.push_str("}\n");
+    out
+}
+
+// split on commas that are not nested in brackets
+fn split_args(args: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    let mut prev = ' ';
+
+    for (i, c) in args.char_indices() {
+        match c {
+            '(' | '<' | '[' => depth += 1,
+            // the arrow of a return type
+            '>' if prev == '-' => (),
+            ')' | '>' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(args[start..i].trim());
+                start = i + 1;
+            }
+            _ => (),
+        }
+        prev = c;
+    }
+    parts.push(args[start..].trim());
+    parts.retain(|p| !p.is_empty());
+    parts
+}
+
+fn wrapper(body: &str) -> String {
+    let mut attrs = String::new();
+    let mut decl = String::new();
+    for line in body.lines() {
+        if line.starts_with("#[") {
+            attrs.push_str(line);
+            attrs.push('\n');
+        } else {
+            decl.push_str(line);
+            decl.push(' ');
+        }
+    }
+
+    let decl = decl.trim().trim_end_matches(';');
+    let decl = decl
+        .strip_prefix("pub fn ")
+        .unwrap_or_else(|| panic!("unsupported extern item: {}", decl));
+    let open = decl.find('(').unwrap();
+    let name = &decl[..open];
+
+    let mut depth = 0;
+    let mut close = open;
+    for (i, c) in decl.char_indices().skip(open) {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => (),
+        }
+        if depth == 0 {
+            close = i;
+            break;
+        }
+    }
+    let args = split_args(&decl[open + 1..close]);
+    let ret = match decl[close + 1..].trim() {
+        "" => String::new(),
+        ret => format!(" {}", ret),
+    };
+
+    // variadic functions are wrapped without their variadic arguments
+    let fixed = args
+        .iter()
+        .filter(|a| **a != "...")
+        .cloned()
+        .collect::<Vec<_>>();
+    let names = fixed
+        .iter()
+        .map(|a| a[..a.find(':').unwrap()].trim())
+        .collect::<Vec<_>>();
+
+    format!(
+        "{attrs}pub unsafe fn {name}({fixed}){ret} {{\n    \
+         static SYM: crate::dynamic::Symbol = crate::dynamic::Symbol::new(\"{name}\\0\");\n    \
+         let f: unsafe extern \"C\" fn({args}){ret} = ::std::mem::transmute(SYM.get());\n    \
+         f({names})\n}}\n",
+        attrs = attrs,
+        name = name,
+        fixed = fixed.join(", "),
+        args = args.join(", "),
+        ret = ret,
+        names = names.join(", "),
+    )
 }
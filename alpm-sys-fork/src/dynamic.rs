@@ -0,0 +1,193 @@
+use std::error;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::mem::transmute;
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+
+const RTLD_NOW: c_int = 2;
+
+extern "C" {
+    fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlclose(handle: *mut c_void) -> c_int;
+    fn dlerror() -> *mut c_char;
+}
+
+/// The major versions of libalpm these bindings can load. The functions
+/// whose signature changed in libalpm 13 are in the `abi13` module, use
+/// `abi()` to pick between them. Bindings generated with the `generate`
+/// feature only match the libalpm they were generated from.
+#[cfg(alpm_multi_abi)]
+pub const SUPPORTED_ABI: &[u32] = &[12, 13];
+#[cfg(all(not(alpm_multi_abi), not(alpm_git)))]
+pub const SUPPORTED_ABI: &[u32] = &[12];
+#[cfg(all(not(alpm_multi_abi), alpm_git))]
+pub const SUPPORTED_ABI: &[u32] = &[13];
+
+static LIBRARY: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+static ABI: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LoadError {
+    /// The library could not be opened, with the reason dlopen gave.
+    NotFound(String),
+    /// The library's `alpm_version()` is not in `SUPPORTED_ABI`.
+    UnsupportedVersion(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::NotFound(err) => write!(fmt, "failed to load libalpm: {}", err),
+            LoadError::UnsupportedVersion(ver) => {
+                write!(fmt, "unsupported libalpm version: {}", ver)
+            }
+        }
+    }
+}
+
+impl error::Error for LoadError {}
+
+fn dl_error() -> String {
+    let err = unsafe { dlerror() };
+    if err.is_null() {
+        "unknown error".to_string()
+    } else {
+        unsafe { CStr::from_ptr(err) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+fn major(version: &str) -> Option<u32> {
+    version.split('.').next().and_then(|v| v.parse().ok())
+}
+
+fn open(name: &str) -> Result<*mut c_void, LoadError> {
+    let cname = CString::new(name).map_err(|_| LoadError::NotFound(name.to_string()))?;
+    let lib = unsafe { dlopen(cname.as_ptr(), RTLD_NOW) };
+    if lib.is_null() {
+        return Err(LoadError::NotFound(dl_error()));
+    }
+
+    let sym = unsafe { dlsym(lib, b"alpm_version\0".as_ptr() as *const c_char) };
+    if sym.is_null() {
+        let err = dl_error();
+        unsafe { dlclose(lib) };
+        return Err(LoadError::NotFound(err));
+    }
+
+    let alpm_version: unsafe extern "C" fn() -> *const c_char = unsafe { transmute(sym) };
+    let version = unsafe { CStr::from_ptr(alpm_version()) }
+        .to_string_lossy()
+        .into_owned();
+    if !matches!(major(&version), Some(major) if SUPPORTED_ABI.contains(&major)) {
+        unsafe { dlclose(lib) };
+        return Err(LoadError::UnsupportedVersion(version));
+    }
+
+    Ok(lib)
+}
+
+fn set_library(lib: *mut c_void) {
+    let ret = LIBRARY.compare_exchange(ptr::null_mut(), lib, Ordering::AcqRel, Ordering::Acquire);
+    // another thread loaded it first, only drop our reference
+    if ret.is_err() {
+        unsafe { dlclose(lib) };
+    }
+}
+
+pub fn is_loaded() -> bool {
+    !LIBRARY.load(Ordering::Acquire).is_null()
+}
+
+/// Load libalpm by its soname if it is not loaded yet. Fails with
+/// `UnsupportedVersion` if it is not the ABI in `SUPPORTED_ABI`. Calling any
+/// libalpm function loads it as well but panics if that fails.
+pub fn load() -> Result<(), LoadError> {
+    if is_loaded() {
+        return Ok(());
+    }
+
+    let mut names = SUPPORTED_ABI
+        .iter()
+        .map(|abi| format!("libalpm.so.{}", abi))
+        .collect::<Vec<_>>();
+    names.push("libalpm.so".to_string());
+
+    let mut not_found = None;
+    let mut unsupported = None;
+    for name in &names {
+        match open(name) {
+            Ok(lib) => {
+                set_library(lib);
+                return Ok(());
+            }
+            Err(e @ LoadError::UnsupportedVersion(_)) => unsupported = unsupported.or(Some(e)),
+            Err(e) => not_found = not_found.or(Some(e)),
+        }
+    }
+
+    // a libalpm that was found but is too new or old says more than the
+    // sonames that were not found
+    Err(unsupported.or(not_found).unwrap())
+}
+
+/// Load libalpm from `path` if it is not loaded yet.
+pub fn load_from(path: &str) -> Result<(), LoadError> {
+    if is_loaded() {
+        return Ok(());
+    }
+
+    set_library(open(path)?);
+    Ok(())
+}
+
+/// The major version of the loaded libalpm, one of `SUPPORTED_ABI`. Like
+/// the libalpm functions this loads it if needed and panics if that fails.
+pub fn abi() -> u32 {
+    let abi = ABI.load(Ordering::Relaxed);
+    if abi != 0 {
+        return abi;
+    }
+
+    let version = unsafe { CStr::from_ptr(crate::alpm_version()) }.to_string_lossy();
+    let abi = major(&version).unwrap_or(0);
+    ABI.store(abi, Ordering::Relaxed);
+    abi
+}
+
+pub(crate) struct Symbol {
+    name: &'static str,
+    ptr: AtomicPtr<c_void>,
+}
+
+impl Symbol {
+    pub(crate) const fn new(name: &'static str) -> Symbol {
+        Symbol {
+            name,
+            ptr: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    pub(crate) fn get(&self) -> *mut c_void {
+        let ptr = self.ptr.load(Ordering::Relaxed);
+        if !ptr.is_null() {
+            return ptr;
+        }
+
+        if let Err(e) = load() {
+            panic!("{}", e);
+        }
+        let lib = LIBRARY.load(Ordering::Acquire);
+        let ptr = unsafe { dlsym(lib, self.name.as_ptr() as *const c_char) };
+        if ptr.is_null() {
+            panic!("libalpm has no symbol {}", self.name.trim_end_matches('\0'));
+        }
+
+        self.ptr.store(ptr, Ordering::Relaxed);
+        ptr
+    }
+}
@@ -2,14 +2,27 @@
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 
-#[cfg(not(feature = "git"))]
+#[cfg(feature = "dynamic")]
+mod dynamic;
+
+#[cfg(all(not(alpm_git), not(alpm_generated)))]
 mod ffi;
 
-#[cfg(feature = "git")]
+#[cfg(all(alpm_git, not(alpm_generated)))]
 mod ffi_git;
 
-#[cfg(not(feature = "git"))]
+// bindings written by the build script, see build.rs
+#[cfg(alpm_generated)]
+#[allow(clippy::missing_safety_doc)]
+mod ffi {
+    include!(concat!(env!("OUT_DIR"), "/ffi.rs"));
+}
+
+#[cfg(feature = "dynamic")]
+pub use crate::dynamic::{abi, is_loaded, load, load_from, LoadError, SUPPORTED_ABI};
+
+#[cfg(any(not(alpm_git), alpm_generated))]
 pub use crate::ffi::*;
 
-#[cfg(feature = "git")]
+#[cfg(all(alpm_git, not(alpm_generated)))]
 pub use crate::ffi_git::*;
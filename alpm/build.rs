@@ -0,0 +1,13 @@
+use std::env;
+
+fn main() {
+    // follow the bindings alpm-sys selected for the installed libalpm
+    println!("cargo:rustc-check-cfg=cfg(alpm_git)");
+    println!("cargo:rustc-check-cfg=cfg(alpm_multi_abi)");
+    match env::var("DEP_ALPM_ABI").ok().as_deref() {
+        Some("13") => println!("cargo:rustc-cfg=alpm_git"),
+        // libalpm is loaded at runtime and may be either
+        Some(abi) if abi.contains(',') => println!("cargo:rustc-cfg=alpm_multi_abi"),
+        _ => (),
+    }
+}
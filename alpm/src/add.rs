@@ -18,8 +18,8 @@ unsafe impl<'a> PkgAdd for LoadedPackage<'a> {
 }
 
 impl Alpm {
-    pub fn trans_add_pkg(&self, pkg: Package) -> Result<()> {
-        let ret = unsafe { alpm_add_pkg(self.handle, pkg.pkg) };
+    pub fn trans_add_pkg<P: PkgAdd>(&self, pkg: P) -> Result<()> {
+        let ret = unsafe { alpm_add_pkg(self.handle, pkg.as_alpm_pkg_t()) };
         let ok = self.check_ret(ret);
         if ok.is_ok() {
             std::mem::forget(pkg);
@@ -29,6 +29,9 @@ pub struct Alpm {
 impl Drop for Alpm {
     fn drop(&mut self) {
         if self.drop {
+            // before the address can be reused by a new handle
+            #[cfg(feature = "downloader")]
+            crate::downloader::release_builtin_downloader(self.handle);
             // alpm should do this for us, but is bugged
             unsafe { alpm_trans_release(self.handle) };
             unsafe { alpm_release(self.handle) };
@@ -38,6 +41,9 @@ impl Drop for Alpm {
 
 impl Alpm {
     pub fn new(root: impl AsRef<str>, db_path: impl AsRef<str>) -> Result<Alpm> {
+        #[cfg(feature = "dynamic")]
+        load_library()?;
+
         let mut err = alpm_errno_t::ALPM_ERR_OK;
         let root = CString::new(root.as_ref()).unwrap();
         let db_path = CString::new(db_path.as_ref()).unwrap();
@@ -68,6 +74,14 @@ impl Alpm {
     }
 }
 
+/// Load libalpm if it is not loaded yet, `Alpm::new` does this as well.
+/// Functions that do not take a handle, such as `version`, panic if libalpm
+/// can not be loaded. See `alpm_sys::load` for why loading failed.
+#[cfg(feature = "dynamic")]
+pub fn load_library() -> Result<()> {
+    alpm_sys::load().map_err(Error::from)
+}
+
 pub fn version() -> &'static str {
     unsafe { from_cstr(alpm_version()) }
 }
@@ -107,16 +121,21 @@ impl Capabilities {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::SigLevel;
+    #[cfg(feature = "fixtures")]
     use crate::{
-        log_action, set_eventcb, set_fetchcb, set_logcb, set_progresscb, set_questioncb, SigLevel,
+        log_action, set_eventcb, set_fetchcb, set_logcb, set_progresscb, set_questioncb, Fixture,
+        FixturePkg,
     };
 
+    #[cfg(feature = "fixtures")]
     fn logcb(level: LogLevel, msg: &str) {
         if level == LogLevel::ERROR {
             print!("log {}", msg);
         }
     }
 
+    #[cfg(feature = "fixtures")]
     fn eventcb(event: &Event) {
         match event {
             Event::DatabaseMissing(x) => println!("missing database: {}", x.dbname()),
@@ -124,10 +143,12 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "fixtures")]
     fn fetchcb(_url: &str, _path: &str, _force: bool) -> FetchCbReturn {
         FetchCbReturn::Ok
     }
 
+    #[cfg(feature = "fixtures")]
     fn questioncb(question: &Question) {
         println!("question {:?}", question);
         match question {
@@ -140,6 +161,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "fixtures")]
     fn progresscb(progress: Progress, pkgname: &str, percent: i32, howmany: usize, current: usize) {
         println!(
             "progress {:?}, {} {} {} {}",
@@ -163,8 +185,13 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "fixtures")]
     fn test_cb() {
-        let mut handle = Alpm::new("/", "tests/db").unwrap();
+        let fixture = Fixture::new()
+            .sync("core", FixturePkg::new("filesystem", "2019.05-2"))
+            .build()
+            .unwrap();
+        let handle = fixture.handle().unwrap();
         set_logcb!(handle, logcb);
         set_eventcb!(handle, eventcb);
         set_fetchcb!(handle, fetchcb);
@@ -172,13 +199,10 @@ mod tests {
         set_progresscb!(handle, progresscb);
 
         handle.set_use_syslog(true);
-        handle.set_logfile("tests/log").unwrap();
 
         log_action!(handle, "me", "look i am logging an action {}", ":D").unwrap();
 
-        let db = handle.register_syncdb_mut("core", SigLevel::NONE).unwrap();
-        db.add_server("https://ftp.rnl.tecnico.ulisboa.pt/pub/archlinux/core/os/x86_64")
-            .unwrap();
+        let db = handle.syncdbs().find(|db| db.name() == "core").unwrap();
         db.pkg("filesystem").unwrap();
     }
 
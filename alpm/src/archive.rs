@@ -0,0 +1,363 @@
+use crate::{
+    parse_mtree, Alpm, BuildInfo, MTreeEntry, MTreeType, Match, PackageFrom, Pkg, PkgInfo,
+};
+
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use tar::{Archive, EntryType};
+use xz2::read::XzDecoder;
+
+pub(crate) fn decompress(path: &Path) -> io::Result<Box<dyn Read>> {
+    let mut file = File::open(path)?;
+    let mut magic = Vec::new();
+    (&mut file).take(6).read_to_end(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    let reader: Box<dyn Read> = if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Box::new(zstd::Decoder::new(file)?)
+    } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Box::new(XzDecoder::new(file))
+    } else if magic.starts_with(&[0x1f, 0x8b]) {
+        Box::new(GzDecoder::new(file))
+    } else if magic.starts_with(b"BZh") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "bzip2 compressed packages are not supported",
+        ));
+    } else {
+        Box::new(file)
+    };
+
+    Ok(reader)
+}
+
+pub(crate) fn entry_path(path: &Path) -> &Path {
+    path.strip_prefix("./").unwrap_or(path)
+}
+
+fn is_meta(path: &Path) -> bool {
+    path.components().count() == 1 && path.to_string_lossy().starts_with('.')
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PkgEntry {
+    pub path: String,
+    pub file_type: MTreeType,
+    pub mode: u32,
+    pub size: u64,
+    pub mtime: u64,
+    pub link: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PkgArchive {
+    path: PathBuf,
+}
+
+impl PkgArchive {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<PkgArchive> {
+        let path = path.as_ref().to_path_buf();
+        decompress(&path)?;
+        Ok(PkgArchive { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn archive(&self) -> io::Result<Archive<Box<dyn Read>>> {
+        Ok(Archive::new(decompress(&self.path)?))
+    }
+
+    fn read_meta(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let mut archive = self.archive()?;
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+            let path = entry_path(&path);
+
+            if path == Path::new(name) {
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data)?;
+                return Ok(Some(data));
+            }
+
+            // metadata files are always at the start of the archive
+            if !path.to_string_lossy().starts_with('.') {
+                break;
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn read_meta_string(&self, name: &str) -> io::Result<String> {
+        let data = self.read_meta(name)?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} not found", name))
+        })?;
+        String::from_utf8(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn pkginfo(&self) -> io::Result<PkgInfo> {
+        let data = self.read_meta_string(".PKGINFO")?;
+        Ok(PkgInfo::parse(data)?)
+    }
+
+    pub fn buildinfo(&self) -> io::Result<BuildInfo> {
+        let data = self.read_meta_string(".BUILDINFO")?;
+        Ok(BuildInfo::parse(data)?)
+    }
+
+    pub fn entries(&self) -> io::Result<Vec<PkgEntry>> {
+        let mut archive = self.archive()?;
+        let mut entries = Vec::new();
+
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let header = entry.header();
+            let path = entry.path()?;
+
+            let file_type = match header.entry_type() {
+                EntryType::Regular | EntryType::Continuous | EntryType::Link => MTreeType::File,
+                EntryType::Directory => MTreeType::Dir,
+                EntryType::Symlink => MTreeType::Link,
+                EntryType::Block => MTreeType::Block,
+                EntryType::Char => MTreeType::Char,
+                EntryType::Fifo => MTreeType::Fifo,
+                _ => continue,
+            };
+
+            entries.push(PkgEntry {
+                path: entry_path(&path).to_string_lossy().into_owned(),
+                file_type,
+                mode: header.mode()? & 0o7777,
+                size: header.size()?,
+                mtime: header.mtime()?,
+                link: entry.link_name()?.map(|l| l.to_string_lossy().into_owned()),
+            });
+        }
+
+        Ok(entries)
+    }
+
+    pub fn read_file(&self, path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        let path = entry_path(path.as_ref());
+        let mut archive = self.archive()?;
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+
+            if entry_path(&entry.path()?) == path {
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data)?;
+                return Ok(data);
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} not found", path.display()),
+        ))
+    }
+
+    pub fn install(&self) -> io::Result<Option<String>> {
+        match self.read_meta(".INSTALL")? {
+            Some(data) => String::from_utf8(data)
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn changelog(&self) -> io::Result<Option<String>> {
+        match self.read_meta(".CHANGELOG")? {
+            Some(data) => Ok(Some(String::from_utf8_lossy(&data).into_owned())),
+            None => Ok(None),
+        }
+    }
+
+    /// Extract the package's files into `dir`, skipping metadata files and
+    /// any path for which `filter` returns false. Returns the skipped paths.
+    pub fn extract<F: FnMut(&str) -> bool>(
+        &self,
+        dir: impl AsRef<Path>,
+        mut filter: F,
+    ) -> io::Result<Vec<String>> {
+        let dir = dir.as_ref();
+        let mut archive = self.archive()?;
+        let mut skipped = Vec::new();
+
+        archive.set_preserve_permissions(true);
+        archive.set_preserve_mtime(true);
+        archive.set_overwrite(true);
+        fs::create_dir_all(dir)?;
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry_path(&entry.path()?).to_path_buf();
+
+            if is_meta(&path) {
+                continue;
+            }
+
+            let name = path.to_string_lossy();
+            if !filter(&name) {
+                skipped.push(name.into_owned());
+                continue;
+            }
+
+            entry.unpack_in(dir)?;
+        }
+
+        Ok(skipped)
+    }
+
+    pub fn mtree(&self) -> io::Result<Vec<MTreeEntry>> {
+        let data = self
+            .read_meta(".MTREE")?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, ".MTREE not found"))?;
+        let mut s = String::new();
+        GzDecoder::new(data.as_slice()).read_to_string(&mut s)?;
+        Ok(parse_mtree(s)?)
+    }
+}
+
+impl<'a> Pkg<'a> {
+    pub fn archive(&self) -> io::Result<PkgArchive> {
+        if self.origin() != PackageFrom::File {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "package was not loaded from a file",
+            ));
+        }
+
+        PkgArchive::open(self.filename())
+    }
+}
+
+impl Alpm {
+    /// Extract `pkg` into `dir`, skipping files matched by the NoExtract list.
+    pub fn extract_pkg(
+        &mut self,
+        pkg: &PkgArchive,
+        dir: impl AsRef<Path>,
+    ) -> io::Result<Vec<String>> {
+        pkg.extract(dir, |path| self.match_noextract(path) != Match::Yes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PkgBuilder;
+
+    #[test]
+    fn test_pkginfo() {
+        let pkg = PkgArchive::open("tests/pacman-5.1.3-1-x86_64.pkg.tar.xz").unwrap();
+        let info = pkg.pkginfo().unwrap();
+        assert_eq!(info.name, "pacman");
+        assert_eq!(info.version, "5.1.3-1");
+        assert_eq!(info.backup, vec!["etc/pacman.conf", "etc/makepkg.conf"]);
+        assert_eq!(info.checkdepends, vec!["python2", "fakechroot"]);
+    }
+
+    #[test]
+    fn test_buildinfo() {
+        let pkg = PkgArchive::open("tests/pacman-5.1.3-1-x86_64.pkg.tar.xz").unwrap();
+        let info = pkg.buildinfo().unwrap();
+        assert_eq!(info.format, 1);
+        assert_eq!(info.build_dir.as_ref().unwrap(), "/build");
+        assert!(info.installed_pkgs().any(|p| p.name == "acl"));
+    }
+
+    fn build() -> (tempfile::TempDir, PkgArchive) {
+        let dir = tempfile::tempdir().unwrap();
+        let stage = dir.path().join("pkg");
+        let path = dir.path().join("foo-1.0-1-any.pkg.tar.zst");
+
+        fs::create_dir_all(stage.join("etc")).unwrap();
+        fs::create_dir_all(stage.join("usr/share/doc/foo")).unwrap();
+        fs::write(stage.join("etc/foo.conf"), "[options]\n").unwrap();
+        fs::write(stage.join("usr/share/doc/foo/README"), "foo\n").unwrap();
+        fs::write(stage.join(".CHANGELOG"), "1.0-1: initial\n").unwrap();
+
+        let info = PkgInfo {
+            name: "foo".into(),
+            version: "1.0-1".into(),
+            ..PkgInfo::default()
+        };
+        PkgBuilder::new(&stage, info)
+            .source_date_epoch(1551404516)
+            .build(&path)
+            .unwrap();
+
+        let pkg = PkgArchive::open(path).unwrap();
+        (dir, pkg)
+    }
+
+    #[test]
+    fn test_entries() {
+        let (_dir, pkg) = build();
+        let entries = pkg.entries().unwrap();
+        assert_eq!(entries[0].path, ".PKGINFO");
+
+        let conf = entries.iter().find(|e| e.path == "etc/foo.conf").unwrap();
+        assert_eq!(conf.file_type, MTreeType::File);
+        assert_eq!(conf.size, 10);
+        assert_eq!(conf.mtime, 1551404516);
+        assert!(entries
+            .iter()
+            .any(|e| e.file_type == MTreeType::Dir && e.path == "etc"));
+    }
+
+    #[test]
+    fn test_read_file() {
+        let (_dir, pkg) = build();
+        assert_eq!(pkg.read_file("etc/foo.conf").unwrap(), b"[options]\n");
+        assert_eq!(pkg.changelog().unwrap().unwrap(), "1.0-1: initial\n");
+        assert!(pkg.install().unwrap().is_none());
+
+        let err = pkg.read_file("etc/missing.conf").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_extract() {
+        let (_dir, pkg) = build();
+        let dir = tempfile::tempdir().unwrap();
+        let skipped = pkg
+            .extract(dir.path(), |p| !p.starts_with("usr/share"))
+            .unwrap();
+
+        assert!(dir.path().join("etc/foo.conf").exists());
+        assert!(!dir.path().join(".PKGINFO").exists());
+        assert!(!dir.path().join(".CHANGELOG").exists());
+        assert!(!dir.path().join("usr/share").exists());
+        assert!(skipped.iter().any(|p| p == "usr/share/doc/foo/README"));
+    }
+
+    #[test]
+    fn test_extract_pkg_noextract() {
+        let mut handle = Alpm::new("/", "tests/db").unwrap();
+        handle.add_noextract("etc/*").unwrap();
+
+        let (_dir, pkg) = build();
+        let dir = tempfile::tempdir().unwrap();
+        let skipped = handle.extract_pkg(&pkg, dir.path()).unwrap();
+
+        assert!(!dir.path().join("etc/foo.conf").exists());
+        assert!(dir.path().join("usr/share/doc/foo/README").exists());
+        assert_eq!(skipped, vec!["etc/foo.conf"]);
+    }
+
+    #[test]
+    fn test_missing() {
+        let pkg = PkgArchive::open("tests/pacman-5.1.3-1-incomplete.pkg.tar.xz").unwrap();
+        let err = pkg.mtree().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -1,6 +1,110 @@
-use crate::{DbMut, Result};
+use crate::{DbMut, Error, Result};
 use alpm_sys::*;
 
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DbChange {
+    Added {
+        name: String,
+        version: String,
+    },
+    Removed {
+        name: String,
+        version: String,
+    },
+    Changed {
+        name: String,
+        old_version: String,
+        new_version: String,
+    },
+}
+
+impl DbChange {
+    pub fn name(&self) -> &str {
+        match self {
+            DbChange::Added { name, .. } => name,
+            DbChange::Removed { name, .. } => name,
+            DbChange::Changed { name, .. } => name,
+        }
+    }
+}
+
+impl fmt::Display for DbChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DbChange::Added { name, version } => write!(f, "{} {} (new)", name, version),
+            DbChange::Removed { name, version } => write!(f, "{} {} (removed)", name, version),
+            DbChange::Changed {
+                name,
+                old_version,
+                new_version,
+            } => write!(f, "{} {} -> {}", name, old_version, new_version),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct DbDiff {
+    pub db: String,
+    /// Sorted by package name.
+    pub changes: Vec<DbChange>,
+}
+
+impl DbDiff {
+    /// Compare two sets of `(name, version)` pairs.
+    pub fn new<S, I, J>(db: impl Into<String>, old: I, new: J) -> DbDiff
+    where
+        S: Into<String>,
+        I: IntoIterator<Item = (S, S)>,
+        J: IntoIterator<Item = (S, S)>,
+    {
+        let collect = |pkgs: I| -> BTreeMap<String, String> {
+            pkgs.into_iter()
+                .map(|(n, v)| (n.into(), v.into()))
+                .collect()
+        };
+        let mut old = collect(old);
+        let mut changes = Vec::new();
+
+        for (name, version) in new.into_iter().map(|(n, v)| (n.into(), v.into())) {
+            match old.remove(&name) {
+                None => changes.push(DbChange::Added { name, version }),
+                Some(old_version) if old_version != version => changes.push(DbChange::Changed {
+                    name,
+                    old_version,
+                    new_version: version,
+                }),
+                Some(_) => (),
+            }
+        }
+        changes.extend(
+            old.into_iter()
+                .map(|(name, version)| DbChange::Removed { name, version }),
+        );
+        changes.sort_by(|a, b| a.name().cmp(b.name()));
+
+        DbDiff {
+            db: db.into(),
+            changes,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl fmt::Display for DbDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{} in {}", change, self.db)?;
+        }
+        Ok(())
+    }
+}
+
 impl<'a> DbMut<'a> {
     pub fn update(&mut self, force: bool) -> Result<bool> {
         let force = if force { 1 } else { 0 };
@@ -11,4 +115,89 @@ impl<'a> DbMut<'a> {
             Ok(ret == 1)
         }
     }
+
+    /// Like `update` but returns the packages that changed compared to the
+    /// previous copy of the database. The diff is empty if it was already up
+    /// to date and `None` if there was no previous copy to compare to.
+    pub fn update_diff(&mut self, force: bool) -> Result<Option<DbDiff>> {
+        let versions = |db: &DbMut| match db.pkgs() {
+            Ok(pkgs) => Ok(Some(
+                pkgs.map(|p| (p.name().to_string(), p.version().to_string()))
+                    .collect::<Vec<_>>(),
+            )),
+            // alpm_db_get_pkgcache resets the error so this is an empty database
+            Err(Error::Ok) => Ok(Some(Vec::new())),
+            Err(Error::DbNotFound) => Ok(None),
+            Err(e) => Err(e),
+        };
+
+        let old = versions(self)?;
+        let force = if force { 1 } else { 0 };
+        let ret = unsafe { alpm_db_update(force, self.db) };
+        if ret < 0 {
+            return Err(self.handle.last_error());
+        }
+
+        let old = match old {
+            Some(old) => old,
+            None => return Ok(None),
+        };
+        // alpm_db_update returns 1 when the database is already up to date
+        if ret == 1 {
+            return Ok(Some(DbDiff {
+                db: self.name().into(),
+                changes: Vec::new(),
+            }));
+        }
+        let new = versions(self)?.unwrap_or_default();
+
+        Ok(Some(DbDiff::new(self.name(), old, new)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_db_diff() {
+        let old = vec![
+            ("linux", "6.1.1.arch1-1"),
+            ("bash", "5.1.016-1"),
+            ("pacman-contrib", "1.1.0-1"),
+        ];
+        let new = vec![
+            ("bash", "5.1.016-1"),
+            ("linux", "6.2.arch1-1"),
+            ("foo", "1.0-1"),
+        ];
+
+        let diff = DbDiff::new("core", old, new);
+        assert_eq!(
+            diff.changes,
+            vec![
+                DbChange::Added {
+                    name: "foo".into(),
+                    version: "1.0-1".into(),
+                },
+                DbChange::Changed {
+                    name: "linux".into(),
+                    old_version: "6.1.1.arch1-1".into(),
+                    new_version: "6.2.arch1-1".into(),
+                },
+                DbChange::Removed {
+                    name: "pacman-contrib".into(),
+                    version: "1.1.0-1".into(),
+                },
+            ]
+        );
+        assert_eq!(
+            diff.to_string(),
+            "foo 1.0-1 (new) in core\n\
+             linux 6.1.1.arch1-1 -> 6.2.arch1-1 in core\n\
+             pacman-contrib 1.1.0-1 (removed) in core\n"
+        );
+
+        assert!(DbDiff::new("core", vec![("a", "1")], vec![("a", "1")]).is_empty());
+    }
 }
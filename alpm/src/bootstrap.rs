@@ -0,0 +1,275 @@
+use crate::{Alpm, BootstrapError, SigLevel, TransFlag};
+
+use std::fs::{self, DirBuilder};
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BootstrapRepo {
+    pub name: String,
+    pub siglevel: SigLevel,
+    pub servers: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BootstrapConfig {
+    dbpath: String,
+    cachedirs: Vec<String>,
+    gpgdir: Option<String>,
+    arch: Option<String>,
+    repos: Vec<BootstrapRepo>,
+    flags: TransFlag,
+}
+
+impl Default for BootstrapConfig {
+    fn default() -> BootstrapConfig {
+        BootstrapConfig::new()
+    }
+}
+
+impl BootstrapConfig {
+    pub fn new() -> BootstrapConfig {
+        BootstrapConfig {
+            dbpath: "var/lib/pacman".into(),
+            cachedirs: Vec::new(),
+            gpgdir: None,
+            arch: None,
+            repos: Vec::new(),
+            flags: TransFlag::NONE,
+        }
+    }
+
+    /// The database path, relative to the new root.
+    pub fn dbpath(mut self, dbpath: impl Into<String>) -> BootstrapConfig {
+        self.dbpath = dbpath.into();
+        self
+    }
+
+    /// Add a package cache directory on the host. Defaults to
+    /// `var/cache/pacman/pkg` inside the new root if none are given.
+    pub fn cachedir(mut self, cachedir: impl Into<String>) -> BootstrapConfig {
+        self.cachedirs.push(cachedir.into());
+        self
+    }
+
+    pub fn gpgdir(mut self, gpgdir: impl Into<String>) -> BootstrapConfig {
+        self.gpgdir = Some(gpgdir.into());
+        self
+    }
+
+    pub fn arch(mut self, arch: impl Into<String>) -> BootstrapConfig {
+        self.arch = Some(arch.into());
+        self
+    }
+
+    pub fn repo<S: Into<String>, I: IntoIterator<Item = S>>(
+        mut self,
+        name: impl Into<String>,
+        siglevel: SigLevel,
+        servers: I,
+    ) -> BootstrapConfig {
+        self.repos.push(BootstrapRepo {
+            name: name.into(),
+            siglevel,
+            servers: servers.into_iter().map(Into::into).collect(),
+        });
+        self
+    }
+
+    pub fn flags(mut self, flags: TransFlag) -> BootstrapConfig {
+        self.flags = flags;
+        self
+    }
+}
+
+fn create_dir(root: &Path, dir: &str, mode: u32) -> io::Result<()> {
+    let path = root.join(dir);
+    DirBuilder::new().recursive(true).mode(mode).create(&path)?;
+    // the umask may have masked off bits, set them explicitly
+    fs::set_permissions(&path, fs::Permissions::from_mode(mode))
+}
+
+// the same layout pacstrap creates
+pub(crate) fn create_layout(root: &Path, dbpath: &str) -> io::Result<()> {
+    create_dir(root, "", 0o755)?;
+    for dir in &[
+        "var/cache/pacman/pkg",
+        "var/lib/pacman",
+        "var/log",
+        "dev",
+        "run",
+        "etc/pacman.d",
+    ] {
+        create_dir(root, dir, 0o755)?;
+    }
+    create_dir(root, "tmp", 0o1777)?;
+    create_dir(root, "sys", 0o555)?;
+    create_dir(root, "proc", 0o555)?;
+
+    let dbpath = root.join(dbpath);
+    fs::create_dir_all(dbpath.join("sync"))?;
+    fs::create_dir_all(dbpath.join("local"))?;
+
+    let version = dbpath.join("local/ALPM_DB_VERSION");
+    if !version.exists() {
+        fs::write(version, "9\n")?;
+    }
+
+    Ok(())
+}
+
+impl Alpm {
+    /// Create a new root at `root`, sync the configured repos and install
+    /// `targets` (packages or groups) into it. Ownership and permissions of
+    /// installed files are only preserved when running as root.
+    pub fn bootstrap<S: AsRef<str>>(
+        root: impl AsRef<str>,
+        config: &BootstrapConfig,
+        targets: &[S],
+    ) -> std::result::Result<Alpm, BootstrapError> {
+        let root = root.as_ref();
+        let root_path = Path::new(root);
+        create_layout(root_path, &config.dbpath)?;
+
+        let dbpath = root_path.join(&config.dbpath);
+        let mut handle = Alpm::new(root, dbpath.to_str().unwrap())?;
+
+        if config.cachedirs.is_empty() {
+            let cachedir = root_path.join("var/cache/pacman/pkg");
+            handle.add_cachedir(cachedir.to_str().unwrap())?;
+        } else {
+            handle.set_cachedirs(config.cachedirs.iter())?;
+        }
+        let logfile = root_path.join("var/log/pacman.log");
+        handle.set_logfile(logfile.to_str().unwrap())?;
+        if let Some(ref gpgdir) = config.gpgdir {
+            handle.set_gpgdir(gpgdir)?;
+        }
+        if let Some(ref arch) = config.arch {
+            handle.set_arch(arch);
+        }
+
+        for repo in &config.repos {
+            let mut db = handle.register_syncdb_mut(&repo.name, repo.siglevel)?;
+            db.set_servers(repo.servers.iter())?;
+            db.update(false)?;
+        }
+
+        handle.trans_init(config.flags)?;
+        let ret = handle.bootstrap_add(targets);
+        let ret = ret.and_then(|_| {
+            handle.trans_prepare().map_err(|(_, e)| e)?;
+            handle.trans_commit().map_err(|(_, e)| e)?;
+            Ok(())
+        });
+        let release = handle.trans_release();
+        ret?;
+        release?;
+
+        Ok(handle)
+    }
+
+    fn bootstrap_add<S: AsRef<str>>(&self, targets: &[S]) -> Result<(), BootstrapError> {
+        for target in targets {
+            let target = target.as_ref();
+
+            let pkg = match target.find('/') {
+                Some(i) => self
+                    .syncdbs()
+                    .find(|db| db.name() == &target[..i])
+                    .and_then(|db| db.pkg(&target[i + 1..]).ok()),
+                None => self.syncdbs().find_satisfier(target),
+            };
+
+            if let Some(pkg) = pkg {
+                if !self.is_trans_target(pkg.name()) {
+                    self.trans_add_pkg(pkg)?;
+                }
+                continue;
+            }
+
+            let group = self.find_group_pkgs(self.syncdbs(), target);
+            if group.is_empty() {
+                return Err(BootstrapError::TargetNotFound(target.to_string()));
+            }
+            for pkg in group {
+                // a member may also be listed on its own
+                if !self.is_trans_target(pkg.name()) {
+                    self.trans_add_pkg(pkg)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_trans_target(&self, name: &str) -> bool {
+        self.trans_add().any(|pkg| pkg.name() == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+
+        create_layout(&root, "var/lib/pacman").unwrap();
+        create_layout(&root, "var/lib/pacman").unwrap();
+
+        let mode = |p: &str| fs::metadata(root.join(p)).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode(""), 0o755);
+        assert_eq!(mode("tmp"), 0o1777);
+        assert_eq!(mode("proc"), 0o555);
+        assert_eq!(mode("var/cache/pacman/pkg"), 0o755);
+        assert!(root.join("var/lib/pacman/sync").is_dir());
+        assert_eq!(
+            fs::read_to_string(root.join("var/lib/pacman/local/ALPM_DB_VERSION")).unwrap(),
+            "9\n"
+        );
+    }
+
+    #[test]
+    fn test_bootstrap_not_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        let config = BootstrapConfig::new();
+
+        match Alpm::bootstrap(root.to_str().unwrap(), &config, &["nonexistent"]) {
+            Err(BootstrapError::TargetNotFound(target)) => assert_eq!(target, "nonexistent"),
+            ret => panic!("unexpected result: {:?}", ret.map(|_| ())),
+        }
+        assert!(root.join("var/lib/pacman/local").is_dir());
+    }
+
+    #[test]
+    #[cfg(feature = "fixtures")]
+    fn test_bootstrap_group() {
+        use crate::{Fixture, FixturePkg};
+
+        let fixture = Fixture::new()
+            .sync("core", FixturePkg::new("foo", "1.0-1").group("base"))
+            .sync("core", FixturePkg::new("bar", "1.0-1").group("base"))
+            .build()
+            .unwrap();
+        let server = format!("file://{}", fixture.repo_dir("core").display());
+        let root = fixture.path().join("new");
+        let config = BootstrapConfig::new()
+            .repo("core", SigLevel::NONE, vec![server])
+            .flags(TransFlag::DB_ONLY);
+
+        let handle = Alpm::bootstrap(root.to_str().unwrap(), &config, &["foo", "base"]).unwrap();
+        let mut pkgs = handle
+            .localdb()
+            .pkgs()
+            .unwrap()
+            .map(|p| p.name())
+            .collect::<Vec<_>>();
+        pkgs.sort_unstable();
+        assert_eq!(pkgs, vec!["bar", "foo"]);
+    }
+}
@@ -0,0 +1,205 @@
+use crate::pkginfo::{parse_kv, parse_num, write_kv, write_kvs};
+use crate::ParseError;
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct BuildInfo {
+    pub format: u32,
+    pub name: String,
+    pub base: Option<String>,
+    pub version: String,
+    pub arch: String,
+    pub pkgbuild_sha256sum: Option<String>,
+    pub packager: Option<String>,
+    pub build_date: i64,
+    pub build_dir: Option<String>,
+    pub start_dir: Option<String>,
+    pub build_tool: Option<String>,
+    pub build_tool_version: Option<String>,
+    pub build_env: Vec<String>,
+    pub options: Vec<String>,
+    pub installed: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InstalledPkg<'a> {
+    pub name: &'a str,
+    pub version: &'a str,
+    pub arch: &'a str,
+}
+
+impl<'a> InstalledPkg<'a> {
+    pub fn parse(s: &'a str) -> Option<InstalledPkg<'a>> {
+        let mut split = s.rsplitn(4, '-');
+        let arch = split.next()?;
+        let pkgrel = split.next()?;
+        let pkgver = split.next()?;
+        let name = split.next()?;
+        let version = &s[name.len() + 1..s.len() - arch.len() - 1];
+
+        if name.is_empty() || pkgver.is_empty() || pkgrel.is_empty() || arch.is_empty() {
+            return None;
+        }
+
+        Some(InstalledPkg {
+            name,
+            version,
+            arch,
+        })
+    }
+}
+
+impl BuildInfo {
+    pub fn parse(s: impl AsRef<str>) -> Result<BuildInfo, ParseError> {
+        let mut info = BuildInfo::default();
+
+        for kv in parse_kv(s.as_ref()) {
+            let (line, key, value) = kv?;
+            let value = value.to_string();
+
+            match key {
+                "format" => info.format = parse_num(line, key, &value)?,
+                "pkgname" => info.name = value,
+                "pkgbase" => info.base = Some(value),
+                "pkgver" => info.version = value,
+                "pkgarch" => info.arch = value,
+                "pkgbuild_sha256sum" => info.pkgbuild_sha256sum = Some(value),
+                "packager" => info.packager = Some(value),
+                "builddate" => info.build_date = parse_num(line, key, &value)?,
+                "builddir" => info.build_dir = Some(value),
+                "startdir" => info.start_dir = Some(value),
+                "buildtool" => info.build_tool = Some(value),
+                "buildtoolver" => info.build_tool_version = Some(value),
+                "buildenv" => info.build_env.push(value),
+                "options" => info.options.push(value),
+                "installed" => {
+                    if InstalledPkg::parse(&value).is_none() {
+                        let msg = format!("invalid installed package: {}", value);
+                        return Err(ParseError::new(line, msg));
+                    }
+                    info.installed.push(value)
+                }
+                _ => (),
+            }
+        }
+
+        if info.format == 0 {
+            return Err(ParseError::missing("format"));
+        }
+        if info.name.is_empty() {
+            return Err(ParseError::missing("pkgname"));
+        }
+        if info.version.is_empty() {
+            return Err(ParseError::missing("pkgver"));
+        }
+
+        Ok(info)
+    }
+
+    pub fn installed_pkgs(&self) -> impl Iterator<Item = InstalledPkg<'_>> {
+        self.installed
+            .iter()
+            .filter_map(|s| InstalledPkg::parse(s.as_str()))
+    }
+}
+
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let opt = |f: &mut fmt::Formatter, key, value: &Option<String>| match value {
+            Some(value) => write_kv(f, key, value),
+            None => Ok(()),
+        };
+
+        write_kv(f, "format", &self.format.to_string())?;
+        write_kv(f, "pkgname", &self.name)?;
+        opt(f, "pkgbase", &self.base)?;
+        write_kv(f, "pkgver", &self.version)?;
+        write_kv(f, "pkgarch", &self.arch)?;
+        opt(f, "pkgbuild_sha256sum", &self.pkgbuild_sha256sum)?;
+        opt(f, "packager", &self.packager)?;
+        write_kv(f, "builddate", &self.build_date.to_string())?;
+        opt(f, "builddir", &self.build_dir)?;
+        opt(f, "startdir", &self.start_dir)?;
+        opt(f, "buildtool", &self.build_tool)?;
+        opt(f, "buildtoolver", &self.build_tool_version)?;
+        write_kvs(f, "buildenv", &self.build_env)?;
+        write_kvs(f, "options", &self.options)?;
+        write_kvs(f, "installed", &self.installed)
+    }
+}
+
+impl FromStr for BuildInfo {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<BuildInfo, ParseError> {
+        BuildInfo::parse(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUILDINFO: &str = "format = 2
+pkgname = pacman
+pkgbase = pacman
+pkgver = 5.1.3-1
+pkgarch = x86_64
+pkgbuild_sha256sum = 596929dd71a6be418f76f125630ac3aae35191a26e784d6fd90787cf66ca9415
+packager = Allan McRae <allan@archlinux.org>
+builddate = 1551404516
+builddir = /build
+startdir = /startdir
+buildtool = devtools
+buildtoolver = 20200213-1-any
+buildenv = !distcc
+buildenv = color
+options = strip
+options = !debug
+installed = acl-2.2.53-1-x86_64
+installed = archlinux-keyring-20190123-2-any
+installed = python-3.8.1-4-x86_64
+";
+
+    #[test]
+    fn test_parse() {
+        let info = BuildInfo::parse(BUILDINFO).unwrap();
+        assert_eq!(info.format, 2);
+        assert_eq!(info.name, "pacman");
+        assert_eq!(info.arch, "x86_64");
+        assert_eq!(info.start_dir.as_ref().unwrap(), "/startdir");
+        assert_eq!(info.build_tool.as_ref().unwrap(), "devtools");
+        assert_eq!(info.build_env, vec!["!distcc", "color"]);
+        assert_eq!(info.options, vec!["strip", "!debug"]);
+
+        let installed = info.installed_pkgs().collect::<Vec<_>>();
+        assert_eq!(installed.len(), 3);
+        assert_eq!(installed[1].name, "archlinux-keyring");
+        assert_eq!(installed[1].version, "20190123-2");
+        assert_eq!(installed[1].arch, "any");
+    }
+
+    #[test]
+    fn test_display() {
+        let info = BuildInfo::parse(BUILDINFO).unwrap();
+        assert_eq!(BuildInfo::parse(info.to_string()).unwrap(), info);
+    }
+
+    #[test]
+    fn test_parse_err() {
+        assert_eq!(
+            BuildInfo::parse("format = 2\npkgname = foo").unwrap_err(),
+            ParseError::MissingField("pkgver".to_string())
+        );
+    }
+
+    #[test]
+    fn test_installed_pkg() {
+        assert!(InstalledPkg::parse("foo-1-x86_64").is_none());
+        let pkg = InstalledPkg::parse("lib32-foo-bar-1:2.0-1-x86_64").unwrap();
+        assert_eq!(pkg.name, "lib32-foo-bar");
+        assert_eq!(pkg.version, "1:2.0-1");
+    }
+}
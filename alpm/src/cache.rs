@@ -0,0 +1,553 @@
+use crate::{compute_md5sum, compute_sha256sum, decode_signature, vercmp, Alpm, Error, SigLevel};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CachedPkg {
+    pub path: PathBuf,
+    pub name: String,
+    pub version: String,
+    pub arch: String,
+    pub sig: Option<PathBuf>,
+}
+
+/// Split a package file name such as `foo-1:2.0-1-x86_64.pkg.tar.zst` into
+/// its name, version and architecture.
+pub fn parse_pkg_filename(filename: &str) -> Option<(&str, &str, &str)> {
+    let end = filename.find(".pkg.tar")?;
+    let rest = &filename[end + ".pkg.tar".len()..];
+    if rest.ends_with(".sig") || rest.ends_with(".part") {
+        return None;
+    }
+
+    let stem = &filename[..end];
+    let mut split = stem.rsplitn(4, '-');
+    let arch = split.next()?;
+    let pkgrel = split.next()?;
+    let pkgver = split.next()?;
+    let name = split.next()?;
+
+    if name.is_empty() || pkgver.is_empty() || pkgrel.is_empty() || arch.is_empty() {
+        return None;
+    }
+
+    Some((
+        name,
+        &stem[name.len() + 1..stem.len() - arch.len() - 1],
+        arch,
+    ))
+}
+
+impl CachedPkg {
+    pub fn from_path(path: impl AsRef<Path>) -> Option<CachedPkg> {
+        let path = path.as_ref();
+        let filename = path.file_name()?.to_str()?;
+        let (name, version, arch) = parse_pkg_filename(filename)?;
+
+        let mut sig = path.as_os_str().to_os_string();
+        sig.push(".sig");
+        let sig = PathBuf::from(sig);
+
+        Some(CachedPkg {
+            path: path.to_path_buf(),
+            name: name.to_string(),
+            version: version.to_string(),
+            arch: arch.to_string(),
+            sig: Some(sig).filter(|s| s.exists()),
+        })
+    }
+
+    pub fn filename(&self) -> &str {
+        self.path.file_name().and_then(|f| f.to_str()).unwrap_or("")
+    }
+}
+
+/// List the package files in `dirs`, skipping signatures and partial
+/// downloads. Missing directories are ignored.
+pub fn scan_cache<P: AsRef<Path>, I: IntoIterator<Item = P>>(
+    dirs: I,
+) -> io::Result<Vec<CachedPkg>> {
+    let mut pkgs = Vec::new();
+
+    for dir in dirs {
+        let entries = match fs::read_dir(dir.as_ref()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            pkgs.extend(CachedPkg::from_path(entry.path()));
+        }
+    }
+
+    pkgs.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(pkgs)
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Ord, PartialOrd, Hash)]
+pub enum CacheRemovalReason {
+    OldVersion,
+    Uninstalled,
+    Corrupt,
+    Unsigned,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheCandidate {
+    pub pkg: CachedPkg,
+    /// Size of the package file and its signature.
+    pub size: u64,
+    pub reason: CacheRemovalReason,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct CacheReport {
+    pub candidates: Vec<CacheCandidate>,
+}
+
+impl CacheReport {
+    pub fn bytes(&self) -> u64 {
+        self.candidates.iter().map(|c| c.size).sum()
+    }
+
+    /// Delete every candidate and its signature. Returns the bytes freed.
+    pub fn remove(&self) -> io::Result<u64> {
+        for candidate in &self.candidates {
+            fs::remove_file(&candidate.pkg.path)?;
+            if let Some(ref sig) = candidate.pkg.sig {
+                fs::remove_file(sig)?;
+            }
+        }
+
+        Ok(self.bytes())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheCleaner {
+    keep: Option<usize>,
+    keep_uninstalled: Option<usize>,
+    remove_corrupt: bool,
+    remove_unsigned: bool,
+}
+
+impl Default for CacheCleaner {
+    fn default() -> CacheCleaner {
+        CacheCleaner::new()
+    }
+}
+
+fn file_size(pkg: &CachedPkg) -> u64 {
+    let size = |p: &Path| fs::metadata(p).map(|m| m.len()).unwrap_or(0);
+    size(&pkg.path) + pkg.sig.as_deref().map(size).unwrap_or(0)
+}
+
+impl CacheCleaner {
+    /// Keeps the last three versions of every package, like paccache.
+    pub fn new() -> CacheCleaner {
+        CacheCleaner {
+            keep: Some(3),
+            keep_uninstalled: None,
+            remove_corrupt: false,
+            remove_unsigned: false,
+        }
+    }
+
+    /// Keep the `n` newest versions of each package, `None` keeps all.
+    pub fn keep(mut self, n: Option<usize>) -> CacheCleaner {
+        self.keep = n;
+        self
+    }
+
+    /// Keep the `n` newest versions of packages that are not installed,
+    /// overriding `keep` for them.
+    pub fn keep_uninstalled(mut self, n: Option<usize>) -> CacheCleaner {
+        self.keep_uninstalled = n;
+        self
+    }
+
+    /// Remove files libalpm can not read the whole archive of.
+    pub fn remove_corrupt(mut self, remove: bool) -> CacheCleaner {
+        self.remove_corrupt = remove;
+        self
+    }
+
+    pub fn remove_unsigned(mut self, remove: bool) -> CacheCleaner {
+        self.remove_unsigned = remove;
+        self
+    }
+
+    /// Select the files to remove from `pkgs`. `is_installed` is called with
+    /// package names and `is_corrupt` only if corrupt files are removed.
+    pub fn select<I, C>(
+        &self,
+        pkgs: &[CachedPkg],
+        is_installed: I,
+        mut is_corrupt: C,
+    ) -> CacheReport
+    where
+        I: Fn(&str) -> bool,
+        C: FnMut(&CachedPkg) -> bool,
+    {
+        let mut candidates = Vec::new();
+        let mut groups: BTreeMap<(&str, &str), Vec<&CachedPkg>> = BTreeMap::new();
+
+        for pkg in pkgs {
+            let reason = if self.remove_corrupt && is_corrupt(pkg) {
+                Some(CacheRemovalReason::Corrupt)
+            } else if self.remove_unsigned && pkg.sig.is_none() {
+                Some(CacheRemovalReason::Unsigned)
+            } else {
+                None
+            };
+
+            match reason {
+                Some(reason) => candidates.push(CacheCandidate {
+                    pkg: pkg.clone(),
+                    size: file_size(pkg),
+                    reason,
+                }),
+                None => groups.entry((&pkg.name, &pkg.arch)).or_default().push(pkg),
+            }
+        }
+
+        for ((name, _), mut versions) in groups {
+            let (keep, reason) = match self.keep_uninstalled {
+                Some(keep) if !is_installed(name) => (keep, CacheRemovalReason::Uninstalled),
+                _ => match self.keep {
+                    Some(keep) => (keep, CacheRemovalReason::OldVersion),
+                    None => continue,
+                },
+            };
+
+            versions.sort_by(|a, b| vercmp(&b.version, &a.version));
+
+            candidates.extend(versions.into_iter().skip(keep).map(|pkg| CacheCandidate {
+                pkg: pkg.clone(),
+                size: file_size(pkg),
+                reason,
+            }));
+        }
+
+        CacheReport { candidates }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CacheFileStatus {
+    Ok,
+    /// No sync database has a package of this name.
+    Orphaned,
+    /// The sync database has a different version of the package.
+    Stale {
+        db_version: String,
+    },
+    ChecksumMismatch,
+    BadSignature,
+    /// The signature could not be checked, such as when libalpm was built
+    /// without GPGME or the keyring is missing.
+    Unverifiable {
+        error: Error,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheVerification {
+    pub pkg: CachedPkg,
+    /// The sync database the package was checked against.
+    pub db: Option<String>,
+    pub status: CacheFileStatus,
+}
+
+impl Alpm {
+    pub fn cached_pkgs(&self) -> io::Result<Vec<CachedPkg>> {
+        scan_cache(self.cachedirs())
+    }
+
+    /// Report which files in `cachedirs()` `cleaner` would remove. Call
+    /// `CacheReport::remove` to actually delete them.
+    pub fn cache_report(&self, cleaner: &CacheCleaner) -> io::Result<CacheReport> {
+        let pkgs = self.cached_pkgs()?;
+        let localdb = self.localdb();
+
+        let report = cleaner.select(
+            &pkgs,
+            |name| localdb.pkg(name).is_ok(),
+            // only reading every entry finds truncated or damaged archives
+            |pkg| match pkg.path.to_str() {
+                Some(path) => self.pkg_load(path, true, SigLevel::NONE).is_err(),
+                None => true,
+            },
+        );
+
+        Ok(report)
+    }
+
+    /// Check every cached package file against the sync databases, by name
+    /// and architecture. The signature is verified against the detached
+    /// signature next to the file, or else the one in the sync database.
+    pub fn verify_cache(&self) -> io::Result<Vec<CacheVerification>> {
+        let mut results = Vec::new();
+
+        for pkg in self.cached_pkgs()? {
+            let syncpkg = self.syncdbs().find_map(|db| {
+                db.pkg(&pkg.name)
+                    .ok()
+                    .filter(|p| p.arch() == Some(&pkg.arch))
+            });
+
+            let (db, status) = match syncpkg {
+                None => (None, CacheFileStatus::Orphaned),
+                Some(syncpkg) => {
+                    let db = syncpkg.db().map(|db| db.name().to_string());
+                    let status = if *syncpkg.version() != *pkg.version {
+                        CacheFileStatus::Stale {
+                            db_version: syncpkg.version().to_string(),
+                        }
+                    } else {
+                        self.verify_cached_pkg(
+                            &pkg,
+                            syncpkg.sha256sum(),
+                            syncpkg.md5sum(),
+                            syncpkg.base64_sig(),
+                        )?
+                    };
+                    (db, status)
+                }
+            };
+
+            results.push(CacheVerification { pkg, db, status });
+        }
+
+        Ok(results)
+    }
+
+    fn verify_cached_pkg(
+        &self,
+        pkg: &CachedPkg,
+        sha256sum: Option<&str>,
+        md5sum: Option<&str>,
+        base64_sig: Option<&str>,
+    ) -> io::Result<CacheFileStatus> {
+        let path = pkg.path.to_str().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "path is not valid utf-8")
+        })?;
+        // surface the real error if the file can't be read
+        fs::File::open(&pkg.path)?;
+
+        let matches = match (sha256sum, md5sum) {
+            (Some(sum), _) => compute_sha256sum(path).ok().as_deref() == Some(sum),
+            (None, Some(sum)) => compute_md5sum(path).ok().as_deref() == Some(sum),
+            (None, None) => true,
+        };
+        if !matches {
+            return Ok(CacheFileStatus::ChecksumMismatch);
+        }
+
+        let status = match (&pkg.sig, base64_sig) {
+            (Some(_), _) => self.check_pkg_signature(path),
+            (None, Some(sig)) => self.check_base64_sig(&pkg.path, sig)?,
+            (None, None) => CacheFileStatus::Ok,
+        };
+
+        Ok(status)
+    }
+
+    fn check_pkg_signature(&self, path: &str) -> CacheFileStatus {
+        let valid = self
+            .pkg_load(path, false, SigLevel::NONE)
+            .and_then(|loaded| loaded.pkg().check_signature().map(|(valid, _)| valid));
+
+        match valid {
+            Ok(true) => CacheFileStatus::Ok,
+            Ok(false) => CacheFileStatus::BadSignature,
+            Err(error) => CacheFileStatus::Unverifiable { error },
+        }
+    }
+
+    // libalpm only checks a package file against the detached signature next
+    // to it, so link the file into a temporary directory next to `sig`
+    fn check_base64_sig(&self, path: &Path, sig: &str) -> io::Result<CacheFileStatus> {
+        let sig = match decode_signature(sig) {
+            Ok(sig) => sig,
+            Err(()) => return Ok(CacheFileStatus::BadSignature),
+        };
+        let dir = tempfile::Builder::new().prefix("alpm_").tempdir()?;
+
+        let link = dir.path().join(path.file_name().unwrap_or_default());
+        symlink(fs::canonicalize(path)?, &link)?;
+        let mut sigpath = link.clone().into_os_string();
+        sigpath.push(".sig");
+        fs::write(sigpath, sig)?;
+
+        let link = link.to_str().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "path is not valid utf-8")
+        })?;
+        let status = self.check_pkg_signature(link);
+        dir.close()?;
+
+        Ok(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pkg_filename() {
+        assert_eq!(
+            parse_pkg_filename("pacman-5.1.3-1-x86_64.pkg.tar.xz"),
+            Some(("pacman", "5.1.3-1", "x86_64"))
+        );
+        assert_eq!(
+            parse_pkg_filename("lib32-foo-bar-1:2.0-1-any.pkg.tar.zst"),
+            Some(("lib32-foo-bar", "1:2.0-1", "any"))
+        );
+        assert_eq!(
+            parse_pkg_filename("pacman-5.1.3-1-x86_64.pkg.tar.xz.sig"),
+            None
+        );
+        assert_eq!(
+            parse_pkg_filename("pacman-5.1.3-1-x86_64.pkg.tar.xz.part"),
+            None
+        );
+        assert_eq!(parse_pkg_filename("foo-1-x86_64.pkg.tar.xz"), None);
+        assert_eq!(parse_pkg_filename("core.db"), None);
+    }
+
+    #[test]
+    fn test_scan_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |f: &str| dir.path().join(f);
+
+        fs::write(path("foo-1.0-1-any.pkg.tar.zst"), "").unwrap();
+        fs::write(path("foo-1.0-1-any.pkg.tar.zst.sig"), "").unwrap();
+        fs::write(path("foo-2.0-1-any.pkg.tar.zst"), "").unwrap();
+        fs::write(path("bar-2.0-1-any.pkg.tar.zst.part"), "").unwrap();
+        fs::create_dir(path("download-abc")).unwrap();
+
+        let pkgs = scan_cache([dir.path(), &path("missing")]).unwrap();
+        assert_eq!(pkgs.len(), 2);
+        assert_eq!(pkgs[0].name, "foo");
+        assert_eq!(pkgs[0].version, "1.0-1");
+        assert_eq!(pkgs[0].sig, Some(path("foo-1.0-1-any.pkg.tar.zst.sig")));
+        assert_eq!(pkgs[1].filename(), "foo-2.0-1-any.pkg.tar.zst");
+        assert_eq!(pkgs[1].sig, None);
+    }
+
+    #[test]
+    fn test_verify_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |f: &str| dir.path().join(f);
+
+        fs::write(path("linux-5.1.8.arch1-1-x86_64.pkg.tar.xz"), "bad").unwrap();
+        fs::write(path("linux-5.0.0.arch1-1-x86_64.pkg.tar.xz"), "old").unwrap();
+        fs::write(path("linux-5.1.8.arch1-1-i686.pkg.tar.xz"), "").unwrap();
+        fs::write(path("notapkg-1.0-1-any.pkg.tar.xz"), "").unwrap();
+
+        let mut handle = Alpm::new("/", "tests/db").unwrap();
+        handle.register_syncdb("core", SigLevel::NONE).unwrap();
+        handle.set_cachedirs(dir.path().to_str()).unwrap();
+
+        let results = handle.verify_cache().unwrap();
+        let status = results
+            .iter()
+            .map(|r| (r.pkg.filename(), &r.status))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            status,
+            vec![
+                (
+                    "linux-5.0.0.arch1-1-x86_64.pkg.tar.xz",
+                    &CacheFileStatus::Stale {
+                        db_version: "5.1.8.arch1-1".into()
+                    }
+                ),
+                // the sync database only has linux for x86_64
+                (
+                    "linux-5.1.8.arch1-1-i686.pkg.tar.xz",
+                    &CacheFileStatus::Orphaned
+                ),
+                (
+                    "linux-5.1.8.arch1-1-x86_64.pkg.tar.xz",
+                    &CacheFileStatus::ChecksumMismatch
+                ),
+                ("notapkg-1.0-1-any.pkg.tar.xz", &CacheFileStatus::Orphaned),
+            ]
+        );
+        assert_eq!(results[0].db.as_deref(), Some("core"));
+    }
+
+    #[test]
+    fn test_cache_cleaner() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |f: &str| dir.path().join(f);
+
+        for f in &[
+            "foo-1.0-1-any.pkg.tar.zst",
+            "foo-2.0-1-any.pkg.tar.zst",
+            "foo-3.0-1-any.pkg.tar.zst",
+            "bar-1.0-1-any.pkg.tar.zst",
+            "bar-2.0-1-any.pkg.tar.zst",
+            "baz-1.0-1-any.pkg.tar.zst",
+        ] {
+            fs::write(path(f), "1234").unwrap();
+        }
+        fs::write(path("foo-3.0-1-any.pkg.tar.zst.sig"), "12").unwrap();
+
+        let pkgs = scan_cache([dir.path()]).unwrap();
+        let installed = |name: &str| name != "bar";
+        let corrupt = |pkg: &CachedPkg| pkg.name == "baz";
+
+        let report = CacheCleaner::new()
+            .keep(Some(2))
+            .select(&pkgs, installed, corrupt);
+        assert_eq!(report.candidates.len(), 1);
+        assert_eq!(
+            report.candidates[0].pkg.filename(),
+            "foo-1.0-1-any.pkg.tar.zst"
+        );
+        assert_eq!(report.candidates[0].reason, CacheRemovalReason::OldVersion);
+        assert_eq!(report.bytes(), 4);
+
+        let report = CacheCleaner::new()
+            .keep(None)
+            .keep_uninstalled(Some(0))
+            .remove_corrupt(true)
+            .select(&pkgs, installed, corrupt);
+        let mut removed = report
+            .candidates
+            .iter()
+            .map(|c| (c.pkg.filename(), c.reason))
+            .collect::<Vec<_>>();
+        removed.sort();
+        assert_eq!(
+            removed,
+            vec![
+                ("bar-1.0-1-any.pkg.tar.zst", CacheRemovalReason::Uninstalled),
+                ("bar-2.0-1-any.pkg.tar.zst", CacheRemovalReason::Uninstalled),
+                ("baz-1.0-1-any.pkg.tar.zst", CacheRemovalReason::Corrupt),
+            ]
+        );
+
+        let report = CacheCleaner::new()
+            .keep(None)
+            .remove_unsigned(true)
+            .select(&pkgs, installed, corrupt);
+        assert_eq!(report.candidates.len(), 5);
+        assert_eq!(report.remove().unwrap(), 20);
+        assert_eq!(scan_cache([dir.path()]).unwrap().len(), 1);
+        assert!(path("foo-3.0-1-any.pkg.tar.zst.sig").exists());
+    }
+}
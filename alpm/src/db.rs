@@ -132,7 +132,7 @@ impl<'a> Db<'a> {
         self.handle.check_ret(ret)
     }
 
-    #[cfg(not(feature = "git"))]
+    #[cfg(not(any(feature = "git", alpm_git, alpm_multi_abi)))]
     pub fn search<S: AsRef<str>, I: IntoIterator<Item = S>>(
         &self,
         list: I,
@@ -145,7 +145,7 @@ impl<'a> Db<'a> {
         Ok(AlpmList::new(self.handle, pkgs, FreeMethod::FreeList))
     }
 
-    #[cfg(feature = "git")]
+    #[cfg(all(any(feature = "git", alpm_git), not(alpm_multi_abi)))]
     pub fn search<S: AsRef<str>, I: IntoIterator<Item = S>>(
         &self,
         list: I,
@@ -159,6 +159,25 @@ impl<'a> Db<'a> {
         Ok(AlpmList::new(self.handle, ret, FreeMethod::FreeList))
     }
 
+    #[cfg(alpm_multi_abi)]
+    pub fn search<S: AsRef<str>, I: IntoIterator<Item = S>>(
+        &self,
+        list: I,
+    ) -> Result<AlpmList<'a, Package<'a>>> {
+        let list = to_strlist(list);
+        let pkgs = if alpm_sys::abi() == 12 {
+            let pkgs = unsafe { alpm_db_search(self.db, list) };
+            self.handle.check_null(pkgs).map(|()| pkgs)
+        } else {
+            let mut ret = std::ptr::null_mut();
+            let ok = unsafe { alpm_sys::abi13::alpm_db_search(self.db, list, &mut ret) };
+            self.handle.check_ret(ok).map(|()| ret)
+        };
+        unsafe { alpm_list_free_inner(list, Some(free)) };
+        unsafe { alpm_list_free(list) };
+        Ok(AlpmList::new(self.handle, pkgs?, FreeMethod::FreeList))
+    }
+
     pub fn groups(&self) -> Result<AlpmList<'a, Group>> {
         let groups = unsafe { alpm_db_get_pkgcache(self.db) };
         self.handle.check_null(groups)?;
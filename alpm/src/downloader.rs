@@ -0,0 +1,497 @@
+use crate::utils::http_date;
+use crate::{set_fetchcb, Alpm, Error, FetchCbReturn, PackageFrom, Result};
+
+use alpm_sys::*;
+
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+// the fetch callback has no context, so the builtin downloader is global and
+// belongs to one handle, which clears it when it is released
+static BUILTIN: Mutex<Option<(Downloader, usize)>> = Mutex::new(None);
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Ord, PartialOrd, Hash)]
+pub enum DownloadStatus {
+    Downloaded,
+    UpToDate,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DownloadJob {
+    pub filename: String,
+    /// Base urls to try in order, `filename` is appended to each.
+    pub servers: Vec<String>,
+    pub dir: PathBuf,
+    pub force: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Downloader {
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    parallel: usize,
+}
+
+impl Default for Downloader {
+    fn default() -> Downloader {
+        Downloader::new()
+    }
+}
+
+fn url_filename(url: &str) -> io::Result<&str> {
+    url.rsplit('/')
+        .next()
+        .filter(|f| !f.is_empty())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid url: {}", url)))
+}
+
+fn mtime(path: &Path) -> Option<i64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    let secs = modified.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    Some(secs as i64)
+}
+
+fn copy_progress<F: FnMut(u64, u64)>(
+    mut reader: impl Read,
+    mut writer: impl Write,
+    mut xfered: u64,
+    total: u64,
+    progress: &mut F,
+) -> io::Result<()> {
+    let mut buf = [0; 64 * 1024];
+    progress(xfered, total);
+
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        writer.write_all(&buf[..n])?;
+        xfered += n as u64;
+        progress(xfered, total);
+    }
+
+    writer.flush()
+}
+
+impl Downloader {
+    pub fn new() -> Downloader {
+        Downloader {
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(10),
+            parallel: 5,
+        }
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Downloader {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// How long a mirror may stall before moving on to the next one.
+    pub fn read_timeout(mut self, timeout: Duration) -> Downloader {
+        self.read_timeout = timeout;
+        self
+    }
+
+    pub fn parallel(mut self, parallel: usize) -> Downloader {
+        self.parallel = parallel.max(1);
+        self
+    }
+
+    /// Download `url` into the directory `dir`. Partial downloads are kept as
+    /// `<file>.part` and resumed. Unless `force` is set, an existing file is
+    /// only downloaded again if the server has a newer one.
+    pub fn download<F: FnMut(u64, u64)>(
+        &self,
+        url: &str,
+        dir: impl AsRef<Path>,
+        force: bool,
+        mut progress: F,
+    ) -> io::Result<DownloadStatus> {
+        let dest = dir.as_ref().join(url_filename(url)?);
+        let mut part = dest.clone().into_os_string();
+        part.push(".part");
+        let part = PathBuf::from(part);
+
+        if let Some(path) = url.strip_prefix("file://") {
+            return self.copy_file(Path::new(path), &dest, &part, force, &mut progress);
+        }
+
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(self.connect_timeout)
+            .timeout_read(self.read_timeout)
+            .build();
+
+        let mut resume = fs::metadata(&part).map(|m| m.len()).unwrap_or(0);
+
+        loop {
+            let mut req = agent.get(url);
+            if resume > 0 {
+                req = req.set("Range", &format!("bytes={}-", resume));
+            }
+            if !force {
+                if let Some(time) = mtime(&dest) {
+                    req = req.set("If-Modified-Since", &http_date(time));
+                }
+            }
+
+            let res = match req.call() {
+                Ok(res) => res,
+                Err(ureq::Error::Status(416, _)) if resume > 0 => {
+                    fs::remove_file(&part)?;
+                    resume = 0;
+                    continue;
+                }
+                Err(ureq::Error::Status(code, _)) => {
+                    let kind = match code {
+                        404 | 410 => io::ErrorKind::NotFound,
+                        401 | 403 => io::ErrorKind::PermissionDenied,
+                        _ => io::ErrorKind::InvalidData,
+                    };
+                    return Err(io::Error::new(kind, format!("{}: HTTP {}", url, code)));
+                }
+                Err(e) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        format!("{}: {}", url, e),
+                    ))
+                }
+            };
+
+            if res.status() == 304 {
+                return Ok(DownloadStatus::UpToDate);
+            }
+
+            let append = res.status() == 206;
+            let xfered = if append { resume } else { 0 };
+            let total = res
+                .header("Content-Length")
+                .and_then(|l| l.parse::<u64>().ok())
+                .map(|l| l + xfered)
+                .unwrap_or(0);
+
+            let file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(append)
+                .truncate(!append)
+                .open(&part)?;
+            copy_progress(res.into_reader(), file, xfered, total, &mut progress)?;
+            break;
+        }
+
+        fs::rename(&part, &dest)?;
+        Ok(DownloadStatus::Downloaded)
+    }
+
+    fn copy_file<F: FnMut(u64, u64)>(
+        &self,
+        src: &Path,
+        dest: &Path,
+        part: &Path,
+        force: bool,
+        progress: &mut F,
+    ) -> io::Result<DownloadStatus> {
+        if !force {
+            if let (Some(src), Some(dest)) = (mtime(src), mtime(dest)) {
+                if dest >= src {
+                    return Ok(DownloadStatus::UpToDate);
+                }
+            }
+        }
+
+        let file = File::open(src)?;
+        let total = file.metadata()?.len();
+        copy_progress(file, File::create(part)?, 0, total, progress)?;
+        fs::rename(part, dest)?;
+        Ok(DownloadStatus::Downloaded)
+    }
+
+    /// Try each server in turn until one of them succeeds.
+    pub fn download_mirrors<S: AsRef<str>, F: FnMut(u64, u64)>(
+        &self,
+        servers: &[S],
+        filename: &str,
+        dir: impl AsRef<Path>,
+        force: bool,
+        mut progress: F,
+    ) -> io::Result<DownloadStatus> {
+        let mut err = io::Error::new(io::ErrorKind::NotFound, "no servers");
+
+        for server in servers {
+            let url = format!("{}/{}", server.as_ref().trim_end_matches('/'), filename);
+            match self.download(&url, dir.as_ref(), force, &mut progress) {
+                Ok(status) => return Ok(status),
+                Err(e) => err = e,
+            }
+        }
+
+        Err(err)
+    }
+
+    /// Run `jobs` using up to `parallel` downloads at once. Results are in
+    /// the same order as `jobs`.
+    pub fn download_all(&self, jobs: &[DownloadJob]) -> Vec<io::Result<DownloadStatus>> {
+        let next = Mutex::new(0);
+        let results = Mutex::new((0..jobs.len()).map(|_| None).collect::<Vec<_>>());
+
+        thread::scope(|s| {
+            for _ in 0..self.parallel.min(jobs.len()) {
+                s.spawn(|| loop {
+                    let i = {
+                        let mut next = next.lock().unwrap();
+                        *next += 1;
+                        *next - 1
+                    };
+                    let job = match jobs.get(i) {
+                        Some(job) => job,
+                        None => break,
+                    };
+
+                    let ret = self.download_mirrors(
+                        &job.servers,
+                        &job.filename,
+                        &job.dir,
+                        job.force,
+                        |_, _| (),
+                    );
+                    results.lock().unwrap()[i] = Some(ret);
+                });
+            }
+        });
+
+        results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect()
+    }
+}
+
+pub(crate) fn release_builtin_downloader(handle: *mut alpm_handle_t) {
+    let mut builtin = BUILTIN.lock().unwrap();
+    if matches!(*builtin, Some((_, h)) if h == handle as usize) {
+        *builtin = None;
+    }
+}
+
+fn builtin_fetchcb(url: &str, localpath: &str, force: bool) -> FetchCbReturn {
+    let (downloader, handle) = match *BUILTIN.lock().unwrap() {
+        Some((ref downloader, handle)) => (downloader.clone(), handle as *mut alpm_handle_t),
+        None => return FetchCbReturn::Err,
+    };
+
+    let dlcb = unsafe { alpm_option_get_dlcb(handle) };
+    let filename = CString::new(url_filename(url).unwrap_or(url)).unwrap();
+    let progress = |xfered: u64, total: u64| {
+        if let Some(dlcb) = dlcb {
+            unsafe { dlcb(filename.as_ptr(), xfered as off_t, total as off_t) };
+        }
+    };
+
+    match downloader.download(url, localpath, force, progress) {
+        Ok(DownloadStatus::Downloaded) => FetchCbReturn::Ok,
+        Ok(DownloadStatus::UpToDate) => FetchCbReturn::FileExists,
+        Err(_) => FetchCbReturn::Err,
+    }
+}
+
+impl Alpm {
+    /// Use `downloader` for all of libalpm's downloads. Progress is reported
+    /// to the download callback. Only one handle can use the builtin
+    /// downloader at a time, others fail with `Error::DownloaderInUse` until
+    /// that handle is dropped.
+    pub fn use_builtin_downloader(&self, downloader: Downloader) -> Result<()> {
+        let mut builtin = BUILTIN.lock().unwrap();
+        if matches!(*builtin, Some((_, handle)) if handle != self.handle as usize) {
+            return Err(Error::DownloaderInUse);
+        }
+
+        *builtin = Some((downloader, self.handle as usize));
+        set_fetchcb!(self, builtin_fetchcb);
+        Ok(())
+    }
+
+    /// Download the sync packages of the current transaction in parallel into
+    /// the first cache directory, so that committing finds them cached.
+    pub fn prefetch_trans(&self, downloader: &Downloader) -> io::Result<()> {
+        let cachedirs = self.cachedirs().collect::<Vec<_>>();
+        let dir = cachedirs
+            .first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no cache directory"))?;
+
+        let jobs = self
+            .trans_add()
+            .filter(|pkg| pkg.origin() == PackageFrom::SyncDb)
+            .filter(|pkg| {
+                !cachedirs
+                    .iter()
+                    .any(|d| Path::new(d).join(pkg.filename()).exists())
+            })
+            .map(|pkg| DownloadJob {
+                filename: pkg.filename().to_string(),
+                servers: pkg
+                    .db()
+                    .map(|db| db.servers().map(|s| s.to_string()).collect())
+                    .unwrap_or_default(),
+                dir: PathBuf::from(dir),
+                force: false,
+            })
+            .collect::<Vec<_>>();
+
+        downloader
+            .download_all(&jobs)
+            .into_iter()
+            .find(|r| r.is_err())
+            .unwrap_or(Ok(DownloadStatus::Downloaded))
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{data, serve};
+
+    #[test]
+    fn test_http_date() {
+        assert_eq!(http_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(http_date(1560240901), "Tue, 11 Jun 2019 08:15:01 GMT");
+    }
+
+    #[test]
+    fn test_download_resume() {
+        let server = serve(vec![("/repo/foo.pkg.tar.zst", data(200_000))]);
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("foo.pkg.tar.zst.part"),
+            &data(200_000)[..1000],
+        )
+        .unwrap();
+
+        let mut last = (0, 0);
+        let status = Downloader::new()
+            .download(
+                &format!("{}/repo/foo.pkg.tar.zst", server),
+                dir.path(),
+                true,
+                |x, t| last = (x, t),
+            )
+            .unwrap();
+
+        assert_eq!(status, DownloadStatus::Downloaded);
+        assert_eq!(last, (200_000, 200_000));
+        assert_eq!(
+            fs::read(dir.path().join("foo.pkg.tar.zst")).unwrap(),
+            data(200_000)
+        );
+        assert!(!dir.path().join("foo.pkg.tar.zst.part").exists());
+    }
+
+    #[test]
+    fn test_download_failover() {
+        let bad = serve(vec![]);
+        let good = serve(vec![("/core/os/x86_64/core.db", data(100))]);
+        let dir = tempfile::tempdir().unwrap();
+
+        let downloader = Downloader::new().read_timeout(Duration::from_secs(1));
+        let servers = [
+            format!("{}/core/os/x86_64", bad),
+            "http://127.0.0.1:1/core/os/x86_64".to_string(),
+            format!("{}/core/os/x86_64", good),
+        ];
+        let status = downloader
+            .download_mirrors(&servers, "core.db", dir.path(), true, |_, _| ())
+            .unwrap();
+        assert_eq!(status, DownloadStatus::Downloaded);
+        assert_eq!(fs::read(dir.path().join("core.db")).unwrap(), data(100));
+
+        assert!(downloader
+            .download_mirrors(&servers[..2], "core.db", dir.path(), true, |_, _| ())
+            .is_err());
+    }
+
+    #[test]
+    fn test_download_file() {
+        let src = tempfile::tempdir().unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        fs::write(src.path().join("extra.db"), data(10)).unwrap();
+
+        let url = format!("file://{}/extra.db", src.path().display());
+        let downloader = Downloader::new();
+        assert_eq!(
+            downloader
+                .download(&url, dest.path(), false, |_, _| ())
+                .unwrap(),
+            DownloadStatus::Downloaded
+        );
+        assert_eq!(
+            downloader
+                .download(&url, dest.path(), false, |_, _| ())
+                .unwrap(),
+            DownloadStatus::UpToDate
+        );
+        assert_eq!(fs::read(dest.path().join("extra.db")).unwrap(), data(10));
+
+        // a copy that fails leaves the file it would replace alone
+        fs::create_dir(src.path().join("core.db")).unwrap();
+        fs::write(dest.path().join("core.db"), data(5)).unwrap();
+        let url = format!("file://{}/core.db", src.path().display());
+        assert!(downloader
+            .download(&url, dest.path(), true, |_, _| ())
+            .is_err());
+        assert_eq!(fs::read(dest.path().join("core.db")).unwrap(), data(5));
+    }
+
+    #[test]
+    fn test_download_all() {
+        let server = serve(vec![
+            ("/a.pkg.tar.zst", data(10)),
+            ("/b.pkg.tar.zst", data(20)),
+            ("/c.pkg.tar.zst", data(30)),
+        ]);
+        let dir = tempfile::tempdir().unwrap();
+
+        let jobs = ["a", "b", "missing", "c"]
+            .iter()
+            .map(|name| DownloadJob {
+                filename: format!("{}.pkg.tar.zst", name),
+                servers: vec![server.clone()],
+                dir: dir.path().to_path_buf(),
+                force: true,
+            })
+            .collect::<Vec<_>>();
+
+        let results = Downloader::new().parallel(2).download_all(&jobs);
+        assert_eq!(results.len(), 4);
+        assert!(results[0].is_ok());
+        assert!(results[2].is_err());
+        assert_eq!(
+            fs::read(dir.path().join("c.pkg.tar.zst")).unwrap(),
+            data(30)
+        );
+    }
+
+    #[test]
+    fn test_builtin_downloader() {
+        let first = Alpm::new("/", "tests/db").unwrap();
+        let second = Alpm::new("/", "tests/db").unwrap();
+
+        first.use_builtin_downloader(Downloader::new()).unwrap();
+        first.use_builtin_downloader(Downloader::new()).unwrap();
+        assert_eq!(
+            second.use_builtin_downloader(Downloader::new()),
+            Err(Error::DownloaderInUse)
+        );
+
+        drop(first);
+        second.use_builtin_downloader(Downloader::new()).unwrap();
+    }
+}
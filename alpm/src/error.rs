@@ -3,6 +3,7 @@ use crate::Alpm;
 use std::error;
 use std::ffi::CStr;
 use std::fmt;
+use std::io;
 use std::mem::transmute;
 
 use alpm_sys::_alpm_errno_t::*;
@@ -68,6 +69,15 @@ pub enum Error {
     ExternalDownload = ALPM_ERR_EXTERNAL_DOWNLOAD as u32,
     Gpgme = ALPM_ERR_GPGME as u32,
     MissingCapabilitySignatures = ALPM_ERR_MISSING_CAPABILITY_SIGNATURES as u32,
+    /// libalpm could not be loaded at runtime.
+    #[cfg(feature = "dynamic")]
+    LibraryNotFound = u32::MAX - 1,
+    /// The libalpm loaded at runtime has an unsupported ABI version.
+    #[cfg(feature = "dynamic")]
+    LibraryVersion = u32::MAX,
+    /// Another handle is using the builtin downloader.
+    #[cfg(feature = "downloader")]
+    DownloaderInUse = u32::MAX - 2,
 }
 
 impl Error {
@@ -90,6 +100,18 @@ impl Error {
 
 impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        // not libalpm errors, and libalpm may not be available to describe them
+        #[cfg(feature = "dynamic")]
+        match self {
+            Error::LibraryNotFound => return fmt.write_str("failed to load libalpm"),
+            Error::LibraryVersion => return fmt.write_str("unsupported libalpm version"),
+            _ => (),
+        }
+        #[cfg(feature = "downloader")]
+        if let Error::DownloaderInUse = self {
+            return fmt.write_str("the builtin downloader is used by another handle");
+        }
+
         let err = unsafe { transmute::<Error, alpm_errno_t>(*self) };
         let s = unsafe { CStr::from_ptr(alpm_strerror(err)) };
         fmt.write_str(&s.to_str().unwrap())
@@ -98,6 +120,102 @@ impl fmt::Display for Error {
 
 impl error::Error for Error {}
 
+#[cfg(feature = "dynamic")]
+impl From<alpm_sys::LoadError> for Error {
+    fn from(err: alpm_sys::LoadError) -> Error {
+        match err {
+            alpm_sys::LoadError::NotFound(_) => Error::LibraryNotFound,
+            alpm_sys::LoadError::UnsupportedVersion(_) => Error::LibraryVersion,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Hash)]
+pub enum ParseError {
+    /// A line that could not be parsed.
+    Line { line: usize, msg: String },
+    /// A required field that does not appear anywhere in the input.
+    MissingField(String),
+}
+
+impl ParseError {
+    pub(crate) fn new(line: usize, msg: impl Into<String>) -> ParseError {
+        ParseError::Line {
+            line,
+            msg: msg.into(),
+        }
+    }
+
+    pub(crate) fn missing(field: impl Into<String>) -> ParseError {
+        ParseError::MissingField(field.into())
+    }
+
+    /// The line the error is on, starting at 1. `None` for missing fields.
+    pub fn line(&self) -> Option<usize> {
+        match *self {
+            ParseError::Line { line, .. } => Some(line),
+            ParseError::MissingField(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Line { line, msg } => write!(fmt, "line {}: {}", line, msg),
+            ParseError::MissingField(field) => write!(fmt, "missing {}", field),
+        }
+    }
+}
+
+impl error::Error for ParseError {}
+
+impl From<ParseError> for io::Error {
+    fn from(err: ParseError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+#[derive(Debug)]
+pub enum BootstrapError {
+    Io(io::Error),
+    Alpm(Error),
+    /// A target that is neither a package nor a group in the sync databases.
+    TargetNotFound(String),
+}
+
+impl fmt::Display for BootstrapError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BootstrapError::Io(err) => err.fmt(fmt),
+            BootstrapError::Alpm(err) => err.fmt(fmt),
+            BootstrapError::TargetNotFound(target) => write!(fmt, "target not found: {}", target),
+        }
+    }
+}
+
+impl error::Error for BootstrapError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            BootstrapError::Io(err) => Some(err),
+            BootstrapError::Alpm(err) => Some(err),
+            BootstrapError::TargetNotFound(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for BootstrapError {
+    fn from(err: io::Error) -> BootstrapError {
+        BootstrapError::Io(err)
+    }
+}
+
+impl From<Error> for BootstrapError {
+    fn from(err: Error) -> BootstrapError {
+        BootstrapError::Alpm(err)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::Alpm;
@@ -0,0 +1,454 @@
+use crate::bootstrap::create_layout;
+use crate::repo::section;
+use crate::{
+    Alpm, Error, MTreeType, PkgArchive, PkgBuilder, PkgInfo, RepoWriter, Result, SigLevel,
+};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use md5::Md5;
+use sha2::Digest;
+use tempfile::TempDir;
+
+// build and install date of every fixture package
+const EPOCH: i64 = 1551404516;
+const DBPATH: &str = "var/lib/pacman";
+
+/// A package to put into a fixture's local or sync databases.
+#[derive(Debug, Clone)]
+pub struct FixturePkg {
+    info: PkgInfo,
+    files: Vec<(String, Vec<u8>, u32)>,
+    explicit: bool,
+}
+
+impl FixturePkg {
+    pub fn new(name: impl Into<String>, version: impl Into<String>) -> FixturePkg {
+        FixturePkg::from_info(PkgInfo {
+            name: name.into(),
+            version: version.into(),
+            arch: Some("any".to_string()),
+            ..PkgInfo::default()
+        })
+    }
+
+    pub fn from_info(info: PkgInfo) -> FixturePkg {
+        FixturePkg {
+            info,
+            files: Vec::new(),
+            explicit: true,
+        }
+    }
+
+    pub fn info(&self) -> &PkgInfo {
+        &self.info
+    }
+
+    pub fn depends(mut self, dep: impl Into<String>) -> FixturePkg {
+        self.info.depends.push(dep.into());
+        self
+    }
+
+    pub fn provides(mut self, dep: impl Into<String>) -> FixturePkg {
+        self.info.provides.push(dep.into());
+        self
+    }
+
+    pub fn conflicts(mut self, dep: impl Into<String>) -> FixturePkg {
+        self.info.conflicts.push(dep.into());
+        self
+    }
+
+    pub fn replaces(mut self, dep: impl Into<String>) -> FixturePkg {
+        self.info.replaces.push(dep.into());
+        self
+    }
+
+    pub fn group(mut self, group: impl Into<String>) -> FixturePkg {
+        self.info.groups.push(group.into());
+        self
+    }
+
+    /// Add a regular file at `path`, relative to the root.
+    pub fn file(mut self, path: impl Into<String>, data: impl Into<Vec<u8>>) -> FixturePkg {
+        self.files.push((path.into(), data.into(), 0o644));
+        self
+    }
+
+    pub fn executable(mut self, path: impl Into<String>, data: impl Into<Vec<u8>>) -> FixturePkg {
+        self.files.push((path.into(), data.into(), 0o755));
+        self
+    }
+
+    /// Add a file that is listed in the package's backup array.
+    pub fn backup(mut self, path: impl Into<String>, data: impl Into<Vec<u8>>) -> FixturePkg {
+        let path = path.into();
+        self.info.backup.push(path.clone());
+        self.file(path, data)
+    }
+
+    /// Mark the package as installed as a dependency. Only used for local
+    /// packages.
+    pub fn as_dependency(mut self) -> FixturePkg {
+        self.explicit = false;
+        self
+    }
+
+    pub fn filename(&self) -> String {
+        format!(
+            "{}-{}-{}.pkg.tar.gz",
+            self.info.name,
+            self.info.version,
+            self.info.arch.as_deref().unwrap_or("any")
+        )
+    }
+
+    fn build(&self, dir: &Path) -> io::Result<PathBuf> {
+        let stage = tempfile::tempdir()?;
+        for (path, data, mode) in &self.files {
+            let file = stage.path().join(path.trim_start_matches('/'));
+            if let Some(parent) = file.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&file, data)?;
+            fs::set_permissions(&file, fs::Permissions::from_mode(*mode))?;
+        }
+
+        let out = dir.join(self.filename());
+        PkgBuilder::new(stage.path(), self.info.clone())
+            .source_date_epoch(EPOCH)
+            .build(&out)?;
+        Ok(out)
+    }
+}
+
+/// Describes a root with local and sync databases, built into a temporary
+/// directory by `build`.
+#[derive(Debug, Clone, Default)]
+pub struct Fixture {
+    local: Vec<FixturePkg>,
+    sync: BTreeMap<String, Vec<FixturePkg>>,
+    pkgs: Vec<FixturePkg>,
+}
+
+/// A fixture built by `Fixture::build`. The directory is removed on drop.
+#[derive(Debug)]
+pub struct FixtureRoot {
+    dir: TempDir,
+    repos: Vec<String>,
+}
+
+// the same fields and order as libalpm writes
+fn local_desc(info: &PkgInfo, explicit: bool) -> String {
+    let mut desc = String::new();
+
+    section(&mut desc, "NAME", Some(&info.name));
+    section(&mut desc, "VERSION", Some(&info.version));
+    section(&mut desc, "BASE", &info.base);
+    section(&mut desc, "DESC", &info.desc);
+    section(&mut desc, "URL", &info.url);
+    section(&mut desc, "ARCH", &info.arch);
+    section(&mut desc, "BUILDDATE", Some(info.build_date.to_string()));
+    section(&mut desc, "INSTALLDATE", Some(EPOCH.to_string()));
+    section(&mut desc, "PACKAGER", &info.packager);
+    section(&mut desc, "SIZE", Some(info.size.to_string()));
+    section(&mut desc, "REASON", if explicit { None } else { Some("1") });
+    section(&mut desc, "GROUPS", &info.groups);
+    section(&mut desc, "LICENSE", &info.licenses);
+    section(&mut desc, "VALIDATION", Some("none"));
+    section(&mut desc, "REPLACES", &info.replaces);
+    section(&mut desc, "DEPENDS", &info.depends);
+    section(&mut desc, "OPTDEPENDS", &info.optdepends);
+    section(&mut desc, "CONFLICTS", &info.conflicts);
+    section(&mut desc, "PROVIDES", &info.provides);
+
+    desc
+}
+
+fn install(root: &Path, path: &Path, explicit: bool) -> io::Result<()> {
+    let archive = PkgArchive::open(path)?;
+    let info = archive.pkginfo()?;
+    archive.extract(root, |_| true)?;
+
+    let mut files = archive
+        .entries()?
+        .into_iter()
+        .filter(|e| !e.path.starts_with('.'))
+        .map(|e| match e.file_type {
+            MTreeType::Dir => format!("{}/", e.path.trim_end_matches('/')),
+            _ => e.path,
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+
+    let mut backup = Vec::new();
+    for file in &info.backup {
+        let data = fs::read(root.join(file))?;
+        backup.push(format!("{}\t{:x}", file, Md5::digest(&data)));
+    }
+
+    let mut files_entry = String::new();
+    section(&mut files_entry, "FILES", &files);
+    section(&mut files_entry, "BACKUP", &backup);
+
+    let dir = root
+        .join(DBPATH)
+        .join("local")
+        .join(format!("{}-{}", info.name, info.version));
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join("desc"), local_desc(&info, explicit))?;
+    fs::write(dir.join("files"), files_entry)?;
+    fs::write(dir.join("mtree"), archive.read_file(".MTREE")?)?;
+
+    Ok(())
+}
+
+impl Fixture {
+    pub fn new() -> Fixture {
+        Fixture::default()
+    }
+
+    /// Install `pkg` into the root and the local database.
+    pub fn local(mut self, pkg: FixturePkg) -> Fixture {
+        self.local.push(pkg);
+        self
+    }
+
+    /// Add `pkg` to the sync database `repo`.
+    pub fn sync(mut self, repo: impl Into<String>, pkg: FixturePkg) -> Fixture {
+        self.sync.entry(repo.into()).or_default().push(pkg);
+        self
+    }
+
+    /// Add a sync database even if it has no packages.
+    pub fn repo(mut self, repo: impl Into<String>) -> Fixture {
+        self.sync.entry(repo.into()).or_default();
+        self
+    }
+
+    /// Build a package file that is in no database, see
+    /// `FixtureRoot::pkg_path`.
+    pub fn pkg(mut self, pkg: FixturePkg) -> Fixture {
+        self.pkgs.push(pkg);
+        self
+    }
+
+    pub fn build(&self) -> io::Result<FixtureRoot> {
+        let fixture = FixtureRoot {
+            dir: tempfile::tempdir()?,
+            repos: self.sync.keys().cloned().collect(),
+        };
+        create_layout(&fixture.root(), DBPATH)?;
+        fs::create_dir_all(fixture.pkgs_dir())?;
+
+        for pkg in &self.local {
+            let path = pkg.build(&fixture.pkgs_dir())?;
+            install(&fixture.root(), &path, pkg.explicit)?;
+            fs::remove_file(path)?;
+        }
+
+        for pkg in &self.pkgs {
+            pkg.build(&fixture.pkgs_dir())?;
+        }
+
+        for (repo, pkgs) in &self.sync {
+            let dir = fixture.repo_dir(repo);
+            fs::create_dir_all(&dir)?;
+
+            let mut writer = RepoWriter::new(&dir, repo.as_str());
+            for pkg in pkgs {
+                writer.add(pkg.build(&dir)?)?;
+            }
+            writer.write()?;
+
+            fs::copy(
+                dir.join(format!("{}.db.tar.gz", repo)),
+                fixture.dbpath().join("sync").join(format!("{}.db", repo)),
+            )?;
+        }
+
+        Ok(fixture)
+    }
+}
+
+impl FixtureRoot {
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn root(&self) -> PathBuf {
+        self.path().join("root")
+    }
+
+    pub fn dbpath(&self) -> PathBuf {
+        self.root().join(DBPATH)
+    }
+
+    pub fn cachedir(&self) -> PathBuf {
+        self.root().join("var/cache/pacman/pkg")
+    }
+
+    pub fn logfile(&self) -> PathBuf {
+        self.root().join("var/log/pacman.log")
+    }
+
+    /// The directory a sync database's packages and database are in, which
+    /// is also its server.
+    pub fn repo_dir(&self, repo: &str) -> PathBuf {
+        self.path().join("repos").join(repo)
+    }
+
+    pub fn pkgs_dir(&self) -> PathBuf {
+        self.path().join("pkgs")
+    }
+
+    /// The package file of a package added with `Fixture::pkg`.
+    pub fn pkg_path(&self, pkg: &FixturePkg) -> PathBuf {
+        self.pkgs_dir().join(pkg.filename())
+    }
+
+    pub fn repos(&self) -> &[String] {
+        &self.repos
+    }
+
+    /// A handle for the root with every sync database registered. Signatures
+    /// are not checked.
+    pub fn handle(&self) -> Result<Alpm> {
+        let path = |p: PathBuf| p.to_str().map(|s| s.to_string()).ok_or(Error::WrongArgs);
+
+        let mut handle = Alpm::new(path(self.root())?, path(self.dbpath())?)?;
+        handle.add_cachedir(path(self.cachedir())?)?;
+        handle.set_logfile(path(self.logfile())?)?;
+        handle.set_default_siglevel(SigLevel::NONE)?;
+        handle.set_local_file_siglevel(SigLevel::NONE)?;
+        handle.set_remote_file_siglevel(SigLevel::NONE)?;
+
+        for repo in &self.repos {
+            let server = format!("file://{}", path(self.repo_dir(repo))?);
+            let db = handle.register_syncdb_mut(repo, SigLevel::NONE)?;
+            db.add_server(server)?;
+        }
+
+        Ok(handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PackageReason, TransFlag};
+
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn fixture() -> FixtureRoot {
+        Fixture::new()
+            .local(
+                FixturePkg::new("glibc", "2.28-1")
+                    .file("usr/lib/libc.so.6", "libc")
+                    .as_dependency(),
+            )
+            .local(
+                FixturePkg::new("foo", "1.0-1")
+                    .depends("glibc")
+                    .executable("usr/bin/foo", "#!/bin/sh\n")
+                    .backup("etc/foo.conf", "foo = 1\n"),
+            )
+            .sync("core", FixturePkg::new("glibc", "2.29-1"))
+            .sync(
+                "extra",
+                FixturePkg::new("foo", "1.1-1")
+                    .depends("glibc")
+                    .file("usr/bin/foo", "#!/bin/sh\necho foo\n"),
+            )
+            .sync("extra", FixturePkg::new("bar", "1.0-1").depends("foo"))
+            .repo("community")
+            .pkg(FixturePkg::new("baz", "1.0-1").file("usr/share/baz", "baz"))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_build() {
+        let fixture = fixture();
+        let root = fixture.root();
+        let local = fixture.dbpath().join("local");
+
+        assert_eq!(fixture.repos(), &["community", "core", "extra"]);
+        assert_eq!(fs::read(root.join("usr/bin/foo")).unwrap(), b"#!/bin/sh\n");
+        let mode = fs::metadata(root.join("usr/bin/foo"))
+            .unwrap()
+            .permissions();
+        assert_eq!(mode.mode() & 0o777, 0o755);
+
+        let desc = fs::read_to_string(local.join("foo-1.0-1/desc")).unwrap();
+        assert!(desc.starts_with("%NAME%\nfoo\n\n%VERSION%\n1.0-1\n\n"));
+        assert!(desc.contains("%DEPENDS%\nglibc\n\n"));
+        assert!(!desc.contains("%REASON%"));
+        let desc = fs::read_to_string(local.join("glibc-2.28-1/desc")).unwrap();
+        assert!(desc.contains("%REASON%\n1\n\n"));
+
+        let files = fs::read_to_string(local.join("foo-1.0-1/files")).unwrap();
+        assert_eq!(
+            files,
+            "%FILES%\netc/\netc/foo.conf\nusr/\nusr/bin/\nusr/bin/foo\n\n\
+             %BACKUP%\netc/foo.conf\t2762f73d04e7545e5e3ab457c5185680\n\n"
+        );
+
+        let mut mtree = String::new();
+        GzDecoder::new(fs::File::open(local.join("foo-1.0-1/mtree")).unwrap())
+            .read_to_string(&mut mtree)
+            .unwrap();
+        assert!(mtree.starts_with("#mtree\n"));
+        assert!(mtree.contains("./usr/bin/foo "));
+
+        for repo in fixture.repos() {
+            let db = fixture.dbpath().join("sync").join(format!("{}.db", repo));
+            assert!(db.is_file());
+        }
+        let pkgs = PkgArchive::open(fixture.repo_dir("extra").join("bar-1.0-1-any.pkg.tar.gz"))
+            .unwrap()
+            .pkginfo()
+            .unwrap();
+        assert_eq!(pkgs.depends, vec!["foo".to_string()]);
+
+        let baz = FixturePkg::new("baz", "1.0-1");
+        assert!(fixture.pkg_path(&baz).is_file());
+        assert!(!fixture.pkgs_dir().join("foo-1.0-1-any.pkg.tar.gz").exists());
+    }
+
+    #[test]
+    fn test_trans() {
+        let fixture = fixture();
+        let mut handle = fixture.handle().unwrap();
+
+        let foo = handle.localdb().pkg("foo").unwrap();
+        assert_eq!(foo.reason(), PackageReason::Explicit);
+        assert!(!foo.backup().is_empty());
+
+        let bar = handle.syncdbs().find_map(|db| db.pkg("bar").ok()).unwrap();
+        handle.trans_init(TransFlag::NONE).unwrap();
+        handle.trans_add_pkg(bar).unwrap();
+        handle.trans_sysupgrade(false).unwrap();
+        handle.trans_prepare().unwrap();
+        handle.trans_commit().unwrap();
+        handle.trans_release().unwrap();
+
+        let localdb = handle.localdb();
+        assert_eq!(localdb.pkg("bar").unwrap().version().to_string(), "1.0-1");
+        assert_eq!(localdb.pkg("foo").unwrap().version().to_string(), "1.1-1");
+        assert_eq!(
+            localdb.pkg("glibc").unwrap().version().to_string(),
+            "2.29-1"
+        );
+        assert_eq!(
+            fs::read(fixture.root().join("usr/bin/foo")).unwrap(),
+            b"#!/bin/sh\necho foo\n"
+        );
+        assert!(fixture.cachedir().join("bar-1.0-1-any.pkg.tar.gz").exists());
+    }
+}
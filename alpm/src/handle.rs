@@ -39,6 +39,10 @@ impl Alpm {
         unsafe { from_cstr(alpm_option_get_lockfile(self.handle)) }
     }
 
+    pub fn logfile(&self) -> Option<&str> {
+        unsafe { from_cstr_optional(alpm_option_get_logfile(self.handle)) }
+    }
+
     pub fn gpgdir(&self) -> &str {
         unsafe { from_cstr(alpm_option_get_gpgdir(self.handle)) }
     }
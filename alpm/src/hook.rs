@@ -0,0 +1,480 @@
+use crate::utils::fnmatch_patterns;
+use crate::{Alpm, HookWhen, ParseError};
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Ord, PartialOrd, Hash)]
+pub enum HookTriggerType {
+    Path,
+    Package,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Ord, PartialOrd, Hash)]
+pub enum HookOperation {
+    Install,
+    Upgrade,
+    Remove,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HookTrigger {
+    pub trigger_type: HookTriggerType,
+    pub operations: Vec<HookOperation>,
+    pub targets: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Hook {
+    pub name: String,
+    pub description: Option<String>,
+    pub triggers: Vec<HookTrigger>,
+    pub depends: Vec<String>,
+    pub when: HookWhen,
+    pub exec: String,
+    pub abort_on_fail: bool,
+    pub needs_targets: bool,
+}
+
+/// The packages and files a transaction installs, upgrades and removes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TransChanges {
+    pub install_pkgs: Vec<String>,
+    pub upgrade_pkgs: Vec<String>,
+    pub remove_pkgs: Vec<String>,
+    pub install_files: Vec<String>,
+    pub upgrade_files: Vec<String>,
+    pub remove_files: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TriggeredHook {
+    pub hook: Hook,
+    pub targets: Vec<String>,
+}
+
+enum Section {
+    None,
+    Trigger,
+    Action,
+}
+
+fn parse_when(line: usize, value: &str) -> Result<HookWhen, ParseError> {
+    match value {
+        "PreTransaction" => Ok(HookWhen::PreTransaction),
+        "PostTransaction" => Ok(HookWhen::PostTransaction),
+        _ => Err(ParseError::new(line, format!("invalid When: {}", value))),
+    }
+}
+
+impl HookTrigger {
+    fn matches(&self, target: &str) -> bool {
+        fnmatch_patterns(&self.targets, target).unwrap_or(false)
+    }
+
+    fn triggered_targets(&self, changes: &TransChanges) -> BTreeSet<String> {
+        let (install, upgrade, remove) = match self.trigger_type {
+            HookTriggerType::Path => (
+                &changes.install_files,
+                &changes.upgrade_files,
+                &changes.remove_files,
+            ),
+            HookTriggerType::Package => (
+                &changes.install_pkgs,
+                &changes.upgrade_pkgs,
+                &changes.remove_pkgs,
+            ),
+        };
+
+        let mut targets = BTreeSet::new();
+        for op in &self.operations {
+            let list = match op {
+                HookOperation::Install => install,
+                HookOperation::Upgrade => upgrade,
+                HookOperation::Remove => remove,
+            };
+            targets.extend(list.iter().filter(|t| self.matches(t)).cloned());
+        }
+
+        targets
+    }
+}
+
+impl Hook {
+    pub fn parse(name: impl Into<String>, s: impl AsRef<str>) -> Result<Hook, ParseError> {
+        let mut section = Section::None;
+        let mut triggers = Vec::new();
+        let mut trigger_type = None;
+        let mut operations = Vec::new();
+        let mut targets = Vec::new();
+        let mut description = None;
+        let mut depends = Vec::new();
+        let mut when = None;
+        let mut exec = None;
+        let mut abort_on_fail = false;
+        let mut needs_targets = false;
+
+        let mut finish_trigger = |line: usize,
+                                  trigger_type: &mut Option<HookTriggerType>,
+                                  operations: &mut Vec<HookOperation>,
+                                  targets: &mut Vec<String>|
+         -> Result<(), ParseError> {
+            let trigger_type = trigger_type
+                .take()
+                .ok_or_else(|| ParseError::new(line, "trigger is missing Type"))?;
+            if operations.is_empty() {
+                return Err(ParseError::new(line, "trigger is missing Operation"));
+            }
+            if targets.is_empty() {
+                return Err(ParseError::new(line, "trigger is missing Target"));
+            }
+
+            triggers.push(HookTrigger {
+                trigger_type,
+                operations: std::mem::take(operations),
+                targets: std::mem::take(targets),
+            });
+            Ok(())
+        };
+
+        for (n, line) in s.as_ref().lines().enumerate() {
+            let n = n + 1;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                if let Section::Trigger = section {
+                    finish_trigger(n, &mut trigger_type, &mut operations, &mut targets)?;
+                }
+
+                section = match &line[1..line.len() - 1] {
+                    "Trigger" => Section::Trigger,
+                    "Action" => Section::Action,
+                    s => return Err(ParseError::new(n, format!("invalid section: {}", s))),
+                };
+                continue;
+            }
+
+            let mut split = line.splitn(2, '=');
+            let key = split.next().unwrap().trim();
+            let value = split.next().map(str::trim);
+
+            match (&section, key, value) {
+                (Section::Trigger, "Type", Some(value)) => {
+                    trigger_type = match value {
+                        "Path" | "File" => Some(HookTriggerType::Path),
+                        "Package" => Some(HookTriggerType::Package),
+                        _ => return Err(ParseError::new(n, format!("invalid Type: {}", value))),
+                    }
+                }
+                (Section::Trigger, "Operation", Some(value)) => operations.push(match value {
+                    "Install" => HookOperation::Install,
+                    "Upgrade" => HookOperation::Upgrade,
+                    "Remove" => HookOperation::Remove,
+                    _ => return Err(ParseError::new(n, format!("invalid Operation: {}", value))),
+                }),
+                (Section::Trigger, "Target", Some(value)) => targets.push(value.to_string()),
+                (Section::Action, "Description", Some(value)) => {
+                    description = Some(value.to_string())
+                }
+                (Section::Action, "When", Some(value)) => when = Some(parse_when(n, value)?),
+                (Section::Action, "Exec", Some(value)) => exec = Some(value.to_string()),
+                (Section::Action, "Depends", Some(value)) => depends.push(value.to_string()),
+                (Section::Action, "AbortOnFail", None) => abort_on_fail = true,
+                (Section::Action, "NeedsTargets", None) => needs_targets = true,
+                (Section::None, _, _) => {
+                    return Err(ParseError::new(n, "option outside of section"));
+                }
+                _ => return Err(ParseError::new(n, format!("invalid option: {}", line))),
+            }
+        }
+
+        if let Section::Trigger = section {
+            let n = s.as_ref().lines().count();
+            finish_trigger(n, &mut trigger_type, &mut operations, &mut targets)?;
+        }
+
+        if triggers.is_empty() {
+            return Err(ParseError::missing("[Trigger]"));
+        }
+        let when = when.ok_or_else(|| ParseError::missing("When"))?;
+        let exec = exec.ok_or_else(|| ParseError::missing("Exec"))?;
+
+        Ok(Hook {
+            name: name.into(),
+            description,
+            triggers,
+            depends,
+            when,
+            exec,
+            // libalpm ignores AbortOnFail for PostTransaction hooks
+            abort_on_fail: abort_on_fail && when == HookWhen::PreTransaction,
+            needs_targets,
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Hook> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy())
+            .unwrap_or_default();
+        let name = name.trim_end_matches(".hook");
+        let data = fs::read_to_string(path)?;
+        Ok(Hook::parse(name, data)?)
+    }
+
+    /// Returns the targets that trigger this hook, or `None` if it does not run.
+    pub fn triggered_targets(&self, changes: &TransChanges) -> Option<Vec<String>> {
+        let mut targets = BTreeSet::new();
+        for trigger in &self.triggers {
+            targets.extend(trigger.triggered_targets(changes));
+        }
+
+        if targets.is_empty() {
+            None
+        } else {
+            Some(targets.into_iter().collect())
+        }
+    }
+}
+
+/// Load all hooks from `dirs`. Hooks in later directories override hooks of
+/// the same name in earlier ones and hooks symlinked to /dev/null are skipped.
+pub fn load_hooks<P: AsRef<Path>, I: IntoIterator<Item = P>>(dirs: I) -> io::Result<Vec<Hook>> {
+    let mut paths = std::collections::BTreeMap::new();
+
+    for dir in dirs {
+        let entries = match fs::read_dir(dir.as_ref()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.ends_with(".hook") {
+                paths.insert(name, entry.path());
+            }
+        }
+    }
+
+    let mut hooks = Vec::new();
+    for path in paths.values() {
+        if fs::read_link(path).ok().as_deref() == Some(Path::new("/dev/null")) {
+            continue;
+        }
+        if path.is_dir() {
+            continue;
+        }
+        hooks.push(Hook::load(path)?);
+    }
+
+    Ok(hooks)
+}
+
+fn split_changes(
+    mut install: Vec<String>,
+    mut remove: Vec<String>,
+) -> (Vec<String>, Vec<String>, Vec<String>) {
+    install.sort();
+    install.dedup();
+    remove.sort();
+    remove.dedup();
+
+    let upgrade = install
+        .iter()
+        .filter(|f| remove.binary_search(f).is_ok())
+        .cloned()
+        .collect::<Vec<_>>();
+    install.retain(|f| upgrade.binary_search(f).is_err());
+    remove.retain(|f| upgrade.binary_search(f).is_err());
+
+    (install, upgrade, remove)
+}
+
+impl Alpm {
+    /// Collects the changes of the current transaction. File lists of sync
+    /// packages are only known when the matching files databases are used.
+    pub fn trans_changes(&self) -> TransChanges {
+        let localdb = self.localdb();
+        let mut changes = TransChanges::default();
+        let mut install_files = Vec::new();
+        let mut remove_files = Vec::new();
+
+        for pkg in self.trans_add() {
+            let files = pkg.files();
+            install_files.extend(files.files().iter().map(|f| f.name().to_string()));
+
+            match localdb.pkg(pkg.name()) {
+                Ok(local) => {
+                    changes.upgrade_pkgs.push(pkg.name().to_string());
+                    let files = local.files();
+                    remove_files.extend(files.files().iter().map(|f| f.name().to_string()));
+                }
+                Err(_) => changes.install_pkgs.push(pkg.name().to_string()),
+            }
+        }
+
+        for pkg in self.trans_remove() {
+            changes.remove_pkgs.push(pkg.name().to_string());
+            let files = pkg.files();
+            remove_files.extend(files.files().iter().map(|f| f.name().to_string()));
+        }
+
+        let (install, upgrade, remove) = split_changes(install_files, remove_files);
+        changes.install_files = install;
+        changes.upgrade_files = upgrade;
+        changes.remove_files = remove;
+        changes
+    }
+
+    /// Predict which hooks in `hookdirs()` the current transaction will run
+    /// at `when`, in the order libalpm runs them.
+    pub fn predict_hooks(&self, when: HookWhen) -> io::Result<Vec<TriggeredHook>> {
+        let changes = self.trans_changes();
+        let hooks = load_hooks(self.hookdirs())?;
+
+        let triggered = hooks
+            .into_iter()
+            .filter(|hook| hook.when == when)
+            .filter_map(|hook| {
+                hook.triggered_targets(&changes)
+                    .map(|targets| TriggeredHook { hook, targets })
+            })
+            .collect();
+
+        Ok(triggered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOOK: &str = "
+# comment
+[Trigger]
+Type = Path
+Operation = Install
+Operation = Upgrade
+Target = usr/lib/modules/*/vmlinuz
+Target = usr/lib/initcpio/*
+Target = !usr/lib/initcpio/README
+
+[Trigger]
+Type = Package
+Operation = Remove
+Target = mkinitcpio
+
+[Action]
+Description = Updating linux initcpios...
+When = PostTransaction
+Exec = /usr/share/libalpm/scripts/mkinitcpio-install
+Depends = mkinitcpio
+NeedsTargets
+AbortOnFail
+";
+
+    #[test]
+    fn test_parse() {
+        let hook = Hook::parse("90-mkinitcpio-install", HOOK).unwrap();
+        assert_eq!(hook.triggers.len(), 2);
+        assert_eq!(hook.triggers[0].trigger_type, HookTriggerType::Path);
+        assert_eq!(
+            hook.triggers[0].operations,
+            vec![HookOperation::Install, HookOperation::Upgrade]
+        );
+        assert_eq!(hook.triggers[1].targets, vec!["mkinitcpio"]);
+        assert_eq!(hook.when, HookWhen::PostTransaction);
+        assert_eq!(hook.depends, vec!["mkinitcpio"]);
+        assert!(hook.needs_targets);
+        assert!(!hook.abort_on_fail);
+    }
+
+    #[test]
+    fn test_parse_err() {
+        let err = Hook::parse("a", "[Action]\nWhen = PostTransaction\nExec = /bin/true");
+        assert_eq!(err.unwrap_err(), ParseError::missing("[Trigger]"));
+        let err = Hook::parse("a", "[Trigger]\nType = Path\nTarget = *\n[Action]").unwrap_err();
+        assert_eq!(err.line(), Some(4));
+        let err = Hook::parse("a", "[Trigger]\nType = Foo").unwrap_err();
+        assert_eq!(err.line(), Some(2));
+    }
+
+    #[test]
+    fn test_triggered_targets() {
+        let hook = Hook::parse("90-mkinitcpio-install", HOOK).unwrap();
+
+        let (install, upgrade, remove) = split_changes(
+            vec![
+                "usr/lib/initcpio/README".into(),
+                "usr/lib/initcpio/install/".into(),
+                "usr/lib/modules/5.1.8-arch1-1/vmlinuz".into(),
+            ],
+            vec![
+                "usr/lib/initcpio/install/".into(),
+                "usr/lib/modules/5.0.0-arch1-1/vmlinuz".into(),
+            ],
+        );
+        let mut changes = TransChanges {
+            install_files: install,
+            upgrade_files: upgrade,
+            remove_files: remove,
+            ..TransChanges::default()
+        };
+
+        assert_eq!(
+            hook.triggered_targets(&changes).unwrap(),
+            vec![
+                "usr/lib/initcpio/install/",
+                "usr/lib/modules/5.1.8-arch1-1/vmlinuz"
+            ]
+        );
+
+        changes.install_files.clear();
+        changes.upgrade_files.clear();
+        assert!(hook.triggered_targets(&changes).is_none());
+
+        changes.remove_pkgs.push("mkinitcpio".into());
+        assert_eq!(
+            hook.triggered_targets(&changes).unwrap(),
+            vec!["mkinitcpio"]
+        );
+    }
+
+    #[test]
+    fn test_load_hooks() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let sys = dir.join("sys");
+        let user = dir.join("user");
+        fs::create_dir_all(&sys).unwrap();
+        fs::create_dir_all(&user).unwrap();
+
+        let hook = |when| {
+            format!(
+                "[Trigger]\nType = Package\nOperation = Install\nTarget = *\n\
+                 [Action]\nWhen = {}\nExec = /bin/true\n",
+                when
+            )
+        };
+        fs::write(sys.join("a.hook"), hook("PreTransaction")).unwrap();
+        fs::write(sys.join("b.hook"), hook("PreTransaction")).unwrap();
+        fs::write(sys.join("c.conf"), "").unwrap();
+        fs::write(user.join("a.hook"), hook("PostTransaction")).unwrap();
+        std::os::unix::fs::symlink("/dev/null", user.join("b.hook")).unwrap();
+
+        let hooks = load_hooks([&sys, &user, &dir.join("missing")]).unwrap();
+
+        assert_eq!(hooks.len(), 1);
+        assert_eq!(hooks[0].name, "a");
+        assert_eq!(hooks[0].when, HookWhen::PostTransaction);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::utils::*;
+use crate::{
+    Backup, DepMod, Depend, File, PackageFrom, PackageReason, PackageValidation, Pkg, Version,
+};
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DependInfo {
+    pub name: String,
+    pub depmod: DepMod,
+    pub version: Option<Version>,
+    pub desc: Option<String>,
+}
+
+impl<'a> From<&Depend<'a>> for DependInfo {
+    fn from(dep: &Depend<'a>) -> DependInfo {
+        let desc = dep.desc();
+
+        DependInfo {
+            name: dep.name().to_string(),
+            depmod: dep.depmod(),
+            version: dep.version().map(|v| Version::new(&**v)),
+            desc: if desc.is_empty() {
+                None
+            } else {
+                Some(desc.to_string())
+            },
+        }
+    }
+}
+
+impl fmt::Display for DependInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)?;
+
+        if let Some(ref version) = self.version {
+            let depmod = match self.depmod {
+                DepMod::Any => "",
+                DepMod::Eq => "=",
+                DepMod::Ge => ">=",
+                DepMod::Le => "<=",
+                DepMod::Gt => ">",
+                DepMod::Lt => "<",
+            };
+            write!(f, "{}{}", depmod, version)?;
+        }
+
+        if let Some(ref desc) = self.desc {
+            write!(f, ": {}", desc)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FileInfo {
+    pub name: String,
+    pub size: i64,
+    pub mode: u32,
+}
+
+impl From<&File> for FileInfo {
+    fn from(file: &File) -> FileInfo {
+        FileInfo {
+            name: file.name().to_string(),
+            size: file.size(),
+            mode: file.mode(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BackupInfo {
+    pub name: String,
+    pub hash: String,
+}
+
+impl From<&Backup> for BackupInfo {
+    fn from(backup: &Backup) -> BackupInfo {
+        BackupInfo {
+            name: backup.name().to_string(),
+            hash: unsafe { from_cstr((*backup.inner).hash) }.to_string(),
+        }
+    }
+}
+
+/// An owned copy of a package's metadata that does not borrow the handle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageInfo {
+    pub name: String,
+    pub filename: String,
+    pub base: Option<String>,
+    pub version: Version,
+    pub origin: PackageFrom,
+    pub desc: Option<String>,
+    pub url: Option<String>,
+    pub build_date: i64,
+    pub install_date: Option<i64>,
+    pub packager: Option<String>,
+    pub md5sum: Option<String>,
+    pub sha256sum: Option<String>,
+    pub arch: Option<String>,
+    pub size: i64,
+    pub isize: i64,
+    pub reason: PackageReason,
+    pub validation: PackageValidation,
+    pub licenses: Vec<String>,
+    pub groups: Vec<String>,
+    pub depends: Vec<DependInfo>,
+    pub optdepends: Vec<DependInfo>,
+    pub checkdepends: Vec<DependInfo>,
+    pub makedepends: Vec<DependInfo>,
+    pub conflicts: Vec<DependInfo>,
+    pub provides: Vec<DependInfo>,
+    pub replaces: Vec<DependInfo>,
+    pub files: Vec<FileInfo>,
+    pub backup: Vec<BackupInfo>,
+    pub db: Option<String>,
+    pub base64_sig: Option<String>,
+    pub has_scriptlet: bool,
+}
+
+impl<'a> Pkg<'a> {
+    pub fn to_owned_info(&self) -> PackageInfo {
+        let to_string = |s: &str| s.to_string();
+        let to_info = |d: Depend| DependInfo::from(&d);
+
+        PackageInfo {
+            name: self.name().to_string(),
+            filename: self.filename().to_string(),
+            base: self.base().map(to_string),
+            version: Version::new(&**self.version()),
+            origin: self.origin(),
+            desc: self.desc().map(to_string),
+            url: self.url().map(to_string),
+            build_date: self.build_date(),
+            install_date: self.install_date(),
+            packager: self.packager().map(to_string),
+            md5sum: self.md5sum().map(to_string),
+            sha256sum: self.sha256sum().map(to_string),
+            arch: self.arch().map(to_string),
+            size: self.size(),
+            isize: self.isize(),
+            reason: self.reason(),
+            validation: self.validation(),
+            licenses: self.licenses().map(to_string).collect(),
+            groups: self.groups().map(to_string).collect(),
+            depends: self.depends().map(to_info).collect(),
+            optdepends: self.optdepends().map(to_info).collect(),
+            checkdepends: self.checkdepends().map(to_info).collect(),
+            makedepends: self.makedepends().map(to_info).collect(),
+            conflicts: self.conflicts().map(to_info).collect(),
+            provides: self.provides().map(to_info).collect(),
+            replaces: self.replaces().map(to_info).collect(),
+            files: self.files().files().iter().map(FileInfo::from).collect(),
+            backup: self.backup().map(|b| BackupInfo::from(&b)).collect(),
+            db: self.db().map(|db| db.name().to_string()),
+            base64_sig: self.base64_sig().map(to_string),
+            has_scriptlet: self.has_scriptlet(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Alpm, SigLevel};
+
+    fn assert_send_sync_clone<T: Send + Sync + Clone>(_: &T) {}
+
+    #[test]
+    fn test_to_owned_info() {
+        let handle = Alpm::new("/", "tests/db").unwrap();
+        let db = handle.register_syncdb("core", SigLevel::NONE).unwrap();
+        let pkg = db.pkg("linux").unwrap();
+        let info = pkg.to_owned_info();
+        drop(handle);
+
+        assert_send_sync_clone(&info);
+        assert_eq!(info.name, "linux");
+        assert_eq!(info.version, "5.1.8.arch1-1");
+        assert_eq!(info.db.as_deref(), Some("core"));
+        assert_eq!(info.groups, vec!["base"]);
+        assert_eq!(
+            info.depends
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>(),
+            vec!["coreutils", "linux-firmware", "kmod", "mkinitcpio"]
+        );
+    }
+
+    #[test]
+    fn test_to_owned_info_local() {
+        let handle = Alpm::new("/", "tests/db").unwrap();
+        let pkg = handle.localdb().pkg("pacman").unwrap();
+        let info = pkg.to_owned_info();
+
+        assert_eq!(info.origin, PackageFrom::LocalDb);
+        assert!(info.install_date.is_some());
+        assert_eq!(info.backup[0].name, "etc/pacman.conf");
+        assert!(info.files.iter().any(|f| f.name == "etc/pacman.conf"));
+        assert_eq!(
+            info.depends
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "bash",
+                "glibc",
+                "libarchive",
+                "curl",
+                "gpgme",
+                "pacman-mirrorlist",
+                "archlinux-keyring"
+            ]
+        );
+        assert_eq!(
+            info.depends
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>(),
+            pkg.depends().map(|d| d.to_string()).collect::<Vec<_>>()
+        );
+    }
+}
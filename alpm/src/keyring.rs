@@ -0,0 +1,612 @@
+use crate::Alpm;
+
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const GPG_CONF: &str = "\
+no-greeting
+no-permission-warning
+lock-never
+keyserver-options timeout=10
+keyserver-options import-clean
+";
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Ord, PartialOrd, Hash)]
+pub enum KeyTrust {
+    Unknown,
+    Undefined,
+    Never,
+    Marginal,
+    Full,
+    Ultimate,
+    Invalid,
+    Revoked,
+    Expired,
+}
+
+impl KeyTrust {
+    fn from_gpg(c: &str) -> KeyTrust {
+        match c {
+            "q" => KeyTrust::Undefined,
+            "n" => KeyTrust::Never,
+            "m" => KeyTrust::Marginal,
+            "f" => KeyTrust::Full,
+            "u" => KeyTrust::Ultimate,
+            "i" | "d" => KeyTrust::Invalid,
+            "r" => KeyTrust::Revoked,
+            "e" => KeyTrust::Expired,
+            _ => KeyTrust::Unknown,
+        }
+    }
+
+    // the levels used by --import-ownertrust
+    fn ownertrust(self) -> Option<u8> {
+        match self {
+            KeyTrust::Undefined => Some(2),
+            KeyTrust::Never => Some(3),
+            KeyTrust::Marginal => Some(4),
+            KeyTrust::Full => Some(5),
+            KeyTrust::Ultimate => Some(6),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeyringSubkey {
+    pub fingerprint: String,
+    pub created: i64,
+    pub expires: Option<i64>,
+    pub validity: KeyTrust,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeyringKey {
+    pub fingerprint: String,
+    pub uids: Vec<String>,
+    pub subkeys: Vec<KeyringSubkey>,
+    pub created: i64,
+    pub expires: Option<i64>,
+    pub validity: KeyTrust,
+    pub owner_trust: KeyTrust,
+    pub disabled: bool,
+    pub secret: bool,
+}
+
+/// A pacman keyring, as managed by pacman-key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Keyring {
+    dir: PathBuf,
+}
+
+// gpg escapes special characters in user ids as \xHH
+fn unescape(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && bytes.get(i + 1) == Some(&b'x') {
+            let hex = s
+                .get(i + 2..i + 4)
+                .and_then(|h| u8::from_str_radix(h, 16).ok());
+            if let Some(c) = hex {
+                out.push(c);
+                i += 4;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn parse_colons(s: &str, secret: bool) -> Vec<KeyringKey> {
+    let mut keys: Vec<KeyringKey> = Vec::new();
+    let mut in_key = false;
+
+    for line in s.lines() {
+        let fields = line.split(':').collect::<Vec<_>>();
+        let field = |n: usize| fields.get(n).copied().unwrap_or("");
+
+        match field(0) {
+            "pub" | "sec" => {
+                in_key = true;
+                keys.push(KeyringKey {
+                    fingerprint: String::new(),
+                    uids: Vec::new(),
+                    subkeys: Vec::new(),
+                    created: field(5).parse().unwrap_or(0),
+                    expires: field(6).parse().ok(),
+                    validity: KeyTrust::from_gpg(field(1)),
+                    owner_trust: KeyTrust::from_gpg(field(8)),
+                    disabled: field(11).contains('D'),
+                    secret,
+                })
+            }
+            "sub" | "ssb" => {
+                in_key = false;
+                if let Some(key) = keys.last_mut() {
+                    key.subkeys.push(KeyringSubkey {
+                        fingerprint: String::new(),
+                        created: field(5).parse().unwrap_or(0),
+                        expires: field(6).parse().ok(),
+                        validity: KeyTrust::from_gpg(field(1)),
+                    });
+                }
+            }
+            "fpr" => {
+                let fingerprint = match keys.last_mut() {
+                    Some(key) if in_key => &mut key.fingerprint,
+                    Some(key) => match key.subkeys.last_mut() {
+                        Some(subkey) => &mut subkey.fingerprint,
+                        None => continue,
+                    },
+                    None => continue,
+                };
+                if fingerprint.is_empty() {
+                    *fingerprint = field(9).to_string();
+                }
+            }
+            "uid" => {
+                if let Some(key) = keys.last_mut() {
+                    key.uids.push(unescape(field(9)));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    keys
+}
+
+// "fingerprint:level:" lines of a -trusted file, or fingerprints of a
+// -revoked file
+fn read_key_list(path: &Path) -> io::Result<Vec<(String, Option<String>)>> {
+    let s = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    Ok(s.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| {
+            let mut split = l.split(':');
+            let fpr = split.next().unwrap().to_string();
+            let level = split
+                .next()
+                .filter(|l| !l.is_empty())
+                .map(|l| l.to_string());
+            (fpr, level)
+        })
+        .collect())
+}
+
+impl Keyring {
+    pub fn new(dir: impl Into<PathBuf>) -> Keyring {
+        Keyring { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn gpg<S: AsRef<str>>(&self, args: &[S], stdin: Option<&[u8]>) -> io::Result<String> {
+        let output = self.gpg_raw(args, stdin)?;
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+
+    fn gpg_raw<S: AsRef<str>>(&self, args: &[S], stdin: Option<&[u8]>) -> io::Result<Vec<u8>> {
+        let mut cmd = Command::new("gpg");
+        cmd.arg("--homedir")
+            .arg(&self.dir)
+            .args(["--batch", "--no-permission-warning", "--quiet"])
+            .args(args.iter().map(|a| a.as_ref()))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        let mut child = cmd.spawn()?;
+        let mut input = child.stdin.take().unwrap();
+        if let Some(stdin) = stdin {
+            input.write_all(stdin)?;
+        }
+        drop(input);
+
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("gpg {}: {}", args[0].as_ref(), stderr.trim()),
+            ));
+        }
+
+        Ok(output.stdout)
+    }
+
+    /// Create a binary detached signature of `path` with the secret key `key`.
+    pub fn detach_sign(&self, key: &str, path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        let path = path.as_ref().to_str().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "path is not valid utf-8")
+        })?;
+        self.gpg_raw(
+            &[
+                "--detach-sign",
+                "--no-armor",
+                "--local-user",
+                key,
+                "--output",
+                "-",
+                path,
+            ],
+            None,
+        )
+    }
+
+    /// Create the keyring and its local master key, like `pacman-key --init`.
+    /// Existing keys are kept.
+    pub fn init(&self) -> io::Result<()> {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o755)
+            .create(&self.dir)?;
+
+        let conf = self.dir.join("gpg.conf");
+        if !conf.exists() {
+            fs::write(&conf, GPG_CONF)?;
+        }
+        fs::set_permissions(&conf, fs::Permissions::from_mode(0o644))?;
+
+        if self.secret_keys()?.is_empty() {
+            self.gpg(
+                &[
+                    "--passphrase",
+                    "",
+                    "--pinentry-mode",
+                    "loopback",
+                    "--quick-gen-key",
+                    "Pacman Keyring Master Key <pacman@localhost>",
+                    "ed25519",
+                    "sign",
+                    "never",
+                ],
+                None,
+            )?;
+        }
+
+        self.update_trustdb()
+    }
+
+    pub fn update_trustdb(&self) -> io::Result<()> {
+        self.gpg(&["--update-trustdb"], None).map(|_| ())
+    }
+
+    pub fn import(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref().to_str().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "path is not valid utf-8")
+        })?;
+        self.gpg(&["--import", path], None).map(|_| ())
+    }
+
+    /// Import binary or armored keys.
+    pub fn import_data(&self, data: &[u8]) -> io::Result<()> {
+        self.gpg(&["--import"], Some(data)).map(|_| ())
+    }
+
+    /// Export the public key `key` in binary form.
+    pub fn export(&self, key: &str) -> io::Result<Vec<u8>> {
+        let data = self.gpg_raw(&["--export", key], None)?;
+        if data.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no key {}", key),
+            ));
+        }
+        Ok(data)
+    }
+
+    pub fn keys(&self) -> io::Result<Vec<KeyringKey>> {
+        let out = self.gpg(&["--with-colons", "--fixed-list-mode", "--list-keys"], None)?;
+        Ok(parse_colons(&out, false))
+    }
+
+    pub fn secret_keys(&self) -> io::Result<Vec<KeyringKey>> {
+        let out = self.gpg(
+            &["--with-colons", "--fixed-list-mode", "--list-secret-keys"],
+            None,
+        )?;
+        Ok(parse_colons(&out, true))
+    }
+
+    pub fn key(&self, key: &str) -> io::Result<KeyringKey> {
+        let out = self.gpg(
+            &["--with-colons", "--fixed-list-mode", "--list-keys", key],
+            None,
+        )?;
+        parse_colons(&out, false)
+            .into_iter()
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no key {}", key)))
+    }
+
+    /// Locally sign `key` with the master key.
+    pub fn lsign(&self, key: &str) -> io::Result<()> {
+        self.gpg(&["--yes", "--quick-lsign-key", key], None)
+            .map(|_| ())
+    }
+
+    /// Set the owner trust of `key`. Only `Undefined`, `Never`, `Marginal`,
+    /// `Full` and `Ultimate` can be set.
+    pub fn set_trust(&self, key: &str, trust: KeyTrust) -> io::Result<()> {
+        let level = trust.ownertrust().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("can not set trust {:?}", trust),
+            )
+        })?;
+        let fingerprint = self.key(key)?.fingerprint;
+        let input = format!("{}:{}:\n", fingerprint, level);
+        self.gpg(&["--import-ownertrust"], Some(input.as_bytes()))
+            .map(|_| ())
+    }
+
+    /// Disable `key` so it is no longer trusted, as pacman-key does for keys
+    /// listed as revoked.
+    pub fn revoke(&self, key: &str) -> io::Result<()> {
+        self.gpg(&["--edit-key", key, "disable", "save"], None)
+            .map(|_| ())
+    }
+
+    /// Import keys from a distribution keyring directory, like
+    /// `pacman-key --populate`. Each keyring `name` consists of `name.gpg`,
+    /// and optionally `name-trusted` and `name-revoked`. If `keyrings` is
+    /// empty every keyring in `dir` is used.
+    pub fn populate<S: AsRef<str>>(&self, dir: impl AsRef<Path>, keyrings: &[S]) -> io::Result<()> {
+        let dir = dir.as_ref();
+        let mut names = keyrings
+            .iter()
+            .map(|k| k.as_ref().to_string())
+            .collect::<Vec<_>>();
+
+        if names.is_empty() {
+            for entry in fs::read_dir(dir)? {
+                let name = entry?.file_name();
+                if let Some(name) = name.to_str().and_then(|n| n.strip_suffix(".gpg")) {
+                    names.push(name.to_string());
+                }
+            }
+            names.sort();
+        }
+
+        for name in &names {
+            let path = dir.join(format!("{}.gpg", name));
+            if !path.exists() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("keyring not found: {}", path.display()),
+                ));
+            }
+        }
+
+        for name in &names {
+            self.import(dir.join(format!("{}.gpg", name)))?;
+        }
+
+        for name in &names {
+            let trusted = read_key_list(&dir.join(format!("{}-trusted", name)))?;
+            let mut ownertrust = String::new();
+
+            for (fpr, level) in trusted {
+                self.lsign(&fpr)?;
+                ownertrust.push_str(&format!("{}:{}:\n", fpr, level.as_deref().unwrap_or("4")));
+            }
+            if !ownertrust.is_empty() {
+                self.gpg(&["--import-ownertrust"], Some(ownertrust.as_bytes()))?;
+            }
+        }
+
+        for name in &names {
+            for (fpr, _) in read_key_list(&dir.join(format!("{}-revoked", name)))? {
+                if self.key(&fpr).is_ok() {
+                    self.revoke(&fpr)?;
+                }
+            }
+        }
+
+        self.update_trustdb()
+    }
+}
+
+impl Alpm {
+    pub fn keyring(&self) -> Keyring {
+        Keyring::new(self.gpgdir())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the keyring tests shell out to gpg, which may not be installed
+    fn has_gpg() -> bool {
+        let gpg = Command::new("gpg")
+            .arg("--version")
+            .stdout(Stdio::null())
+            .status();
+        if !gpg.is_ok_and(|s| s.success()) {
+            eprintln!("skipping: gpg is not installed");
+            return false;
+        }
+        true
+    }
+
+    // export a new key from a throwaway keyring, returning its fingerprint
+    fn gen_key(uid: &str, out: &Path) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let keyring = Keyring::new(dir.path());
+        keyring
+            .gpg(
+                &[
+                    "--passphrase",
+                    "",
+                    "--pinentry-mode",
+                    "loopback",
+                    "--quick-gen-key",
+                    uid,
+                    "ed25519",
+                    "sign",
+                    "never",
+                ],
+                None,
+            )
+            .unwrap();
+
+        let path = out.to_str().unwrap();
+        keyring
+            .gpg(&["--yes", "--output", path, "--export"], None)
+            .unwrap();
+        keyring.keys().unwrap().remove(0).fingerprint
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(
+            unescape("Foo Bar <foo@example.org>"),
+            "Foo Bar <foo@example.org>"
+        );
+        assert_eq!(unescape("a\\x3ab"), "a:b");
+        assert_eq!(unescape("trailing\\x"), "trailing\\x");
+    }
+
+    #[test]
+    fn test_parse_colons() {
+        let colons = "\
+pub:f:255:22:6AD9B0AF1C1AB1E7:1792380474:::-:::scSC::::::23::0:
+fpr:::::::::44771FB4F813F0B09F24E6E96AD9B0AF1C1AB1E7:
+uid:f::::1792380474::0::Ed Packager <ed@example.org>::::::::::0:
+sub:r:255:22:8B2B21623900286D:1792384198::::::s::::::23:
+fpr:::::::::420E67B2E92C857CA4AE065A8B2B21623900286D:
+";
+        let keys = parse_colons(colons, false);
+        assert_eq!(keys.len(), 1);
+        assert_eq!(
+            keys[0].fingerprint,
+            "44771FB4F813F0B09F24E6E96AD9B0AF1C1AB1E7"
+        );
+        assert_eq!(keys[0].validity, KeyTrust::Full);
+        assert_eq!(
+            keys[0].subkeys,
+            vec![KeyringSubkey {
+                fingerprint: "420E67B2E92C857CA4AE065A8B2B21623900286D".into(),
+                created: 1792384198,
+                expires: None,
+                validity: KeyTrust::Revoked,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_keyring() {
+        if !has_gpg() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let keyring = Keyring::new(dir.path().join("gnupg"));
+        keyring.init().unwrap();
+        keyring.init().unwrap();
+
+        let master = keyring.secret_keys().unwrap();
+        assert_eq!(master.len(), 1);
+        assert_eq!(
+            master[0].uids,
+            vec!["Pacman Keyring Master Key <pacman@localhost>"]
+        );
+
+        let key = dir.path().join("packager.gpg");
+        let fpr = gen_key("Packager <packager@example.org>", &key);
+        keyring.import(&key).unwrap();
+
+        let imported = keyring.key(&fpr).unwrap();
+        assert_eq!(imported.uids, vec!["Packager <packager@example.org>"]);
+        assert_eq!(imported.validity, KeyTrust::Unknown);
+        assert_eq!(imported.expires, None);
+        assert!(!imported.disabled);
+        assert_eq!(keyring.keys().unwrap().len(), 2);
+
+        keyring.lsign(&fpr).unwrap();
+        keyring.set_trust(&fpr, KeyTrust::Marginal).unwrap();
+        keyring.update_trustdb().unwrap();
+        let signed = keyring.key(&fpr).unwrap();
+        assert_eq!(signed.validity, KeyTrust::Full);
+        assert_eq!(signed.owner_trust, KeyTrust::Marginal);
+        assert!(keyring.set_trust(&fpr, KeyTrust::Revoked).is_err());
+
+        let data = dir.path().join("data");
+        fs::write(&data, "data").unwrap();
+        let sig = keyring.detach_sign(&master[0].fingerprint, &data).unwrap();
+        fs::write(dir.path().join("data.sig"), sig).unwrap();
+        let sig = dir.path().join("data.sig");
+        keyring
+            .gpg(
+                &["--verify", sig.to_str().unwrap(), data.to_str().unwrap()],
+                None,
+            )
+            .unwrap();
+        assert!(keyring.detach_sign(&fpr, &data).is_err());
+
+        keyring.revoke(&fpr).unwrap();
+        assert!(keyring.key(&fpr).unwrap().disabled);
+
+        let other = Keyring::new(dir.path().join("other"));
+        other.init().unwrap();
+        other.import_data(&keyring.export(&fpr).unwrap()).unwrap();
+        assert_eq!(other.key(&fpr).unwrap().uids, imported.uids);
+        assert!(keyring.export("0123456789ABCDEF").is_err());
+
+        assert!(keyring.import(dir.path().join("missing.gpg")).is_err());
+        assert!(keyring.key("0123456789ABCDEF").is_err());
+    }
+
+    #[test]
+    fn test_populate() {
+        if !has_gpg() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let keyrings = dir.path().join("keyrings");
+        fs::create_dir(&keyrings).unwrap();
+
+        let trusted = gen_key("Trusted <trusted@example.org>", &dir.path().join("a.gpg"));
+        let revoked = gen_key("Revoked <revoked@example.org>", &dir.path().join("b.gpg"));
+        let mut data = fs::read(dir.path().join("a.gpg")).unwrap();
+        data.extend(fs::read(dir.path().join("b.gpg")).unwrap());
+        fs::write(keyrings.join("distro.gpg"), data).unwrap();
+        fs::write(
+            keyrings.join("distro-trusted"),
+            format!("# trusted keys\n{}:4:\n", trusted),
+        )
+        .unwrap();
+        fs::write(keyrings.join("distro-revoked"), format!("{}\n", revoked)).unwrap();
+
+        let keyring = Keyring::new(dir.path().join("gnupg"));
+        keyring.init().unwrap();
+        keyring.populate(&keyrings, &[] as &[&str]).unwrap();
+
+        let key = keyring.key(&trusted).unwrap();
+        assert_eq!(key.validity, KeyTrust::Full);
+        assert_eq!(key.owner_trust, KeyTrust::Marginal);
+        assert!(keyring.key(&revoked).unwrap().disabled);
+
+        assert!(keyring.populate(&keyrings, &["missing"]).is_err());
+    }
+}
@@ -1,47 +1,111 @@
 mod add;
 mod alpm;
+#[cfg(feature = "archive")]
+mod archive;
 mod be_local;
 mod be_pkg;
 mod be_sync;
+mod bootstrap;
+mod buildinfo;
+mod cache;
 mod conflict;
 mod db;
 mod deps;
 mod dload;
+#[cfg(feature = "downloader")]
+mod downloader;
 mod error;
 mod filelist;
+#[cfg(feature = "fixtures")]
+mod fixture;
 mod handle;
+mod hook;
+mod info;
+mod keyring;
 mod list;
+mod lock;
+mod log;
 mod macros;
+#[cfg(feature = "downloader")]
+mod mirror;
+mod mtree;
 mod package;
+#[cfg(feature = "openpgp")]
+mod pgp;
+#[cfg(feature = "archive")]
+mod pkgbuilder;
+mod pkginfo;
 mod remove;
+#[cfg(feature = "archive")]
+mod repo;
+mod rollback;
+#[cfg(feature = "fixtures")]
+mod scenario;
+mod scriptlet;
 mod signing;
+mod snapshot;
 mod sync;
+#[cfg(all(test, feature = "downloader"))]
+mod test_server;
 mod trans;
+mod trust;
 mod types;
 mod util;
 mod utils;
+mod verify;
 mod version;
 
 pub use crate::add::*;
 pub use crate::alpm::*;
+#[cfg(feature = "archive")]
+pub use crate::archive::*;
 pub use crate::be_local::*;
 pub use crate::be_pkg::*;
 pub use crate::be_sync::*;
+pub use crate::bootstrap::*;
+pub use crate::buildinfo::*;
+pub use crate::cache::*;
 pub use crate::conflict::*;
 pub use crate::db::*;
 pub use crate::deps::*;
 pub use crate::dload::*;
+#[cfg(feature = "downloader")]
+pub use crate::downloader::*;
 pub use crate::error::*;
 pub use crate::filelist::*;
+#[cfg(feature = "fixtures")]
+pub use crate::fixture::*;
 pub use crate::handle::*;
+pub use crate::hook::*;
+pub use crate::info::*;
+pub use crate::keyring::*;
 pub use crate::list::*;
+pub use crate::lock::*;
+pub use crate::log::*;
 pub use crate::macros::*;
+#[cfg(feature = "downloader")]
+pub use crate::mirror::*;
+pub use crate::mtree::*;
 pub use crate::package::*;
+#[cfg(feature = "openpgp")]
+pub use crate::pgp::*;
+#[cfg(feature = "archive")]
+pub use crate::pkgbuilder::*;
+pub use crate::pkginfo::*;
 pub use crate::remove::*;
+#[cfg(feature = "archive")]
+pub use crate::repo::*;
+pub use crate::rollback::*;
+#[cfg(feature = "fixtures")]
+pub use crate::scenario::*;
+pub use crate::scriptlet::*;
 pub use crate::signing::*;
+pub use crate::snapshot::*;
 pub use crate::sync::*;
 pub use crate::trans::*;
+pub use crate::trust::*;
 pub use crate::types::*;
 pub use crate::util::*;
+pub use crate::verify::*;
 pub use crate::version::*;
 pub use alpm_sys;
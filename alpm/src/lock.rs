@@ -0,0 +1,221 @@
+use crate::{Alpm, Error, Result, TransFlag};
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// PROC_PID_INIT_INO, the inode of the initial pid namespace
+const INIT_PID_NS: &str = "pid:[4026531836]";
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
+pub enum LockStatus {
+    Unlocked,
+    /// `pid` is the process holding the lock, if it could be found.
+    Locked {
+        pid: Option<u32>,
+    },
+    /// The lock file exists but no process has it open.
+    Stale,
+}
+
+// whether /proc lists every process, which it does not from inside a pid
+// namespace or, with hidepid, for other users' processes
+fn proc_is_complete() -> bool {
+    let ns = fs::read_link("/proc/self/ns/pid");
+    if !matches!(ns, Ok(ref ns) if ns.as_os_str() == INIT_PID_NS) {
+        return false;
+    }
+
+    let mountinfo = match fs::read_to_string("/proc/self/mountinfo") {
+        Ok(mountinfo) => mountinfo,
+        Err(_) => return false,
+    };
+    !mountinfo.lines().any(|line| {
+        line.split(' ').nth(4) == Some("/proc")
+            && line.split([' ', ',']).any(|opt| {
+                opt.starts_with("hidepid=") && opt != "hidepid=0" && opt != "hidepid=off"
+            })
+    })
+}
+
+// the processes that have the file `meta` is for open, and whether every
+// process could be checked
+fn holders(meta: &fs::Metadata) -> io::Result<(Vec<u32>, bool)> {
+    let mut pids = Vec::new();
+    let mut complete = proc_is_complete();
+
+    for entry in fs::read_dir("/proc")? {
+        let entry = entry?;
+        let pid = match entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+
+        let fds = match fs::read_dir(entry.path().join("fd")) {
+            Ok(fds) => fds,
+            // the process has exited
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(_) => {
+                complete = false;
+                continue;
+            }
+        };
+
+        // compare the files themselves, a process in another mount namespace
+        // sees the lock file at another path
+        for fd in fds.flatten() {
+            let open = fs::metadata(fd.path());
+            if matches!(open, Ok(open) if open.dev() == meta.dev() && open.ino() == meta.ino()) {
+                pids.push(pid);
+                break;
+            }
+        }
+    }
+
+    Ok((pids, complete))
+}
+
+/// The status of the lock file at `path`. libalpm keeps the lock file open
+/// while it holds the lock, so a lock file no process has open is stale.
+///
+/// A lock is only considered stale if every process on the system could be
+/// checked. That is not the case for other users' processes without root,
+/// with /proc mounted with hidepid, or from inside a pid namespace such as a
+/// container, where processes outside of it can not be seen at all. The
+/// status is `Locked { pid: None }` then.
+pub fn lock_status(path: impl AsRef<Path>) -> io::Result<LockStatus> {
+    let meta = match fs::metadata(path) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(LockStatus::Unlocked),
+        Err(e) => return Err(e),
+    };
+
+    let (pids, complete) = holders(&meta)?;
+    let status = match pids.first() {
+        Some(&pid) => LockStatus::Locked { pid: Some(pid) },
+        None if complete => LockStatus::Stale,
+        None => LockStatus::Locked { pid: None },
+    };
+
+    Ok(status)
+}
+
+/// Wait up to `timeout` for the lock file at `path` to be released. Returns
+/// early if the lock is stale and returns `Locked` if the timeout expired.
+pub fn wait_for_lock(path: impl AsRef<Path>, timeout: Duration) -> io::Result<LockStatus> {
+    let path = path.as_ref();
+    let start = Instant::now();
+
+    loop {
+        let status = lock_status(path)?;
+        if !matches!(status, LockStatus::Locked { .. }) || start.elapsed() >= timeout {
+            return Ok(status);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+impl Alpm {
+    pub fn lock_status(&self) -> io::Result<LockStatus> {
+        lock_status(self.lockfile())
+    }
+
+    /// See `wait_for_lock`.
+    pub fn wait_for_lock(&self, timeout: Duration) -> io::Result<LockStatus> {
+        wait_for_lock(self.lockfile(), timeout)
+    }
+
+    /// Remove the lock file only if it is stale, unlike `unlock`. Returns
+    /// whether it was removed. See `lock_status` for when a lock can not be
+    /// known to be stale.
+    pub fn remove_stale_lock(&self) -> io::Result<bool> {
+        if self.lock_status()? != LockStatus::Stale {
+            return Ok(false);
+        }
+
+        match fs::remove_file(self.lockfile()) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Like `trans_init` but waits up to `timeout` for the lock to be
+    /// released instead of failing with `Error::HandleLock` right away.
+    pub fn trans_init_wait(&self, flags: TransFlag, timeout: Duration) -> Result<()> {
+        let start = Instant::now();
+
+        loop {
+            match self.trans_init(flags) {
+                Err(Error::HandleLock) if start.elapsed() < timeout => thread::sleep(POLL_INTERVAL),
+                ret => return ret,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn test_lock_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.lck");
+        assert_eq!(lock_status(&path).unwrap(), LockStatus::Unlocked);
+
+        let file = File::create(&path).unwrap();
+        assert_eq!(
+            lock_status(&path).unwrap(),
+            LockStatus::Locked {
+                pid: Some(std::process::id())
+            }
+        );
+
+        drop(file);
+        match lock_status(&path).unwrap() {
+            LockStatus::Stale | LockStatus::Locked { pid: None } => (),
+            status => panic!("unexpected status: {:?}", status),
+        }
+
+        // the lock file open at another path, like from another mount namespace
+        let link = dir.path().join("link.lck");
+        fs::hard_link(&path, &link).unwrap();
+        let file = File::open(&link).unwrap();
+        assert_eq!(
+            lock_status(&path).unwrap(),
+            LockStatus::Locked {
+                pid: Some(std::process::id())
+            }
+        );
+        drop(file);
+    }
+
+    #[test]
+    fn test_wait_for_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.lck");
+
+        let file = File::create(&path).unwrap();
+        let status = wait_for_lock(&path, Duration::from_millis(200)).unwrap();
+        assert!(matches!(status, LockStatus::Locked { .. }));
+
+        let release = {
+            let path = path.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(200));
+                // remove the file first so the lock never looks stale
+                fs::remove_file(path).unwrap();
+                drop(file);
+            })
+        };
+        let status = wait_for_lock(&path, Duration::from_secs(10)).unwrap();
+        release.join().unwrap();
+        assert_eq!(status, LockStatus::Unlocked);
+    }
+}
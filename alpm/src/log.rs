@@ -0,0 +1,428 @@
+use crate::utils::days_from_civil;
+use crate::Alpm;
+
+use std::fs;
+use std::io;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LogEvent {
+    Command(String),
+    TransactionStarted,
+    TransactionCompleted,
+    TransactionFailed,
+    Installed {
+        name: String,
+        version: String,
+    },
+    Reinstalled {
+        name: String,
+        version: String,
+    },
+    Upgraded {
+        name: String,
+        old_version: String,
+        new_version: String,
+    },
+    Downgraded {
+        name: String,
+        old_version: String,
+        new_version: String,
+    },
+    Removed {
+        name: String,
+        version: String,
+    },
+    Hook(String),
+    Warning(String),
+    Error(String),
+    Scriptlet(String),
+    Other(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LogEntry {
+    /// Seconds since the epoch. Timestamps from logs written before
+    /// pacman 5.1 carry no UTC offset and are read as UTC.
+    pub time: i64,
+    pub source: String,
+    pub event: LogEvent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LogTransaction {
+    pub command: Option<String>,
+    pub start: i64,
+    pub end: Option<i64>,
+    pub completed: bool,
+    pub entries: Vec<LogEntry>,
+}
+
+impl LogTransaction {
+    pub fn changes(&self) -> impl Iterator<Item = &LogEvent> {
+        self.entries.iter().map(|e| &e.event).filter(|e| {
+            matches!(
+                e,
+                LogEvent::Installed { .. }
+                    | LogEvent::Reinstalled { .. }
+                    | LogEvent::Upgraded { .. }
+                    | LogEvent::Downgraded { .. }
+                    | LogEvent::Removed { .. }
+            )
+        })
+    }
+}
+
+fn num(s: &str, range: std::ops::RangeInclusive<i64>) -> Option<i64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok().filter(|n| range.contains(n))
+}
+
+// 2019-03-01T12:34:56+0000 or the older 2019-03-01 12:34
+fn parse_time(s: &str) -> Option<i64> {
+    let (date, time) = if s.len() >= 11 && (&s[10..11] == "T" || &s[10..11] == " ") {
+        (&s[..10], &s[11..])
+    } else {
+        return None;
+    };
+
+    let mut date = date.split('-');
+    let y = num(date.next()?, 0..=9999)?;
+    let m = num(date.next()?, 1..=12)?;
+    let d = num(date.next()?, 1..=31)?;
+
+    let (time, offset) = match time.find(&['+', '-'][..]) {
+        Some(i) => {
+            let (time, offset) = time.split_at(i);
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let offset = &offset[1..];
+            if offset.len() != 4 {
+                return None;
+            }
+            let hours = num(&offset[..2], 0..=23)?;
+            let minutes = num(&offset[2..], 0..=59)?;
+            (time, sign * (hours * 3600 + minutes * 60))
+        }
+        None => (time, 0),
+    };
+
+    let mut time = time.split(':');
+    let hh = num(time.next()?, 0..=23)?;
+    let mm = num(time.next()?, 0..=59)?;
+    let ss = match time.next() {
+        Some(ss) => num(ss, 0..=60)?,
+        None => 0,
+    };
+    if time.next().is_some() {
+        return None;
+    }
+
+    Some(days_from_civil(y, m, d) * 86400 + hh * 3600 + mm * 60 + ss - offset)
+}
+
+fn parse_pkg(s: &str) -> Option<(String, &str)> {
+    let start = s.find(" (")?;
+    let versions = s[start + 2..].strip_suffix(')')?;
+    Some((s[..start].to_string(), versions))
+}
+
+fn parse_event(source: &str, msg: &str) -> LogEvent {
+    if source == "ALPM-SCRIPTLET" {
+        return LogEvent::Scriptlet(msg.to_string());
+    }
+
+    let version = |s: &str| parse_pkg(s).map(|(name, v)| (name, v.to_string()));
+    let upgrade = |s: &str| -> Option<(String, String, String)> {
+        let (name, versions) = parse_pkg(s)?;
+        let mut split = versions.splitn(2, " -> ");
+        let old = split.next()?.to_string();
+        let new = split.next()?.to_string();
+        Some((name, old, new))
+    };
+
+    let event = if let Some(m) = msg.strip_prefix("Running '").filter(|_| source == "PACMAN") {
+        m.strip_suffix('\'')
+            .map(|m| LogEvent::Command(m.to_string()))
+    } else if let Some(m) = msg.strip_prefix("installed ") {
+        version(m).map(|(name, version)| LogEvent::Installed { name, version })
+    } else if let Some(m) = msg.strip_prefix("reinstalled ") {
+        version(m).map(|(name, version)| LogEvent::Reinstalled { name, version })
+    } else if let Some(m) = msg.strip_prefix("removed ") {
+        version(m).map(|(name, version)| LogEvent::Removed { name, version })
+    } else if let Some(m) = msg.strip_prefix("upgraded ") {
+        upgrade(m).map(|(name, old_version, new_version)| LogEvent::Upgraded {
+            name,
+            old_version,
+            new_version,
+        })
+    } else if let Some(m) = msg.strip_prefix("downgraded ") {
+        upgrade(m).map(|(name, old_version, new_version)| LogEvent::Downgraded {
+            name,
+            old_version,
+            new_version,
+        })
+    } else if let Some(m) = msg.strip_prefix("running '") {
+        m.strip_suffix("'...")
+            .map(|m| LogEvent::Hook(m.to_string()))
+    } else if let Some(m) = msg.strip_prefix("warning: ") {
+        Some(LogEvent::Warning(m.to_string()))
+    } else if let Some(m) = msg.strip_prefix("error: ") {
+        Some(LogEvent::Error(m.to_string()))
+    } else {
+        match msg {
+            "transaction started" => Some(LogEvent::TransactionStarted),
+            "transaction completed" => Some(LogEvent::TransactionCompleted),
+            "transaction failed" | "transaction interrupted" => Some(LogEvent::TransactionFailed),
+            _ => None,
+        }
+    };
+
+    event.unwrap_or_else(|| LogEvent::Other(msg.to_string()))
+}
+
+fn parse_line(line: &str) -> Option<LogEntry> {
+    let rest = line.strip_prefix('[')?;
+    let end = rest.find(']')?;
+    let time = parse_time(&rest[..end])?;
+    let rest = rest[end + 1..].trim_start();
+
+    let (source, msg) = match rest.strip_prefix('[') {
+        Some(rest) => {
+            let end = rest.find(']')?;
+            let msg = &rest[end + 1..];
+            (&rest[..end], msg.strip_prefix(' ').unwrap_or(msg))
+        }
+        None => ("", rest),
+    };
+
+    Some(LogEntry {
+        time,
+        source: source.to_string(),
+        event: parse_event(source, msg.trim_end()),
+    })
+}
+
+// add a line that is not an entry, such as multi-line scriptlet output, to
+// the message of the entry before it
+fn continue_entry(entry: &mut LogEntry, line: &str) {
+    match entry.event {
+        LogEvent::Scriptlet(ref mut msg)
+        | LogEvent::Warning(ref mut msg)
+        | LogEvent::Error(ref mut msg)
+        | LogEvent::Other(ref mut msg) => {
+            msg.push('\n');
+            msg.push_str(line.trim_end());
+        }
+        _ => (),
+    }
+}
+
+/// Parse the entries of a pacman log. Lines that are not an entry, such as
+/// multi-line scriptlet output, continue the message of the entry before
+/// them. They are dropped if that entry has no free-form message.
+pub fn parse_log(s: impl AsRef<str>) -> Vec<LogEntry> {
+    let mut entries: Vec<LogEntry> = Vec::new();
+
+    for line in s.as_ref().lines() {
+        match parse_line(line) {
+            Some(entry) => entries.push(entry),
+            None => {
+                if let Some(entry) = entries.last_mut() {
+                    continue_entry(entry, line);
+                }
+            }
+        }
+    }
+
+    entries
+}
+
+/// Group log entries into transactions. Entries logged outside of a
+/// transaction are dropped, except the command that started it. A
+/// transaction that is still open when the next command or transaction
+/// starts was interrupted, and is not completed.
+pub fn log_transactions(entries: &[LogEntry]) -> Vec<LogTransaction> {
+    let mut transactions = Vec::new();
+    let mut command = None;
+    let mut current: Option<LogTransaction> = None;
+
+    for entry in entries {
+        match entry.event {
+            LogEvent::Command(ref cmd) => {
+                transactions.extend(current.take());
+                command = Some(cmd.clone());
+            }
+            LogEvent::TransactionStarted => {
+                transactions.extend(current.take());
+                current = Some(LogTransaction {
+                    command: command.take(),
+                    start: entry.time,
+                    end: None,
+                    completed: false,
+                    entries: Vec::new(),
+                });
+            }
+            LogEvent::TransactionCompleted | LogEvent::TransactionFailed => {
+                if let Some(mut trans) = current.take() {
+                    trans.end = Some(entry.time);
+                    trans.completed = entry.event == LogEvent::TransactionCompleted;
+                    transactions.push(trans);
+                }
+            }
+            _ => {
+                if let Some(ref mut trans) = current {
+                    trans.entries.push(entry.clone());
+                }
+            }
+        }
+    }
+
+    transactions.extend(current);
+    transactions
+}
+
+impl Alpm {
+    pub fn read_log(&self) -> io::Result<Vec<LogEntry>> {
+        let path = self
+            .logfile()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no logfile set"))?;
+        let data = fs::read(path)?;
+        Ok(parse_log(String::from_utf8_lossy(&data)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "\
+[2019-05-05 14:02] [PACMAN] Running 'pacman -S vifm'
+[2019-05-05 14:02] [ALPM] transaction started
+[2019-05-05 14:02] [ALPM] installed vifm (0.10-1)
+[2019-05-05 14:02] [ALPM] transaction completed
+[2019-06-11T10:15:01+0200] [PACMAN] Running 'pacman -Syu'
+[2019-06-11T10:15:01+0200] [PACMAN] synchronizing package lists
+[2019-06-11T10:15:09+0200] [ALPM] transaction started
+[2019-06-11T10:15:10+0200] [ALPM] upgraded linux (5.1.7.arch1-1 -> 5.1.8.arch1-1)
+[2019-06-11T10:15:10+0200] [ALPM] downgraded vifm (0.10-1 -> 0.9.1-2)
+[2019-06-11T10:15:10+0200] [ALPM] removed pacman-contrib (1.1.0-1)
+[2019-06-11T10:15:10+0200] [ALPM] reinstalled bash (5.0.007-1)
+[2019-06-11T10:15:10+0200] [ALPM] warning: /etc/pacman.conf installed as /etc/pacman.conf.pacnew
+[2019-06-11T10:15:11+0200] [ALPM] running '90-mkinitcpio-install.hook'...
+[2019-06-11T10:15:11+0200] [ALPM-SCRIPTLET] ==> Building image from preset: /etc/mkinitcpio.d/linux.preset: 'default'
+[2019-06-11T10:15:20+0200] [ALPM] transaction completed
+[2019-06-12T08:00:00+0000] [ALPM] transaction started
+[2019-06-12T08:00:00+0000] [ALPM] error: could not extract usr/bin/foo
+";
+
+    #[test]
+    fn test_parse_log() {
+        let entries = parse_log(LOG);
+        assert_eq!(entries.len(), 17);
+
+        assert_eq!(entries[0].time, 1557064920);
+        assert_eq!(entries[0].source, "PACMAN");
+        assert_eq!(entries[0].event, LogEvent::Command("pacman -S vifm".into()));
+
+        assert_eq!(entries[4].time, 1560240901);
+        assert_eq!(
+            entries[7].event,
+            LogEvent::Upgraded {
+                name: "linux".into(),
+                old_version: "5.1.7.arch1-1".into(),
+                new_version: "5.1.8.arch1-1".into(),
+            }
+        );
+        assert_eq!(
+            entries[11].event,
+            LogEvent::Warning("/etc/pacman.conf installed as /etc/pacman.conf.pacnew".into())
+        );
+        assert_eq!(
+            entries[12].event,
+            LogEvent::Hook("90-mkinitcpio-install.hook".into())
+        );
+        assert_eq!(entries[13].source, "ALPM-SCRIPTLET");
+        assert_eq!(
+            entries[5].event,
+            LogEvent::Other("synchronizing package lists".into())
+        );
+    }
+
+    #[test]
+    fn test_parse_log_continued() {
+        let log = "\
+before the first entry
+[2019-05-05 14:02] [ALPM-SCRIPTLET] ==> Building image
+  -> Running build hook: [base]
+
+[2019-13-05 14:02] [ALPM] bad month
+[2019-05-05 14:02] [ALPM] installed foo (1.0-1)
+dropped
+[2019-05-05 14:03] [ALPM] ok
+";
+        let entries = parse_log(log);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries[0].event,
+            LogEvent::Scriptlet(
+                "==> Building image\n  -> Running build hook: [base]\n\n\
+                 [2019-13-05 14:02] [ALPM] bad month"
+                    .into()
+            )
+        );
+        assert_eq!(
+            entries[1].event,
+            LogEvent::Installed {
+                name: "foo".into(),
+                version: "1.0-1".into(),
+            }
+        );
+        assert_eq!(entries[2].time, 1557064980);
+        assert_eq!(entries[2].event, LogEvent::Other("ok".into()));
+    }
+
+    #[test]
+    fn test_log_transactions() {
+        let entries = parse_log(LOG);
+        let trans = log_transactions(&entries);
+        assert_eq!(trans.len(), 3);
+
+        assert_eq!(trans[0].command.as_deref(), Some("pacman -S vifm"));
+        assert!(trans[0].completed);
+        assert_eq!(trans[0].changes().count(), 1);
+
+        assert_eq!(trans[1].command.as_deref(), Some("pacman -Syu"));
+        assert_eq!(trans[1].end, Some(1560240920));
+        assert_eq!(trans[1].changes().count(), 4);
+        assert_eq!(trans[1].entries.len(), 7);
+
+        assert_eq!(trans[2].command, None);
+        assert!(!trans[2].completed);
+        assert_eq!(trans[2].end, None);
+    }
+
+    #[test]
+    fn test_log_transactions_interrupted() {
+        let log = "\
+[2019-06-12T08:00:00+0000] [PACMAN] Running 'pacman -S foo'
+[2019-06-12T08:00:00+0000] [ALPM] transaction started
+[2019-06-12T08:00:00+0000] [ALPM] installed foo (1.0-1)
+[2019-06-12T09:00:00+0000] [PACMAN] Running 'pacman -S bar'
+[2019-06-12T09:00:00+0000] [ALPM] transaction started
+[2019-06-12T09:00:00+0000] [ALPM] installed bar (1.0-1)
+[2019-06-12T09:00:00+0000] [ALPM] transaction completed
+";
+        let trans = log_transactions(&parse_log(log));
+        assert_eq!(trans.len(), 2);
+
+        assert_eq!(trans[0].command.as_deref(), Some("pacman -S foo"));
+        assert!(!trans[0].completed);
+        assert_eq!(trans[0].end, None);
+        assert_eq!(trans[0].entries.len(), 1);
+
+        assert_eq!(trans[1].command.as_deref(), Some("pacman -S bar"));
+        assert!(trans[1].completed);
+        assert_eq!(trans[1].changes().count(), 1);
+    }
+}
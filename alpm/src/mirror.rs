@@ -0,0 +1,411 @@
+use crate::pkginfo::{parse_kv, parse_num, write_kv};
+use crate::utils::parse_http_date;
+use crate::{DbMut, ParseError};
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MirrorProbe {
+    pub server: String,
+    /// Last-Modified time of the repo database.
+    pub last_sync: Option<i64>,
+    /// Seconds behind the most recently synced mirror.
+    pub lag: Option<i64>,
+    pub latency: Option<Duration>,
+    /// Bytes per second.
+    pub throughput: Option<u64>,
+    pub error: Option<String>,
+}
+
+impl MirrorProbe {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+
+    pub fn is_out_of_sync(&self, max_lag: i64) -> bool {
+        !matches!(self.lag, Some(lag) if lag <= max_lag)
+    }
+
+    /// Higher is better. Failed mirrors score 0.
+    pub fn score(&self) -> f64 {
+        match (self.latency, self.throughput) {
+            (Some(latency), Some(throughput)) if self.is_ok() => {
+                throughput as f64 / (1.0 + latency.as_secs_f64())
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MirrorRanking {
+    pub repo: String,
+    pub time: i64,
+    pub max_lag: i64,
+    /// Best mirror first.
+    pub mirrors: Vec<MirrorProbe>,
+}
+
+impl MirrorRanking {
+    pub fn servers(&self) -> impl Iterator<Item = &str> {
+        self.mirrors.iter().map(|m| m.server.as_str())
+    }
+
+    pub fn out_of_sync(&self) -> impl Iterator<Item = &MirrorProbe> {
+        let max_lag = self.max_lag;
+        self.mirrors
+            .iter()
+            .filter(move |m| m.is_ok() && m.is_out_of_sync(max_lag))
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &MirrorProbe> {
+        self.mirrors.iter().filter(|m| !m.is_ok())
+    }
+
+    pub fn parse(s: impl AsRef<str>) -> Result<MirrorRanking, ParseError> {
+        let mut ranking = MirrorRanking::default();
+
+        for kv in parse_kv(s.as_ref()) {
+            let (line, key, value) = kv?;
+
+            if key == "server" {
+                ranking.mirrors.push(MirrorProbe {
+                    server: value.to_string(),
+                    ..MirrorProbe::default()
+                });
+                continue;
+            }
+
+            match (key, ranking.mirrors.last_mut()) {
+                ("repo", None) => ranking.repo = value.to_string(),
+                ("time", None) => ranking.time = parse_num(line, key, value)?,
+                ("maxlag", None) => ranking.max_lag = parse_num(line, key, value)?,
+                ("lastsync", Some(m)) => m.last_sync = Some(parse_num(line, key, value)?),
+                ("lag", Some(m)) => m.lag = Some(parse_num(line, key, value)?),
+                ("latency", Some(m)) => {
+                    m.latency = Some(Duration::from_millis(parse_num(line, key, value)?))
+                }
+                ("throughput", Some(m)) => m.throughput = Some(parse_num(line, key, value)?),
+                ("error", Some(m)) => m.error = Some(value.to_string()),
+                _ => return Err(ParseError::new(line, format!("unexpected key: {}", key))),
+            }
+        }
+
+        Ok(ranking)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<MirrorRanking> {
+        Ok(MirrorRanking::parse(fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+}
+
+impl fmt::Display for MirrorRanking {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_kv(f, "repo", &self.repo)?;
+        write_kv(f, "time", &self.time.to_string())?;
+        write_kv(f, "maxlag", &self.max_lag.to_string())?;
+
+        for m in &self.mirrors {
+            write_kv(f, "server", &m.server)?;
+            if let Some(last_sync) = m.last_sync {
+                write_kv(f, "lastsync", &last_sync.to_string())?;
+            }
+            if let Some(lag) = m.lag {
+                write_kv(f, "lag", &lag.to_string())?;
+            }
+            if let Some(latency) = m.latency {
+                write_kv(f, "latency", &latency.as_millis().to_string())?;
+            }
+            if let Some(throughput) = m.throughput {
+                write_kv(f, "throughput", &throughput.to_string())?;
+            }
+            if let Some(ref error) = m.error {
+                write_kv(f, "error", error)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MirrorRanker {
+    timeout: Duration,
+    max_lag: i64,
+    test_file: Option<String>,
+}
+
+impl Default for MirrorRanker {
+    fn default() -> MirrorRanker {
+        MirrorRanker::new()
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+impl MirrorRanker {
+    pub fn new() -> MirrorRanker {
+        MirrorRanker {
+            timeout: Duration::from_secs(10),
+            max_lag: 3600,
+            test_file: None,
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> MirrorRanker {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Mirrors whose database is more than `max_lag` seconds older than the
+    /// newest one are out of sync and ranked after all others.
+    pub fn max_lag(mut self, max_lag: i64) -> MirrorRanker {
+        self.max_lag = max_lag;
+        self
+    }
+
+    /// A file, relative to the server, used to measure throughput instead of
+    /// the repo database.
+    pub fn test_file(mut self, test_file: impl Into<String>) -> MirrorRanker {
+        self.test_file = Some(test_file.into());
+        self
+    }
+
+    fn fetch(&self, url: &str) -> io::Result<(Option<i64>, Duration, u64, Duration)> {
+        let agent = ureq::AgentBuilder::new().timeout(self.timeout).build();
+
+        let start = Instant::now();
+        let res = agent
+            .get(url)
+            .call()
+            .map_err(|e| io::Error::new(io::ErrorKind::ConnectionAborted, e.to_string()))?;
+        let latency = start.elapsed();
+        let last_modified = res.header("Last-Modified").and_then(parse_http_date);
+
+        let mut buf = Vec::new();
+        res.into_reader().read_to_end(&mut buf)?;
+
+        Ok((last_modified, latency, buf.len() as u64, start.elapsed()))
+    }
+
+    /// Probe a single server. `lag` is left unset.
+    pub fn probe(&self, repo: &str, server: &str) -> MirrorProbe {
+        let base = server.trim_end_matches('/');
+        let mut probe = MirrorProbe {
+            server: server.to_string(),
+            ..MirrorProbe::default()
+        };
+
+        let db = match self.fetch(&format!("{}/{}.db", base, repo)) {
+            Ok(db) => db,
+            Err(e) => {
+                probe.error = Some(e.to_string());
+                return probe;
+            }
+        };
+        probe.last_sync = db.0;
+        probe.latency = Some(db.1);
+
+        let (size, elapsed) = match self.test_file {
+            Some(ref file) => match self.fetch(&format!("{}/{}", base, file)) {
+                Ok((_, _, size, elapsed)) => (size, elapsed),
+                Err(e) => {
+                    probe.error = Some(e.to_string());
+                    return probe;
+                }
+            },
+            None => (db.2, db.3),
+        };
+        probe.throughput = Some((size as f64 / elapsed.as_secs_f64().max(0.001)) as u64);
+
+        probe
+    }
+
+    /// Probe all `servers` in parallel and order them: in sync mirrors by
+    /// score, then out of sync mirrors, then mirrors that failed.
+    pub fn rank<S: AsRef<str> + Sync>(&self, repo: &str, servers: &[S]) -> MirrorRanking {
+        let mut mirrors = thread::scope(|s| {
+            let handles = servers
+                .iter()
+                .map(|server| s.spawn(move || self.probe(repo, server.as_ref())))
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        let newest = mirrors.iter().filter_map(|m| m.last_sync).max();
+        for m in &mut mirrors {
+            m.lag = newest.and_then(|newest| Some(newest - m.last_sync?));
+        }
+
+        let class = |m: &MirrorProbe| match m {
+            m if !m.is_ok() => 2,
+            m if m.is_out_of_sync(self.max_lag) => 1,
+            _ => 0,
+        };
+        mirrors.sort_by(|a, b| {
+            class(a)
+                .cmp(&class(b))
+                .then(b.score().partial_cmp(&a.score()).unwrap_or(Ordering::Equal))
+        });
+
+        MirrorRanking {
+            repo: repo.to_string(),
+            time: now(),
+            max_lag: self.max_lag,
+            mirrors,
+        }
+    }
+}
+
+impl<'a> DbMut<'a> {
+    /// Probe this database's servers and reorder them by their ranking.
+    pub fn rank_servers(&self, ranker: &MirrorRanker) -> crate::Result<MirrorRanking> {
+        let servers = self.servers().map(|s| s.to_string()).collect::<Vec<_>>();
+        let ranking = ranker.rank(self.name(), &servers);
+        self.apply_ranking(&ranking)?;
+        Ok(ranking)
+    }
+
+    /// Reorder the servers according to a previously saved ranking. Servers
+    /// that are not part of the ranking are kept after the ranked ones.
+    pub fn apply_ranking(&self, ranking: &MirrorRanking) -> crate::Result<()> {
+        let current = self.servers().map(|s| s.to_string()).collect::<Vec<_>>();
+        let ranked = ranking.servers().filter(|s| current.iter().any(|c| c == s));
+        let unranked = current
+            .iter()
+            .map(|s| s.as_str())
+            .filter(|s| !ranking.servers().any(|r| r == *s));
+        let servers = ranked.chain(unranked).collect::<Vec<_>>();
+        self.set_servers(servers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{data, serve_with};
+    use crate::{Alpm, SigLevel};
+
+    #[test]
+    fn test_parse_http_date() {
+        assert_eq!(
+            parse_http_date("Tue, 11 Jun 2019 08:15:01 GMT"),
+            Some(1560240901)
+        );
+        assert_eq!(parse_http_date("yesterday"), None);
+    }
+
+    #[test]
+    fn test_rank() {
+        let fresh = serve_with(vec![("/core/core.db", data(1000))], Some(1560240901));
+        let stale = serve_with(vec![("/core/core.db", data(1000))], Some(1560000000));
+        let missing = serve_with(vec![], None);
+
+        let servers = [
+            format!("{}/core", missing),
+            format!("{}/core", stale),
+            format!("{}/core/", fresh),
+        ];
+        let ranking = MirrorRanker::new()
+            .timeout(Duration::from_secs(2))
+            .rank("core", &servers);
+
+        assert_eq!(
+            ranking.servers().collect::<Vec<_>>(),
+            vec![&servers[2], &servers[1], &servers[0]]
+        );
+        let first = &ranking.mirrors[0];
+        assert_eq!(first.last_sync, Some(1560240901));
+        assert_eq!(first.lag, Some(0));
+        assert!(first.latency.is_some());
+        assert!(first.score() > 0.0);
+
+        let out_of_sync = ranking.out_of_sync().collect::<Vec<_>>();
+        assert_eq!(out_of_sync.len(), 1);
+        assert_eq!(out_of_sync[0].lag, Some(240901));
+        assert_eq!(ranking.failed().count(), 1);
+    }
+
+    #[test]
+    fn test_test_file() {
+        let server = serve_with(
+            vec![("/extra.db", data(10)), ("/speedtest", data(100_000))],
+            None,
+        );
+
+        let ranker = MirrorRanker::new().test_file("speedtest");
+        assert!(ranker.probe("extra", &server).throughput.is_some());
+        let ranker = MirrorRanker::new().test_file("missing");
+        assert!(!ranker.probe("extra", &server).is_ok());
+    }
+
+    #[test]
+    fn test_persist() {
+        let ranking = MirrorRanking {
+            repo: "core".into(),
+            time: 1560240901,
+            max_lag: 3600,
+            mirrors: vec![
+                MirrorProbe {
+                    server: "https://a.example.org/core/os/x86_64".into(),
+                    last_sync: Some(1560240901),
+                    lag: Some(0),
+                    latency: Some(Duration::from_millis(12)),
+                    throughput: Some(1_000_000),
+                    error: None,
+                },
+                MirrorProbe {
+                    server: "https://b.example.org/core/os/x86_64".into(),
+                    error: Some("HTTP 404".into()),
+                    ..MirrorProbe::default()
+                },
+            ],
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("core.ranking");
+        ranking.save(&path).unwrap();
+        assert_eq!(MirrorRanking::load(&path).unwrap(), ranking);
+
+        assert!(MirrorRanking::parse("lag = 1\n").is_err());
+    }
+
+    #[test]
+    fn test_apply_ranking() {
+        let mut handle = Alpm::new("/", "tests/db").unwrap();
+        let db = handle.register_syncdb_mut("foo", SigLevel::NONE).unwrap();
+        db.set_servers(vec!["a", "b", "c"]).unwrap();
+
+        let ranking = MirrorRanking {
+            mirrors: ["c", "gone", "a"]
+                .iter()
+                .map(|s| MirrorProbe {
+                    server: s.to_string(),
+                    ..MirrorProbe::default()
+                })
+                .collect(),
+            ..MirrorRanking::default()
+        };
+        db.apply_ranking(&ranking).unwrap();
+
+        assert_eq!(db.servers().collect::<Vec<_>>(), vec!["c", "a", "b"]);
+    }
+}
@@ -0,0 +1,307 @@
+use crate::pkginfo::parse_num;
+use crate::ParseError;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Ord, PartialOrd, Hash)]
+pub enum MTreeType {
+    File,
+    Dir,
+    Link,
+    Block,
+    Char,
+    Fifo,
+    Socket,
+}
+
+impl MTreeType {
+    fn keyword(self) -> &'static str {
+        match self {
+            MTreeType::File => "file",
+            MTreeType::Dir => "dir",
+            MTreeType::Link => "link",
+            MTreeType::Block => "block",
+            MTreeType::Char => "char",
+            MTreeType::Fifo => "fifo",
+            MTreeType::Socket => "socket",
+        }
+    }
+
+    fn from_keyword(s: &str) -> Option<MTreeType> {
+        let t = match s {
+            "file" => MTreeType::File,
+            "dir" => MTreeType::Dir,
+            "link" => MTreeType::Link,
+            "block" => MTreeType::Block,
+            "char" => MTreeType::Char,
+            "fifo" => MTreeType::Fifo,
+            "socket" => MTreeType::Socket,
+            _ => return None,
+        };
+
+        Some(t)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MTreeEntry {
+    pub path: String,
+    pub file_type: MTreeType,
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u32,
+    pub size: u64,
+    pub time: i64,
+    pub time_nsec: u32,
+    pub link: Option<String>,
+    pub md5digest: Option<String>,
+    pub sha256digest: Option<String>,
+}
+
+fn unescape(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 4 <= bytes.len() {
+            let oct = &bytes[i + 1..i + 4];
+            if oct.iter().all(|b| (b'0'..=b'7').contains(b)) {
+                let n = oct.iter().fold(0u32, |n, b| n * 8 + u32::from(b - b'0'));
+                out.push(n as u8);
+                i += 4;
+                continue;
+            }
+        }
+
+        if bytes[i] == b'\\' && i + 1 < bytes.len() {
+            out.push(bytes[i + 1]);
+            i += 2;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+
+    for &b in s.as_bytes() {
+        if b.is_ascii_graphic() && b != b'\\' && b != b'#' && b != b'=' {
+            out.push(b as char);
+        } else {
+            write!(out, "\\{:03o}", b).unwrap();
+        }
+    }
+
+    out
+}
+
+fn parse_time(line: usize, value: &str) -> Result<(i64, u32), ParseError> {
+    let mut split = value.splitn(2, '.');
+    let secs = parse_num(line, "time", split.next().unwrap())?;
+    let nsec = match split.next() {
+        Some(nsec) if nsec.len() <= 9 => {
+            let nsec = format!("{:0<9}", nsec);
+            parse_num(line, "time", &nsec)?
+        }
+        Some(_) => return Err(ParseError::new(line, format!("invalid time: {}", value))),
+        None => 0,
+    };
+
+    Ok((secs, nsec))
+}
+
+fn entry<'a>(
+    line: usize,
+    path: &str,
+    keywords: &HashMap<&'a str, &'a str>,
+) -> Result<MTreeEntry, ParseError> {
+    let mut entry = MTreeEntry {
+        path: unescape(path.trim_start_matches("./")),
+        file_type: MTreeType::File,
+        uid: 0,
+        gid: 0,
+        mode: 0,
+        size: 0,
+        time: 0,
+        time_nsec: 0,
+        link: None,
+        md5digest: None,
+        sha256digest: None,
+    };
+
+    for (&key, &value) in keywords {
+        match key {
+            "type" => {
+                entry.file_type = MTreeType::from_keyword(value)
+                    .ok_or_else(|| ParseError::new(line, format!("invalid type: {}", value)))?
+            }
+            "uid" => entry.uid = parse_num(line, key, value)?,
+            "gid" => entry.gid = parse_num(line, key, value)?,
+            "mode" => {
+                entry.mode = u32::from_str_radix(value, 8)
+                    .map_err(|_| ParseError::new(line, format!("invalid mode: {}", value)))?
+            }
+            "size" => entry.size = parse_num(line, key, value)?,
+            "time" => {
+                let (time, nsec) = parse_time(line, value)?;
+                entry.time = time;
+                entry.time_nsec = nsec;
+            }
+            "link" => entry.link = Some(unescape(value)),
+            "md5" | "md5digest" => entry.md5digest = Some(value.to_string()),
+            "sha256" | "sha256digest" => entry.sha256digest = Some(value.to_string()),
+            _ => (),
+        }
+    }
+
+    Ok(entry)
+}
+
+pub fn parse_mtree(s: impl AsRef<str>) -> Result<Vec<MTreeEntry>, ParseError> {
+    let mut defaults = HashMap::new();
+    let mut entries = Vec::new();
+
+    for (n, line) in s.as_ref().lines().enumerate() {
+        let n = n + 1;
+        let line = line.trim();
+        let mut words = line.split_whitespace();
+
+        let first = match words.next() {
+            Some(first) if !first.starts_with('#') => first,
+            _ => continue,
+        };
+
+        let mut keywords = Vec::new();
+        for word in words {
+            let mut split = word.splitn(2, '=');
+            let key = split.next().unwrap();
+            keywords.push((key, split.next().unwrap_or("")));
+        }
+
+        match first {
+            "/set" => defaults.extend(keywords),
+            "/unset" => {
+                for (key, _) in keywords {
+                    if key == "all" {
+                        defaults.clear();
+                    } else {
+                        defaults.remove(key);
+                    }
+                }
+            }
+            path if path.starts_with('/') => {
+                return Err(ParseError::new(n, format!("unknown command: {}", path)));
+            }
+            "." => (),
+            path => {
+                let mut all = defaults.clone();
+                all.extend(keywords);
+                entries.push(entry(n, path, &all)?);
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+pub fn write_mtree(entries: &[MTreeEntry]) -> String {
+    let mut out = String::from("#mtree\n/set type=file uid=0 gid=0 mode=644\n");
+
+    for entry in entries {
+        write!(out, "./{} time={}", escape(&entry.path), entry.time).unwrap();
+
+        if entry.time_nsec == 0 {
+            out.push_str(".0");
+        } else {
+            write!(out, ".{:09}", entry.time_nsec).unwrap();
+        }
+
+        if entry.uid != 0 {
+            write!(out, " uid={}", entry.uid).unwrap();
+        }
+        if entry.gid != 0 {
+            write!(out, " gid={}", entry.gid).unwrap();
+        }
+        if entry.mode != 0o644 {
+            write!(out, " mode={:o}", entry.mode).unwrap();
+        }
+        if entry.file_type != MTreeType::File {
+            write!(out, " type={}", entry.file_type.keyword()).unwrap();
+        } else {
+            write!(out, " size={}", entry.size).unwrap();
+        }
+        if let Some(ref link) = entry.link {
+            write!(out, " link={}", escape(link)).unwrap();
+        }
+        if let Some(ref md5) = entry.md5digest {
+            write!(out, " md5digest={}", md5).unwrap();
+        }
+        if let Some(ref sha256) = entry.sha256digest {
+            write!(out, " sha256digest={}", sha256).unwrap();
+        }
+
+        out.push('\n');
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MTREE: &str = r"#mtree
+/set type=file uid=0 gid=0 mode=755
+./.BUILDINFO time=1551404516.0 mode=644 size=5161 md5digest=e4bc7d7a38272a8e50cada55e9d851a3 sha256digest=531a25b8d31b007f6fd21c9fdd20fed86c23bf5feb19c3862fb81505fe1b6e9f
+/set mode=644
+./etc time=1551404516.0 mode=755 type=dir
+./etc/pacman.conf time=1551404516.0 size=2902 md5digest=dae74459bac48c07ea12fb22da5a83b7
+./usr/lib/ONDA-V80\040PLUS.txt time=1558064864.0 size=989
+./usr/lib/libargon2.so time=1515422786.236328148 mode=777 type=link link=libargon2.so.1
+";
+
+    #[test]
+    fn test_parse_mtree() {
+        let entries = parse_mtree(MTREE).unwrap();
+        assert_eq!(entries.len(), 5);
+
+        assert_eq!(entries[0].path, ".BUILDINFO");
+        assert_eq!(entries[0].mode, 0o644);
+        assert_eq!(entries[0].size, 5161);
+
+        assert_eq!(entries[1].path, "etc");
+        assert_eq!(entries[1].file_type, MTreeType::Dir);
+        assert_eq!(entries[1].mode, 0o755);
+
+        assert_eq!(entries[2].file_type, MTreeType::File);
+        assert_eq!(entries[2].mode, 0o644);
+        assert_eq!(entries[2].time, 1551404516);
+        assert!(entries[2].sha256digest.is_none());
+
+        assert_eq!(entries[3].path, "usr/lib/ONDA-V80 PLUS.txt");
+
+        assert_eq!(entries[4].file_type, MTreeType::Link);
+        assert_eq!(entries[4].link.as_ref().unwrap(), "libargon2.so.1");
+        assert_eq!(entries[4].time_nsec, 236328148);
+    }
+
+    #[test]
+    fn test_write_mtree() {
+        let entries = parse_mtree(MTREE).unwrap();
+        let written = write_mtree(&entries);
+        assert_eq!(parse_mtree(&written).unwrap(), entries);
+        assert!(written.contains("./usr/lib/ONDA-V80\\040PLUS.txt "));
+    }
+
+    #[test]
+    fn test_parse_mtree_err() {
+        assert_eq!(parse_mtree("./a mode=999").unwrap_err().line(), Some(1));
+        assert_eq!(parse_mtree("#mtree\n/foo").unwrap_err().line(), Some(2));
+    }
+}
@@ -0,0 +1,1178 @@
+use crate::signing::OwnedSigResult;
+use crate::{Db, PackageFrom, Pkg, SigList, SigStatus, SigValidity};
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ed25519_dalek::{Signer, Verifier};
+use num_bigint::BigUint;
+use sha1::Sha1;
+use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
+
+const ED25519_OID: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0xda, 0x47, 0x0f, 0x01];
+// the subpackets that are parsed or only state preferences, a signature with
+// any other subpacket marked critical is rejected
+const KNOWN_SUBPACKETS: &[u8] = &[2, 3, 9, 11, 16, 21, 22, 23, 25, 27, 30, 32, 33];
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+fn unhex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .filter_map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn write_mpi(out: &mut Vec<u8>, mpi: &[u8]) {
+    let start = mpi.iter().position(|&b| b != 0).unwrap_or(mpi.len());
+    let mpi = &mpi[start..];
+    let bits = match mpi.first() {
+        Some(b) => (mpi.len() - 1) * 8 + 8 - b.leading_zeros() as usize,
+        None => 0,
+    };
+    out.extend([(bits >> 8) as u8, bits as u8]);
+    out.extend(mpi);
+}
+
+// a new format packet
+fn packet(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![0xc0 | tag];
+    match body.len() {
+        len @ 0..=191 => out.push(len as u8),
+        len @ 192..=8383 => out.extend([((len - 192) >> 8) as u8 + 192, (len - 192) as u8]),
+        len => {
+            out.push(255);
+            out.extend((len as u32).to_be_bytes());
+        }
+    }
+    out.extend(body);
+    out
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < n {
+            return Err(invalid("truncated packet"));
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<usize> {
+        let b = self.take(2)?;
+        Ok((b[0] as usize) << 8 | b[1] as usize)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn mpi(&mut self) -> io::Result<&'a [u8]> {
+        let bits = self.u16()?;
+        self.take(bits.div_ceil(8))
+    }
+}
+
+fn packets(data: &[u8]) -> io::Result<Vec<(u8, &[u8])>> {
+    let mut r = Reader { data };
+    let mut packets = Vec::new();
+
+    while !r.data.is_empty() {
+        let header = r.u8()?;
+        if header & 0x80 == 0 {
+            return Err(invalid("invalid packet header"));
+        }
+
+        let (tag, len) = if header & 0x40 != 0 {
+            let len = match r.u8()? as usize {
+                len @ 0..=191 => len,
+                len @ 192..=223 => ((len - 192) << 8) + r.u8()? as usize + 192,
+                255 => r.u32()? as usize,
+                _ => return Err(invalid("partial body lengths are not supported")),
+            };
+            (header & 0x3f, len)
+        } else {
+            let len = match header & 0x03 {
+                0 => r.u8()? as usize,
+                1 => r.u16()?,
+                2 => r.u32()? as usize,
+                _ => r.data.len(),
+            };
+            ((header >> 2) & 0x0f, len)
+        };
+
+        packets.push((tag, r.take(len)?));
+    }
+
+    Ok(packets)
+}
+
+/// Decode ASCII armor, or return the data as is if it is not armored.
+fn dearmor(data: &[u8]) -> io::Result<Vec<u8>> {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) if text.trim_start().starts_with("-----BEGIN PGP") => text,
+        _ => return Ok(data.to_vec()),
+    };
+
+    let mut out = Vec::new();
+    let mut lines = text.lines().map(|l| l.trim());
+
+    while let Some(line) = lines.next() {
+        if !line.starts_with("-----BEGIN PGP") {
+            continue;
+        }
+
+        // armor headers end at the first empty line
+        for line in &mut lines {
+            if line.is_empty() {
+                break;
+            }
+        }
+
+        let mut b64 = String::new();
+        for line in &mut lines {
+            if line.starts_with('=') || line.starts_with("-----END") {
+                break;
+            }
+            b64.push_str(line);
+        }
+        out.extend(base64::decode(&b64).map_err(|e| invalid(e.to_string()))?);
+    }
+
+    Ok(out)
+}
+
+#[derive(Debug, Clone)]
+enum KeyMaterial {
+    Rsa { n: BigUint, e: BigUint },
+    Ed25519(ed25519_dalek::PublicKey),
+    Unsupported,
+}
+
+#[derive(Debug, Clone)]
+struct PublicKey {
+    // the public key packet, hashed by key signatures
+    body: Vec<u8>,
+    fingerprint: String,
+    keyid: String,
+    created: i64,
+    algo: u8,
+    bits: u32,
+    material: KeyMaterial,
+}
+
+impl PublicKey {
+    fn parse(body: &[u8]) -> io::Result<PublicKey> {
+        PublicKey::read(&mut Reader { data: body })
+    }
+
+    // secret key packets start with the public key, so only consume that
+    fn read(r: &mut Reader) -> io::Result<PublicKey> {
+        let body = r.data;
+        if r.u8()? != 4 {
+            return Err(invalid("only version 4 keys are supported"));
+        }
+        let created = r.u32()? as i64;
+        let algo = r.u8()?;
+
+        let (material, bits) = match algo {
+            1..=3 => {
+                let n = r.mpi()?;
+                let e = r.mpi()?;
+                let n = BigUint::from_bytes_be(n);
+                let bits = n.bits() as u32;
+                let e = BigUint::from_bytes_be(e);
+                (KeyMaterial::Rsa { n, e }, bits)
+            }
+            22 => {
+                let oid_len = r.u8()? as usize;
+                let oid = r.take(oid_len)?;
+                let point = r.mpi()?;
+                match (oid, point.split_first()) {
+                    (ED25519_OID, Some((0x40, point))) => {
+                        let key = ed25519_dalek::PublicKey::from_bytes(point)
+                            .map_err(|e| invalid(e.to_string()))?;
+                        (KeyMaterial::Ed25519(key), 255)
+                    }
+                    _ => (KeyMaterial::Unsupported, 0),
+                }
+            }
+            _ => (KeyMaterial::Unsupported, 0),
+        };
+        if let KeyMaterial::Unsupported = material {
+            r.take(r.data.len())?;
+        }
+        let body = &body[..body.len() - r.data.len()];
+
+        let mut hasher = Sha1::new();
+        hasher.update([0x99, (body.len() >> 8) as u8, body.len() as u8]);
+        hasher.update(body);
+        let fingerprint = hex(&hasher.finalize());
+        let keyid = fingerprint[fingerprint.len() - 16..].to_string();
+
+        Ok(PublicKey {
+            body: body.to_vec(),
+            fingerprint,
+            keyid,
+            created,
+            algo,
+            bits,
+            material,
+        })
+    }
+}
+
+enum Hasher {
+    Sha1(Sha1),
+    Sha224(Sha224),
+    Sha256(Sha256),
+    Sha384(Sha384),
+    Sha512(Sha512),
+}
+
+impl Hasher {
+    fn new(algo: u8) -> Option<Hasher> {
+        match algo {
+            2 => Some(Hasher::Sha1(Sha1::new())),
+            8 => Some(Hasher::Sha256(Sha256::new())),
+            9 => Some(Hasher::Sha384(Sha384::new())),
+            10 => Some(Hasher::Sha512(Sha512::new())),
+            11 => Some(Hasher::Sha224(Sha224::new())),
+            _ => None,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha1(h) => h.update(data),
+            Hasher::Sha224(h) => h.update(data),
+            Hasher::Sha256(h) => h.update(data),
+            Hasher::Sha384(h) => h.update(data),
+            Hasher::Sha512(h) => h.update(data),
+        }
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha1(h) => h.finalize().to_vec(),
+            Hasher::Sha224(h) => h.finalize().to_vec(),
+            Hasher::Sha256(h) => h.finalize().to_vec(),
+            Hasher::Sha384(h) => h.finalize().to_vec(),
+            Hasher::Sha512(h) => h.finalize().to_vec(),
+        }
+    }
+
+    // the DER encoded DigestInfo prefix used by PKCS#1 v1.5
+    fn digest_info(&self) -> &'static [u8] {
+        match self {
+            Hasher::Sha1(_) => &[
+                0x30, 0x21, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00, 0x04,
+                0x14,
+            ],
+            Hasher::Sha224(_) => &[
+                0x30, 0x2d, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+                0x04, 0x05, 0x00, 0x04, 0x1c,
+            ],
+            Hasher::Sha256(_) => &[
+                0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+                0x01, 0x05, 0x00, 0x04, 0x20,
+            ],
+            Hasher::Sha384(_) => &[
+                0x30, 0x41, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+                0x02, 0x05, 0x00, 0x04, 0x30,
+            ],
+            Hasher::Sha512(_) => &[
+                0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+                0x03, 0x05, 0x00, 0x04, 0x40,
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Signature {
+    sigtype: u8,
+    algo: u8,
+    hash_algo: u8,
+    // version through the end of the hashed subpackets
+    hashed: Vec<u8>,
+    created: i64,
+    expires: Option<i64>,
+    key_expires: Option<i64>,
+    key_flags: Option<u8>,
+    issuer: Option<String>,
+    issuer_fingerprint: Option<String>,
+    // the primary key binding signature of a signing subkey
+    embedded: Option<Box<Signature>>,
+    left16: [u8; 2],
+    mpis: Vec<Vec<u8>>,
+}
+
+impl Signature {
+    fn parse(body: &[u8]) -> io::Result<Signature> {
+        let mut r = Reader { data: body };
+        if r.u8()? != 4 {
+            return Err(invalid("only version 4 signatures are supported"));
+        }
+        let sigtype = r.u8()?;
+        let algo = r.u8()?;
+        let hash_algo = r.u8()?;
+        let hashed_len = r.u16()?;
+        let hashed_area = r.take(hashed_len)?;
+        let hashed = body[..6 + hashed_len].to_vec();
+        let unhashed_len = r.u16()?;
+        let unhashed_area = r.take(unhashed_len)?;
+        let left = r.take(2)?;
+
+        let mut sig = Signature {
+            sigtype,
+            algo,
+            hash_algo,
+            hashed,
+            created: 0,
+            expires: None,
+            key_expires: None,
+            key_flags: None,
+            issuer: None,
+            issuer_fingerprint: None,
+            embedded: None,
+            left16: [left[0], left[1]],
+            mpis: Vec::new(),
+        };
+
+        while !r.data.is_empty() {
+            sig.mpis.push(r.mpi()?.to_vec());
+        }
+
+        for (area, hashed) in &[(hashed_area, true), (unhashed_area, false)] {
+            let mut r = Reader { data: area };
+            while !r.data.is_empty() {
+                let len = match r.u8()? as usize {
+                    len @ 0..=191 => len,
+                    len @ 192..=254 => ((len - 192) << 8) + r.u8()? as usize + 192,
+                    _ => r.u32()? as usize,
+                };
+                if len == 0 {
+                    return Err(invalid("invalid subpacket"));
+                }
+                let data = r.take(len)?;
+                let mut sub = Reader { data: &data[1..] };
+                if data[0] & 0x80 != 0 && !KNOWN_SUBPACKETS.contains(&(data[0] & 0x7f)) {
+                    return Err(invalid(format!(
+                        "unknown critical subpacket {}",
+                        data[0] & 0x7f
+                    )));
+                }
+
+                // the issuer and embedded signature are only used to find
+                // the key and are verified themselves, so they may be in
+                // either area but the hashed one comes first
+                match (data[0] & 0x7f, hashed) {
+                    (2, true) => sig.created = sub.u32()? as i64,
+                    (3, true) => sig.expires = Some(sub.u32()? as i64).filter(|&e| e != 0),
+                    (9, true) => sig.key_expires = Some(sub.u32()? as i64).filter(|&e| e != 0),
+                    (27, true) => sig.key_flags = Some(sub.u8()?),
+                    (16, _) if sig.issuer.is_none() => sig.issuer = Some(hex(sub.take(8)?)),
+                    (32, _) if sig.embedded.is_none() => {
+                        sig.embedded = Some(Box::new(Signature::parse(sub.data)?))
+                    }
+                    (33, _) if sig.issuer_fingerprint.is_none() => {
+                        let version = sub.u8()?;
+                        if version == 4 {
+                            sig.issuer_fingerprint = Some(hex(sub.take(20)?));
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(sig)
+    }
+
+    fn matches(&self, key: &PublicKey) -> bool {
+        match (&self.issuer_fingerprint, &self.issuer) {
+            (Some(fpr), _) => *fpr == key.fingerprint,
+            (None, Some(keyid)) => *keyid == key.keyid,
+            _ => false,
+        }
+    }
+
+    fn verify(&self, key: &PublicKey, hasher: Hasher) -> bool {
+        self.sigtype == 0x00 && self.check(key, hasher)
+    }
+
+    // verify a signature made by `signer` over `keys`, followed by `uid` for
+    // certifications, SHA-1 is too weak for these
+    fn verify_key(&self, signer: &PublicKey, keys: &[&PublicKey], uid: Option<&[u8]>) -> bool {
+        let mut hasher = match Hasher::new(self.hash_algo) {
+            Some(Hasher::Sha1(_)) | None => return false,
+            Some(hasher) => hasher,
+        };
+        for key in keys {
+            hasher.update(&[0x99]);
+            hasher.update(&(key.body.len() as u16).to_be_bytes());
+            hasher.update(&key.body);
+        }
+        if let Some(uid) = uid {
+            hasher.update(&[0xb4]);
+            hasher.update(&(uid.len() as u32).to_be_bytes());
+            hasher.update(uid);
+        }
+        self.check(signer, hasher)
+    }
+
+    // finish hashing the signed data and check the signature against `key`
+    fn check(&self, key: &PublicKey, hasher: Hasher) -> bool {
+        if self.algo != key.algo {
+            return false;
+        }
+
+        let digest_info = hasher.digest_info();
+        let mut hasher = hasher;
+        hasher.update(&self.hashed);
+        hasher.update(&[0x04, 0xff]);
+        hasher.update(&(self.hashed.len() as u32).to_be_bytes());
+        let hash = hasher.finalize();
+
+        if hash[..2] != self.left16 {
+            return false;
+        }
+
+        match (&key.material, self.mpis.as_slice()) {
+            (KeyMaterial::Rsa { n, e }, [s]) => {
+                let k = n.bits().div_ceil(8) as usize;
+                let m = BigUint::from_bytes_be(s).modpow(e, n).to_bytes_be();
+                if m.len() > k || k < digest_info.len() + hash.len() + 11 {
+                    return false;
+                }
+
+                let mut expected = vec![0x00, 0x01];
+                expected.resize(k - digest_info.len() - hash.len() - 1, 0xff);
+                expected.push(0x00);
+                expected.extend(digest_info);
+                expected.extend(&hash);
+
+                let mut padded = vec![0; k - m.len()];
+                padded.extend(m);
+                padded == expected
+            }
+            (KeyMaterial::Ed25519(pubkey), [r, s]) if r.len() <= 32 && s.len() <= 32 => {
+                let mut bytes = [0; 64];
+                bytes[32 - r.len()..32].copy_from_slice(r);
+                bytes[64 - s.len()..].copy_from_slice(s);
+                match ed25519_dalek::Signature::try_from(&bytes[..]) {
+                    Ok(sig) => pubkey.verify(&hash, &sig).is_ok(),
+                    Err(_) => false,
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Subkey {
+    key: PublicKey,
+    expires: Option<i64>,
+    revoked: bool,
+}
+
+/// A certificate from a keyring file: a primary key with its user ids and
+/// subkeys.
+#[derive(Debug, Clone)]
+pub struct PgpCert {
+    primary: PublicKey,
+    subkeys: Vec<Subkey>,
+    uids: Vec<String>,
+    expires: Option<i64>,
+    revoked: bool,
+}
+
+impl PgpCert {
+    pub fn fingerprint(&self) -> &str {
+        &self.primary.fingerprint
+    }
+
+    pub fn uids(&self) -> &[String] {
+        &self.uids
+    }
+
+    pub fn created(&self) -> i64 {
+        self.primary.created
+    }
+
+    pub fn expires(&self) -> Option<i64> {
+        self.expires
+    }
+
+    pub fn revoked(&self) -> bool {
+        self.revoked
+    }
+
+    // build a certificate from its primary key and the packets up to the
+    // next one, keeping only the user ids and subkeys the primary key signed.
+    // None if the primary key has no valid certification or direct key
+    // signature.
+    fn parse(primary: &[u8], packets: &[(u8, &[u8])]) -> io::Result<Option<PgpCert>> {
+        let mut cert = PgpCert {
+            primary: PublicKey::parse(primary)?,
+            subkeys: Vec::new(),
+            uids: Vec::new(),
+            expires: None,
+            revoked: false,
+        };
+        let primary = &cert.primary;
+        let mut self_sigs = Vec::new();
+
+        // each user id or subkey is followed by its signatures, the ones
+        // before the first are about the primary key itself
+        let mut component = None;
+        let mut rest = packets;
+        loop {
+            let n = rest.iter().take_while(|(tag, _)| *tag == 2).count();
+            // skip certifications by other keys before verifying anything
+            let sigs = rest[..n]
+                .iter()
+                .filter_map(|(_, body)| Signature::parse(body).ok())
+                .filter(|sig| {
+                    sig.matches(primary) || sig.issuer.is_none() && sig.issuer_fingerprint.is_none()
+                });
+
+            match component {
+                None => {
+                    for sig in sigs {
+                        match sig.sigtype {
+                            0x1f if sig.verify_key(primary, &[primary], None) => {
+                                self_sigs.push(sig)
+                            }
+                            0x20 if sig.verify_key(primary, &[primary], None) => {
+                                cert.revoked = true
+                            }
+                            _ => (),
+                        }
+                    }
+                }
+                Some((13, uid)) => {
+                    let certified = sigs
+                        .filter(|sig| matches!(sig.sigtype, 0x10..=0x13))
+                        .filter(|sig| sig.verify_key(primary, &[primary], Some(uid)))
+                        .collect::<Vec<_>>();
+                    if !certified.is_empty() {
+                        cert.uids.push(String::from_utf8_lossy(uid).into_owned());
+                    }
+                    self_sigs.extend(certified);
+                }
+                Some((7, body)) | Some((14, body)) => {
+                    let key = PublicKey::parse(body)?;
+                    let mut binding: Option<Signature> = None;
+                    let mut revoked = false;
+
+                    for sig in sigs {
+                        if !sig.verify_key(primary, &[primary, &key], None) {
+                            continue;
+                        }
+                        match sig.sigtype {
+                            0x18 if !matches!(binding, Some(ref b) if b.created > sig.created) => {
+                                binding = Some(sig)
+                            }
+                            0x28 => revoked = true,
+                            _ => (),
+                        }
+                    }
+
+                    // only signing subkeys are used, and those have to sign
+                    // the primary key back so no one can claim another's key
+                    let signs = binding.filter(|b| {
+                        !matches!(b.key_flags, Some(flags) if flags & 0x02 == 0)
+                            && b.embedded.as_ref().is_some_and(|back| {
+                                back.sigtype == 0x19
+                                    && back.verify_key(&key, &[primary, &key], None)
+                            })
+                    });
+                    if let Some(binding) = signs {
+                        cert.subkeys.push(Subkey {
+                            expires: binding.key_expires.map(|e| key.created + e),
+                            key,
+                            revoked,
+                        });
+                    }
+                }
+                _ => (),
+            }
+
+            match rest.get(n) {
+                Some(&packet) => component = Some(packet),
+                None => break,
+            }
+            rest = &rest[n + 1..];
+        }
+
+        // the newest self-signature replaces the older ones
+        let sig = match self_sigs.iter().max_by_key(|sig| sig.created) {
+            Some(sig) => sig,
+            None => return Ok(None),
+        };
+        cert.expires = sig.key_expires.map(|e| cert.primary.created + e);
+
+        Ok(Some(cert))
+    }
+
+    // the key that made `sig`, with when it expires and if it is revoked
+    fn signer(&self, sig: &Signature) -> Option<(&PublicKey, Option<i64>, bool)> {
+        if sig.matches(&self.primary) {
+            return Some((&self.primary, self.expires, self.revoked));
+        }
+
+        let subkey = self.subkeys.iter().find(|s| sig.matches(&s.key))?;
+        // a subkey is not valid for longer than its primary key
+        let expires = match (self.expires, subkey.expires) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        Some((&subkey.key, expires, self.revoked || subkey.revoked))
+    }
+
+    fn result(&self, status: SigStatus, validity: SigValidity) -> OwnedSigResult {
+        let uid = self.uids.first().cloned().unwrap_or_default();
+        let (name, email) = match (uid.rfind(" <"), uid.ends_with('>')) {
+            (Some(i), true) => (uid[..i].to_string(), uid[i + 2..uid.len() - 1].to_string()),
+            _ => (uid.clone(), String::new()),
+        };
+
+        OwnedSigResult {
+            fingerprint: self.primary.fingerprint.clone(),
+            uid,
+            name,
+            email,
+            created: self.primary.created,
+            expires: self.expires.unwrap_or(0),
+            length: self.primary.bits,
+            revoked: self.revoked,
+            pubkey_algo: if self.primary.algo == 22 { b'E' } else { b'R' },
+            status,
+            validity,
+        }
+    }
+}
+
+/// A set of public keys, such as an exported keyring file, used to verify
+/// signatures without gpg.
+#[derive(Debug, Default, Clone)]
+pub struct PgpKeyring {
+    certs: Vec<PgpCert>,
+}
+
+impl PgpKeyring {
+    /// Parse binary or ASCII armored public keys. The public part of secret
+    /// keys is used as well. Keys without a valid self-signature are left out.
+    pub fn parse(data: &[u8]) -> io::Result<PgpKeyring> {
+        let data = dearmor(data)?;
+        let packets = packets(&data)?;
+        let starts = packets
+            .iter()
+            .enumerate()
+            .filter(|(_, (tag, _))| *tag == 5 || *tag == 6)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+
+        let mut certs = Vec::new();
+        for (i, &start) in starts.iter().enumerate() {
+            let end = starts.get(i + 1).cloned().unwrap_or(packets.len());
+            certs.extend(PgpCert::parse(packets[start].1, &packets[start + 1..end])?);
+        }
+
+        Ok(PgpKeyring { certs })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<PgpKeyring> {
+        PgpKeyring::parse(&fs::read(path)?)
+    }
+
+    /// Add the keys of `other` to this keyring.
+    pub fn extend(&mut self, other: PgpKeyring) {
+        self.certs.extend(other.certs);
+    }
+
+    pub fn certs(&self) -> &[PgpCert] {
+        &self.certs
+    }
+
+    /// Verify the detached, binary or armored, signature `sig` of `data`.
+    /// Keys in the keyring are considered fully trusted.
+    pub fn verify(&self, mut data: impl Read, sig: &[u8]) -> io::Result<(bool, SigList)> {
+        let sigs = packets(&dearmor(sig)?)?
+            .into_iter()
+            .filter(|(tag, _)| *tag == 2)
+            .map(|(_, body)| Signature::parse(body))
+            .collect::<io::Result<Vec<_>>>()?;
+        if sigs.is_empty() {
+            return Err(invalid("no signature found"));
+        }
+
+        let mut hashers = sigs
+            .iter()
+            .map(|s| Hasher::new(s.hash_algo))
+            .collect::<Vec<_>>();
+        let mut buf = [0; 64 * 1024];
+        loop {
+            let n = data.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            for hasher in hashers.iter_mut().flatten() {
+                hasher.update(&buf[..n]);
+            }
+        }
+
+        let now = now();
+        let mut results = Vec::new();
+
+        for (sig, hasher) in sigs.iter().zip(hashers) {
+            let found = self
+                .certs
+                .iter()
+                .find_map(|c| c.signer(sig).map(|signer| (c, signer)));
+
+            let (cert, (key, expires, revoked)) = match found {
+                Some(found) => found,
+                None => {
+                    let fingerprint = sig
+                        .issuer_fingerprint
+                        .clone()
+                        .or_else(|| sig.issuer.clone());
+                    results.push(OwnedSigResult {
+                        fingerprint: fingerprint.unwrap_or_default(),
+                        uid: String::new(),
+                        name: String::new(),
+                        email: String::new(),
+                        created: 0,
+                        expires: 0,
+                        length: 0,
+                        revoked: false,
+                        pubkey_algo: 0,
+                        status: SigStatus::KeyUnknown,
+                        validity: SigValidity::Unknown,
+                    });
+                    continue;
+                }
+            };
+
+            let valid = match hasher {
+                Some(h) => sig.verify(key, h),
+                None => false,
+            };
+            let status = if !valid {
+                SigStatus::Invalid
+            } else if matches!(sig.expires, Some(e) if sig.created + e < now) {
+                SigStatus::SigExpired
+            } else if matches!(expires, Some(e) if e < now) {
+                SigStatus::KeyExpired
+            } else {
+                SigStatus::Valid
+            };
+            let validity = match status {
+                SigStatus::Invalid => SigValidity::Unknown,
+                _ if revoked => SigValidity::Never,
+                _ => SigValidity::Full,
+            };
+
+            results.push(cert.result(status, validity));
+        }
+
+        let valid = results
+            .iter()
+            .all(|r| r.status == SigStatus::Valid && r.validity == SigValidity::Full);
+        Ok((valid, SigList::from_owned(results)))
+    }
+
+    /// Verify a base64 encoded signature, as found in sync databases.
+    pub fn verify_base64(&self, data: impl Read, sig: &str) -> io::Result<(bool, SigList)> {
+        let sig = base64::decode(sig.trim()).map_err(|e| invalid(e.to_string()))?;
+        self.verify(data, &sig)
+    }
+
+    /// Verify `path` against its detached signature `path.sig`.
+    pub fn verify_file(&self, path: impl AsRef<Path>) -> io::Result<(bool, SigList)> {
+        let path = path.as_ref();
+        let mut sig = path.as_os_str().to_owned();
+        sig.push(".sig");
+        let sig = fs::read(sig)?;
+        self.verify(File::open(path)?, &sig)
+    }
+}
+
+/// An unprotected secret key, as exported by `gpg --export-secret-keys` for a
+/// key without a passphrase, used to create signatures without gpg. Only
+/// Ed25519 keys are supported, RSA signing is left to gpg.
+#[derive(Clone)]
+pub struct PgpSecretKey {
+    key: PublicKey,
+    seed: [u8; 32],
+}
+
+impl fmt::Debug for PgpSecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PgpSecretKey")
+            .field("fingerprint", &self.key.fingerprint)
+            .finish()
+    }
+}
+
+impl PgpSecretKey {
+    /// Parse the first binary or ASCII armored secret primary key.
+    pub fn parse(data: &[u8]) -> io::Result<PgpSecretKey> {
+        let data = dearmor(data)?;
+        let body = packets(&data)?
+            .into_iter()
+            .find(|(tag, _)| *tag == 5)
+            .map(|(_, body)| body)
+            .ok_or_else(|| invalid("no secret key found"))?;
+
+        let mut r = Reader { data: body };
+        let key = PublicKey::read(&mut r)?;
+        if r.u8()? != 0 {
+            return Err(invalid("protected secret keys are not supported"));
+        }
+
+        if !matches!(key.material, KeyMaterial::Ed25519(_)) {
+            return Err(invalid("only Ed25519 secret keys are supported"));
+        }
+        let mpi = r.mpi()?;
+        if mpi.len() > 32 {
+            return Err(invalid("invalid Ed25519 secret key"));
+        }
+        let mut seed = [0; 32];
+        seed[32 - mpi.len()..].copy_from_slice(mpi);
+
+        Ok(PgpSecretKey { key, seed })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<PgpSecretKey> {
+        PgpSecretKey::parse(&fs::read(path)?)
+    }
+
+    pub fn fingerprint(&self) -> &str {
+        &self.key.fingerprint
+    }
+
+    /// Create a binary detached signature of `data`.
+    pub fn sign(&self, mut data: impl Read) -> io::Result<Vec<u8>> {
+        let created = now();
+        let fingerprint = unhex(&self.key.fingerprint);
+
+        let mut subpackets = vec![22, 33, 4];
+        subpackets.extend(&fingerprint);
+        subpackets.extend([5, 2]);
+        subpackets.extend((created as u32).to_be_bytes());
+
+        let mut hashed = vec![4, 0x00, self.key.algo, 8];
+        hashed.extend((subpackets.len() as u16).to_be_bytes());
+        hashed.extend(subpackets);
+
+        let mut hasher = Hasher::Sha256(Sha256::new());
+        let mut buf = [0; 64 * 1024];
+        loop {
+            let n = data.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        hasher.update(&hashed);
+        hasher.update(&[0x04, 0xff]);
+        hasher.update(&(hashed.len() as u32).to_be_bytes());
+        let hash = hasher.finalize();
+
+        let secret =
+            ed25519_dalek::SecretKey::from_bytes(&self.seed).map_err(|e| invalid(e.to_string()))?;
+        let public = ed25519_dalek::PublicKey::from(&secret);
+        let sig = ed25519_dalek::Keypair { secret, public }
+            .sign(&hash)
+            .to_bytes();
+
+        let mut body = hashed;
+        body.extend([0, 10, 9, 16]);
+        body.extend(&fingerprint[fingerprint.len() - 8..]);
+        body.extend(&hash[..2]);
+        write_mpi(&mut body, &sig[..32]);
+        write_mpi(&mut body, &sig[32..]);
+
+        Ok(packet(2, &body))
+    }
+}
+
+impl<'a> Pkg<'a> {
+    /// Like `check_signature` but verified in rust against `keyring`.
+    pub fn check_signature_with(&self, keyring: &PgpKeyring) -> io::Result<(bool, SigList)> {
+        let path = match self.origin() {
+            PackageFrom::File => PathBuf::from(self.filename()),
+            PackageFrom::SyncDb => self
+                .handle
+                .cachedirs()
+                .map(|dir| Path::new(dir).join(self.filename()))
+                .find(|path| path.exists())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{} is not in the cache", self.filename()),
+                    )
+                })?,
+            PackageFrom::LocalDb => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "installed packages have no package file",
+                ))
+            }
+        };
+
+        match self.base64_sig() {
+            Some(sig) => keyring.verify_base64(File::open(path)?, sig),
+            None => keyring.verify_file(path),
+        }
+    }
+}
+
+impl<'a> Db<'a> {
+    /// Like `check_signature` but verified in rust against `keyring`.
+    pub fn check_signature_with(&self, keyring: &PgpKeyring) -> io::Result<(bool, SigList)> {
+        let ext = match self.handle.dbext() {
+            "" => ".db",
+            ext => ext,
+        };
+        let path =
+            Path::new(self.handle.dbpath())
+                .join("sync")
+                .join(format!("{}{}", self.name(), ext));
+        keyring.verify_file(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ED: &str = "44771FB4F813F0B09F24E6E96AD9B0AF1C1AB1E7";
+    const RSA: &str = "F043329F98FB507DE8A774AFF20A2BC8A18AF47D";
+    const SUB: &str = "67B61E4DADCF88C79648B120606152E225043A24";
+
+    fn fixture(name: &str) -> Vec<u8> {
+        fs::read(Path::new("tests/pgp").join(name)).unwrap()
+    }
+
+    fn verify(sig: &str) -> (bool, SigList) {
+        let keyring = PgpKeyring::load("tests/pgp/keyring.gpg").unwrap();
+        keyring.verify(&fixture("data")[..], &fixture(sig)).unwrap()
+    }
+
+    #[test]
+    fn test_parse_keyring() {
+        let keyring = PgpKeyring::load("tests/pgp/keyring.gpg").unwrap();
+        let certs = keyring.certs();
+        assert_eq!(certs.len(), 3);
+        assert_eq!(certs[0].fingerprint(), ED);
+        assert_eq!(certs[0].uids(), ["Ed Packager <ed@example.org>"]);
+        assert_eq!(certs[0].expires(), None);
+        assert_eq!(certs[1].fingerprint(), RSA);
+        assert_eq!(certs[2].expires(), Some(certs[2].created() + 3));
+
+        let armored = PgpKeyring::load("tests/pgp/ed.asc").unwrap();
+        assert_eq!(armored.certs().len(), 1);
+        assert_eq!(armored.certs()[0].fingerprint(), ED);
+    }
+
+    #[test]
+    fn test_verify_ed25519() {
+        let (valid, siglist) = verify("data.ed.sig");
+        assert!(valid);
+        let result = &siglist.results()[0];
+        assert_eq!(result.status(), SigStatus::Valid);
+        assert_eq!(result.validity(), SigValidity::Full);
+        assert_eq!(result.key().fingerprint(), ED);
+        assert_eq!(result.key().name(), "Ed Packager");
+        assert_eq!(result.key().email(), "ed@example.org");
+        assert_eq!(result.key().length(), 255);
+
+        let (valid, siglist) = verify("data.asc");
+        assert!(valid);
+        assert_eq!(siglist.results().len(), 1);
+    }
+
+    #[test]
+    fn test_verify_rsa() {
+        let (valid, siglist) = verify("data.rsa.sig");
+        assert!(valid);
+        let result = &siglist.results()[0];
+        assert_eq!(result.status(), SigStatus::Valid);
+        assert_eq!(result.key().fingerprint(), RSA);
+        assert_eq!(result.key().length(), 2048);
+    }
+
+    #[test]
+    fn test_verify_bad() {
+        let keyring = PgpKeyring::load("tests/pgp/keyring.gpg").unwrap();
+        let mut data = fixture("data");
+        data[0] ^= 1;
+
+        for sig in &["data.ed.sig", "data.rsa.sig"] {
+            let (valid, siglist) = keyring.verify(&data[..], &fixture(sig)).unwrap();
+            assert!(!valid);
+            assert_eq!(siglist.results()[0].status(), SigStatus::Invalid);
+        }
+
+        let (valid, siglist) = verify("data.unknown.sig");
+        assert!(!valid);
+        let result = &siglist.results()[0];
+        assert_eq!(result.status(), SigStatus::KeyUnknown);
+        assert_eq!(
+            result.key().fingerprint(),
+            "AAA3577115B1C18C36B68A89C82F4B5BC1462403"
+        );
+
+        let (valid, siglist) = verify("data.expired.sig");
+        assert!(!valid);
+        assert_eq!(siglist.results()[0].status(), SigStatus::KeyExpired);
+
+        assert!(keyring.verify(&data[..], b"not a signature").is_err());
+    }
+
+    #[test]
+    fn test_verify_subkey() {
+        let keyring = PgpKeyring::load("tests/pgp/sub.gpg").unwrap();
+        let (valid, siglist) = keyring
+            .verify(&fixture("data")[..], &fixture("data.sub.sig"))
+            .unwrap();
+        assert!(valid);
+        assert_eq!(siglist.results()[0].key().fingerprint(), SUB);
+
+        // without its binding signature the subkey is not part of the cert
+        let keyring = PgpKeyring::load("tests/pgp/sub-unbound.gpg").unwrap();
+        let (valid, siglist) = keyring
+            .verify(&fixture("data")[..], &fixture("data.sub.sig"))
+            .unwrap();
+        assert!(!valid);
+        assert_eq!(siglist.results()[0].status(), SigStatus::KeyUnknown);
+
+        let keyring = PgpKeyring::load("tests/pgp/sub-revoked.gpg").unwrap();
+        let (valid, siglist) = keyring
+            .verify(&fixture("data")[..], &fixture("data.sub.sig"))
+            .unwrap();
+        assert!(!valid);
+        assert_eq!(siglist.results()[0].validity(), SigValidity::Never);
+    }
+
+    #[test]
+    fn test_uid_self_signature() {
+        let keyring = PgpKeyring::load("tests/pgp/sub.gpg").unwrap();
+        assert_eq!(
+            keyring.certs()[0].uids(),
+            ["Sub Packager <sub@example.org>"]
+        );
+
+        // a key whose only user id was not certified by it is left out
+        let mut data = fixture("sub.gpg");
+        let i = data.windows(3).position(|w| w == b"Sub").unwrap();
+        data[i..i + 3].copy_from_slice(b"Bad");
+        let keyring = PgpKeyring::parse(&data).unwrap();
+        assert!(keyring.certs().is_empty());
+        let (valid, siglist) = keyring
+            .verify(&fixture("data")[..], &fixture("data.sub.sig"))
+            .unwrap();
+        assert!(!valid);
+        assert_eq!(siglist.results()[0].status(), SigStatus::KeyUnknown);
+
+        // and so is a bare public key
+        let data = fixture("sub.gpg");
+        let (tag, body) = packets(&data).unwrap()[0];
+        assert_eq!(tag, 6);
+        let keyring = PgpKeyring::parse(&packet(6, body)).unwrap();
+        assert!(keyring.certs().is_empty());
+    }
+
+    #[test]
+    fn test_critical_subpacket() {
+        let keyring = PgpKeyring::load("tests/pgp/keyring.gpg").unwrap();
+        let sig = fixture("data.ed.sig");
+        let (_, body) = packets(&sig).unwrap()[0];
+
+        // add an unknown critical subpacket to the unhashed area, which does
+        // not change the signed data
+        let hashed_len = (body[4] as usize) << 8 | body[5] as usize;
+        let i = 6 + hashed_len;
+        let unhashed_len = (body[i] as usize) << 8 | body[i + 1] as usize;
+        let mut body = body.to_vec();
+        body.splice(i + 2..i + 2, [2, 0x80 | 100, 0]);
+        body[i..i + 2].copy_from_slice(&(unhashed_len as u16 + 3).to_be_bytes());
+        assert!(keyring
+            .verify(&fixture("data")[..], &packet(2, &body))
+            .is_err());
+
+        // the same subpacket is fine when it is not critical
+        body[i + 3] = 100;
+        assert!(
+            keyring
+                .verify(&fixture("data")[..], &packet(2, &body))
+                .unwrap()
+                .0
+        );
+    }
+
+    #[test]
+    fn test_verify_base64() {
+        let keyring = PgpKeyring::load("tests/pgp/ed.asc").unwrap();
+        let sig = base64::encode(fixture("data.ed.sig"));
+        let (valid, _) = keyring.verify_base64(&fixture("data")[..], &sig).unwrap();
+        assert!(valid);
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("foo.db"), fixture("data")).unwrap();
+        fs::write(dir.path().join("foo.db.sig"), fixture("data.ed.sig")).unwrap();
+        assert!(keyring.verify_file(dir.path().join("foo.db")).unwrap().0);
+    }
+
+    #[test]
+    fn test_sign() {
+        let path = "tests/pgp/signer-ed.sec";
+        let key = PgpSecretKey::load(path).unwrap();
+        let keyring = PgpKeyring::load(path).unwrap();
+        assert_eq!(keyring.certs()[0].fingerprint(), key.fingerprint());
+
+        let sig = key.sign(&fixture("data")[..]).unwrap();
+        let (valid, siglist) = keyring.verify(&fixture("data")[..], &sig).unwrap();
+        assert!(valid);
+        assert_eq!(siglist.results()[0].key().fingerprint(), key.fingerprint());
+
+        let (valid, _) = keyring.verify(&b"other data"[..], &sig).unwrap();
+        assert!(!valid);
+
+        assert!(PgpSecretKey::load("tests/pgp/signer-rsa.sec").is_err());
+        assert!(PgpSecretKey::load("tests/pgp/keyring.gpg").is_err());
+    }
+}
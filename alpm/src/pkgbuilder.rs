@@ -0,0 +1,497 @@
+use crate::archive::entry_path;
+use crate::{write_mtree, BuildInfo, MTreeEntry, MTreeType, PkgInfo};
+
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use tar::{Builder, EntryType, Header};
+use xz2::write::XzEncoder;
+
+const META_FILES: &[&str] = &[".PKGINFO", ".BUILDINFO", ".MTREE"];
+const EXTRA_META_FILES: &[&str] = &[".INSTALL", ".CHANGELOG"];
+
+#[derive(Debug)]
+struct StagedFile {
+    path: PathBuf,
+    file_type: MTreeType,
+    mode: u32,
+    size: u64,
+    link: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PkgBuilder {
+    dir: PathBuf,
+    pkginfo: PkgInfo,
+    buildinfo: Option<BuildInfo>,
+    source_date_epoch: Option<i64>,
+}
+
+fn source_date_epoch() -> i64 {
+    env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0)
+        })
+}
+
+fn walk(root: &Path, dir: &Path, files: &mut Vec<StagedFile>) -> io::Result<()> {
+    for entry in fs::read_dir(root.join(dir))? {
+        let entry = entry?;
+        let path = dir.join(entry.file_name());
+        let meta = entry.path().symlink_metadata()?;
+        let ft = meta.file_type();
+
+        if dir.as_os_str().is_empty() && META_FILES.iter().any(|m| path == Path::new(m)) {
+            continue;
+        }
+
+        let (file_type, size, link) = if ft.is_symlink() {
+            (MTreeType::Link, 0, Some(fs::read_link(entry.path())?))
+        } else if ft.is_dir() {
+            (MTreeType::Dir, 0, None)
+        } else if ft.is_file() {
+            (MTreeType::File, meta.len(), None)
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported file type: {}", path.display()),
+            ));
+        };
+
+        files.push(StagedFile {
+            path: path.clone(),
+            file_type,
+            mode: meta.mode() & 0o7777,
+            size,
+            link,
+        });
+
+        if ft.is_dir() {
+            walk(root, &path, files)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn digests(r: &mut impl Read) -> io::Result<(String, String)> {
+    let mut md5 = Md5::new();
+    let mut sha256 = Sha256::new();
+    let mut buf = [0; 64 * 1024];
+    loop {
+        let n = r.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        md5.update(&buf[..n]);
+        sha256.update(&buf[..n]);
+    }
+    Ok((
+        format!("{:x}", md5.finalize()),
+        format!("{:x}", sha256.finalize()),
+    ))
+}
+
+pub(crate) fn header(entry_type: EntryType, mode: u32, size: u64, mtime: i64) -> Header {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_mode(mode);
+    header.set_size(size);
+    header.set_mtime(mtime.max(0) as u64);
+    header.set_uid(0);
+    header.set_gid(0);
+    // only fails for names longer than the field
+    let _ = header.set_username("root");
+    let _ = header.set_groupname("root");
+    header
+}
+
+impl PkgBuilder {
+    pub fn new(dir: impl AsRef<Path>, pkginfo: PkgInfo) -> PkgBuilder {
+        PkgBuilder {
+            dir: dir.as_ref().to_path_buf(),
+            pkginfo,
+            buildinfo: None,
+            source_date_epoch: None,
+        }
+    }
+
+    pub fn buildinfo(mut self, buildinfo: BuildInfo) -> PkgBuilder {
+        self.buildinfo = Some(buildinfo);
+        self
+    }
+
+    pub fn source_date_epoch(mut self, epoch: i64) -> PkgBuilder {
+        self.source_date_epoch = Some(epoch);
+        self
+    }
+
+    pub fn build(&self, out: impl AsRef<Path>) -> io::Result<()> {
+        let out = out.as_ref();
+        let ext = out.extension().and_then(OsStr::to_str).unwrap_or("");
+        // only create the file once the extension is known to be supported
+        let create = || File::create(out);
+
+        match ext {
+            "zst" => self
+                .write(zstd::Encoder::new(create()?, 19)?)?
+                .finish()?
+                .sync_all(),
+            "xz" => self
+                .write(XzEncoder::new(create()?, 6))?
+                .finish()?
+                .sync_all(),
+            "gz" => self
+                .write(GzEncoder::new(create()?, Compression::best()))?
+                .finish()?
+                .sync_all(),
+            "tar" => self.write(create()?)?.sync_all(),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported package extension: {}", out.display()),
+            )),
+        }
+    }
+
+    fn staged_files(&self) -> io::Result<Vec<StagedFile>> {
+        let mut files = Vec::new();
+        walk(&self.dir, Path::new(""), &mut files)?;
+
+        let order = |f: &StagedFile| {
+            EXTRA_META_FILES
+                .iter()
+                .position(|m| f.path == Path::new(m))
+                .unwrap_or(EXTRA_META_FILES.len())
+        };
+        files.sort_by(|a, b| {
+            order(a).cmp(&order(b)).then_with(|| {
+                a.path
+                    .as_os_str()
+                    .as_bytes()
+                    .cmp(b.path.as_os_str().as_bytes())
+            })
+        });
+
+        Ok(files)
+    }
+
+    fn mtree_entry(
+        &self,
+        file: &StagedFile,
+        digests: Option<(String, String)>,
+        time: i64,
+    ) -> MTreeEntry {
+        let (md5digest, sha256digest) = match digests {
+            Some((md5, sha256)) => (Some(md5), Some(sha256)),
+            None => (None, None),
+        };
+
+        MTreeEntry {
+            path: entry_path(&file.path).to_string_lossy().into_owned(),
+            file_type: file.file_type,
+            uid: 0,
+            gid: 0,
+            mode: file.mode,
+            size: file.size,
+            time,
+            time_nsec: 0,
+            link: file.link.as_ref().map(|l| l.to_string_lossy().into_owned()),
+            md5digest,
+            sha256digest,
+        }
+    }
+
+    fn write<W: Write>(&self, w: W) -> io::Result<W> {
+        let epoch = self.source_date_epoch.unwrap_or_else(source_date_epoch);
+        let files = self.staged_files()?;
+
+        let mut pkginfo = self.pkginfo.clone();
+        pkginfo.build_date = epoch;
+        pkginfo.size = files
+            .iter()
+            .filter(|f| f.file_type == MTreeType::File)
+            .filter(|f| !EXTRA_META_FILES.iter().any(|m| f.path == Path::new(m)))
+            .map(|f| f.size as i64)
+            .sum();
+
+        let mut buildinfo = self.buildinfo.clone().unwrap_or_else(|| BuildInfo {
+            format: 2,
+            name: pkginfo.name.clone(),
+            base: pkginfo.base.clone(),
+            version: pkginfo.version.clone(),
+            arch: pkginfo.arch.clone().unwrap_or_else(|| "any".to_string()),
+            packager: pkginfo.packager.clone(),
+            ..BuildInfo::default()
+        });
+        buildinfo.build_date = epoch;
+
+        let pkginfo = pkginfo.to_string().into_bytes();
+        let buildinfo = buildinfo.to_string().into_bytes();
+
+        let mut mtree = Vec::new();
+        for (name, data) in &[(".PKGINFO", &pkginfo), (".BUILDINFO", &buildinfo)] {
+            let file = StagedFile {
+                path: PathBuf::from(name),
+                file_type: MTreeType::File,
+                mode: 0o644,
+                size: data.len() as u64,
+                link: None,
+            };
+            let digests = digests(&mut data.as_slice())?;
+            mtree.push(self.mtree_entry(&file, Some(digests), epoch));
+        }
+        for file in &files {
+            let entry = if file.file_type == MTreeType::File {
+                let digests = digests(&mut File::open(self.dir.join(&file.path))?)?;
+                self.mtree_entry(file, Some(digests), epoch)
+            } else {
+                self.mtree_entry(file, None, epoch)
+            };
+            mtree.push(entry);
+        }
+
+        let mut gz = GzEncoder::new(Vec::new(), Compression::best());
+        gz.write_all(write_mtree(&mtree).as_bytes())?;
+        let mtree = gz.finish()?;
+
+        let mut builder = Builder::new(w);
+
+        for (name, data) in &[
+            (".PKGINFO", &pkginfo),
+            (".BUILDINFO", &buildinfo),
+            (".MTREE", &mtree),
+        ] {
+            let mut header = header(EntryType::Regular, 0o644, data.len() as u64, epoch);
+            builder.append_data(&mut header, name, data.as_slice())?;
+        }
+
+        for file in &files {
+            match file.file_type {
+                MTreeType::Dir => {
+                    let mut header = header(EntryType::Directory, file.mode, 0, epoch);
+                    builder.append_data(&mut header, &file.path, io::empty())?;
+                }
+                MTreeType::Link => {
+                    let link = file.link.as_ref().unwrap();
+                    let mut header = header(EntryType::Symlink, 0o777, 0, epoch);
+                    builder.append_link(&mut header, &file.path, link)?;
+                }
+                _ => {
+                    let mut header = header(EntryType::Regular, file.mode, file.size, epoch);
+                    let data = File::open(self.dir.join(&file.path))?.take(file.size);
+                    builder.append_data(&mut header, &file.path, data)?;
+                }
+            }
+        }
+
+        builder.into_inner()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Alpm, PkgArchive, SigLevel};
+
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    fn stage() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+
+        fs::create_dir_all(root.join("usr/bin")).unwrap();
+        fs::create_dir_all(root.join("etc")).unwrap();
+        fs::write(root.join("usr/bin/foo"), "#!/bin/sh\necho foo\n").unwrap();
+        fs::set_permissions(root.join("usr/bin/foo"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(root.join("etc/foo.conf"), "foo = bar\n").unwrap();
+        symlink("foo", root.join("usr/bin/bar")).unwrap();
+        fs::write(root.join(".INSTALL"), "post_install() {\n\ttrue\n}\n").unwrap();
+
+        dir
+    }
+
+    fn pkginfo() -> PkgInfo {
+        PkgInfo {
+            name: "foo".to_string(),
+            version: "1.0-1".to_string(),
+            desc: Some("a test package".to_string()),
+            arch: Some("any".to_string()),
+            backup: vec!["etc/foo.conf".to_string()],
+            depends: vec!["sh".to_string()],
+            ..PkgInfo::default()
+        }
+    }
+
+    #[test]
+    fn test_build() {
+        let stage = stage();
+        let out = tempfile::tempdir().unwrap();
+        let path = out.path().join("foo-1.0-1-any.pkg.tar.zst");
+
+        PkgBuilder::new(stage.path(), pkginfo())
+            .source_date_epoch(1551404516)
+            .build(&path)
+            .unwrap();
+
+        let pkg = PkgArchive::open(&path).unwrap();
+        let info = pkg.pkginfo().unwrap();
+        assert_eq!(info.name, "foo");
+        assert_eq!(info.build_date, 1551404516);
+        assert_eq!(info.size, 29);
+        assert_eq!(info.backup, vec!["etc/foo.conf"]);
+        assert_eq!(pkg.buildinfo().unwrap().build_date, 1551404516);
+
+        let mtree = pkg.mtree().unwrap();
+        let foo = mtree.iter().find(|e| e.path == "usr/bin/foo").unwrap();
+        assert_eq!(foo.mode, 0o755);
+        assert_eq!(foo.time, 1551404516);
+        let bar = mtree.iter().find(|e| e.path == "usr/bin/bar").unwrap();
+        assert_eq!(bar.link.as_deref(), Some("foo"));
+
+        let mut archive = pkg.archive().unwrap();
+        let entries = archive
+            .entries()
+            .unwrap()
+            .map(|e| {
+                let e = e.unwrap();
+                assert_eq!(e.header().uid().unwrap(), 0);
+                assert_eq!(e.header().mtime().unwrap(), 1551404516);
+                e.path().unwrap().to_string_lossy().into_owned()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            vec![
+                ".PKGINFO",
+                ".BUILDINFO",
+                ".MTREE",
+                ".INSTALL",
+                "etc",
+                "etc/foo.conf",
+                "usr",
+                "usr/bin",
+                "usr/bin/bar",
+                "usr/bin/foo",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_reproducible() {
+        let stage = stage();
+        let out = tempfile::tempdir().unwrap();
+        let a = out.path().join("a.pkg.tar.xz");
+        let b = out.path().join("b.pkg.tar.xz");
+
+        let builder = PkgBuilder::new(stage.path(), pkginfo()).source_date_epoch(0);
+        builder.build(&a).unwrap();
+        builder.build(&b).unwrap();
+
+        assert_eq!(fs::read(a).unwrap(), fs::read(b).unwrap());
+    }
+
+    #[test]
+    fn test_build_unsupported() {
+        let stage = stage();
+        let out = tempfile::tempdir().unwrap();
+        let path = out.path().join("foo-1.0-1-any.pkg.tar.bz2");
+
+        let err = PkgBuilder::new(stage.path(), pkginfo())
+            .build(&path)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_build_pkg_load() {
+        let stage = stage();
+        let out = tempfile::tempdir().unwrap();
+        let path = out.path().join("foo-1.0-1-any.pkg.tar.zst");
+
+        let info = PkgInfo {
+            name: "foo".to_string(),
+            base: Some("foo-base".to_string()),
+            version: "1:1.0-1".to_string(),
+            desc: Some("a test package".to_string()),
+            url: Some("https://example.org/foo".to_string()),
+            packager: Some("Foo Bar <foo@example.org>".to_string()),
+            arch: Some("any".to_string()),
+            licenses: vec!["MIT".to_string(), "GPL".to_string()],
+            replaces: vec!["oldfoo".to_string()],
+            groups: vec!["foos".to_string()],
+            conflicts: vec!["bar<2".to_string()],
+            provides: vec!["libfoo.so=1-64".to_string()],
+            backup: vec!["etc/foo.conf".to_string()],
+            depends: vec!["sh".to_string(), "glibc>=2.30".to_string()],
+            optdepends: vec!["bash: for bar".to_string()],
+            makedepends: vec!["make".to_string()],
+            checkdepends: vec!["python".to_string()],
+            ..PkgInfo::default()
+        };
+        PkgBuilder::new(stage.path(), info.clone())
+            .source_date_epoch(1_600_000_000)
+            .build(&path)
+            .unwrap();
+
+        let expected = PkgInfo {
+            build_date: 1_600_000_000,
+            size: 29,
+            ..info
+        };
+        assert_eq!(
+            PkgArchive::open(&path).unwrap().pkginfo().unwrap(),
+            expected
+        );
+
+        let handle = Alpm::new("/", "tests/db").unwrap();
+        let pkg = handle
+            .pkg_load(path.to_str().unwrap(), true, SigLevel::NONE)
+            .unwrap();
+        let pkg = pkg.pkg();
+        let to_string = |s: &str| s.to_string();
+        let deps = |deps: crate::AlpmList<crate::Depend>| deps.map(|d| d.to_string()).collect();
+        let loaded = PkgInfo {
+            name: pkg.name().to_string(),
+            base: pkg.base().map(to_string),
+            version: pkg.version().to_string(),
+            desc: pkg.desc().map(to_string),
+            url: pkg.url().map(to_string),
+            build_date: pkg.build_date(),
+            packager: pkg.packager().map(to_string),
+            size: pkg.isize(),
+            arch: pkg.arch().map(to_string),
+            licenses: pkg.licenses().map(to_string).collect(),
+            replaces: deps(pkg.replaces()),
+            groups: pkg.groups().map(to_string).collect(),
+            conflicts: deps(pkg.conflicts()),
+            provides: deps(pkg.provides()),
+            backup: pkg.backup().map(|b| b.name().to_string()).collect(),
+            depends: deps(pkg.depends()),
+            optdepends: deps(pkg.optdepends()),
+            makedepends: deps(pkg.makedepends()),
+            checkdepends: deps(pkg.checkdepends()),
+            // libalpm does not expose these
+            makepkgopts: Vec::new(),
+            xdata: Vec::new(),
+        };
+        assert_eq!(loaded, expected);
+        assert!(pkg.has_scriptlet());
+        assert!(pkg.files().contains("usr/bin/foo").unwrap().is_some());
+    }
+}
@@ -0,0 +1,221 @@
+use crate::ParseError;
+
+use std::fmt;
+use std::str::FromStr;
+
+pub(crate) fn parse_kv(s: &str) -> impl Iterator<Item = Result<(usize, &str, &str), ParseError>> {
+    s.lines()
+        .enumerate()
+        .map(|(n, line)| (n + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(n, line)| {
+            let mut split = line.splitn(2, '=');
+            let key = split.next().unwrap().trim();
+            match split.next() {
+                Some(value) if !key.is_empty() => Ok((n, key, value.trim())),
+                _ => Err(ParseError::new(
+                    n,
+                    format!("expected 'key = value': {}", line),
+                )),
+            }
+        })
+}
+
+pub(crate) fn write_kv(f: &mut fmt::Formatter, key: &str, value: &str) -> fmt::Result {
+    writeln!(f, "{} = {}", key, value)
+}
+
+pub(crate) fn write_kvs(f: &mut fmt::Formatter, key: &str, values: &[String]) -> fmt::Result {
+    for value in values {
+        write_kv(f, key, value)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn parse_num<T: FromStr>(line: usize, key: &str, value: &str) -> Result<T, ParseError> {
+    value
+        .parse()
+        .map_err(|_| ParseError::new(line, format!("invalid {}: {}", key, value)))
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct PkgInfo {
+    pub name: String,
+    pub base: Option<String>,
+    pub version: String,
+    pub desc: Option<String>,
+    pub url: Option<String>,
+    pub build_date: i64,
+    pub packager: Option<String>,
+    pub size: i64,
+    pub arch: Option<String>,
+    pub licenses: Vec<String>,
+    pub replaces: Vec<String>,
+    pub groups: Vec<String>,
+    pub conflicts: Vec<String>,
+    pub provides: Vec<String>,
+    pub backup: Vec<String>,
+    pub depends: Vec<String>,
+    pub optdepends: Vec<String>,
+    pub makedepends: Vec<String>,
+    pub checkdepends: Vec<String>,
+    pub makepkgopts: Vec<String>,
+    pub xdata: Vec<String>,
+}
+
+impl PkgInfo {
+    pub fn parse(s: impl AsRef<str>) -> Result<PkgInfo, ParseError> {
+        let mut info = PkgInfo::default();
+
+        for kv in parse_kv(s.as_ref()) {
+            let (line, key, value) = kv?;
+            let value = value.to_string();
+
+            match key {
+                "pkgname" => info.name = value,
+                "pkgbase" => info.base = Some(value),
+                "pkgver" => info.version = value,
+                "pkgdesc" => info.desc = Some(value),
+                "url" => info.url = Some(value),
+                "builddate" => info.build_date = parse_num(line, key, &value)?,
+                "packager" => info.packager = Some(value),
+                "size" => info.size = parse_num(line, key, &value)?,
+                "arch" => info.arch = Some(value),
+                "license" => info.licenses.push(value),
+                "replaces" => info.replaces.push(value),
+                "group" => info.groups.push(value),
+                "conflict" => info.conflicts.push(value),
+                "provides" => info.provides.push(value),
+                "backup" => info.backup.push(value),
+                "depend" => info.depends.push(value),
+                "optdepend" => info.optdepends.push(value),
+                "makedepend" => info.makedepends.push(value),
+                "checkdepend" => info.checkdepends.push(value),
+                "makepkgopt" => info.makepkgopts.push(value),
+                "xdata" => info.xdata.push(value),
+                _ => (),
+            }
+        }
+
+        if info.name.is_empty() {
+            return Err(ParseError::missing("pkgname"));
+        }
+        if info.version.is_empty() {
+            return Err(ParseError::missing("pkgver"));
+        }
+
+        Ok(info)
+    }
+}
+
+impl fmt::Display for PkgInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_kv(f, "pkgname", &self.name)?;
+        if let Some(ref base) = self.base {
+            write_kv(f, "pkgbase", base)?;
+        }
+        write_kv(f, "pkgver", &self.version)?;
+        if let Some(ref desc) = self.desc {
+            write_kv(f, "pkgdesc", desc)?;
+        }
+        if let Some(ref url) = self.url {
+            write_kv(f, "url", url)?;
+        }
+        write_kv(f, "builddate", &self.build_date.to_string())?;
+        if let Some(ref packager) = self.packager {
+            write_kv(f, "packager", packager)?;
+        }
+        write_kv(f, "size", &self.size.to_string())?;
+        if let Some(ref arch) = self.arch {
+            write_kv(f, "arch", arch)?;
+        }
+        write_kvs(f, "license", &self.licenses)?;
+        write_kvs(f, "replaces", &self.replaces)?;
+        write_kvs(f, "group", &self.groups)?;
+        write_kvs(f, "conflict", &self.conflicts)?;
+        write_kvs(f, "provides", &self.provides)?;
+        write_kvs(f, "backup", &self.backup)?;
+        write_kvs(f, "depend", &self.depends)?;
+        write_kvs(f, "optdepend", &self.optdepends)?;
+        write_kvs(f, "makedepend", &self.makedepends)?;
+        write_kvs(f, "checkdepend", &self.checkdepends)?;
+        write_kvs(f, "makepkgopt", &self.makepkgopts)?;
+        write_kvs(f, "xdata", &self.xdata)
+    }
+}
+
+impl FromStr for PkgInfo {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<PkgInfo, ParseError> {
+        PkgInfo::parse(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PKGINFO: &str = "# Generated by makepkg 5.1.2
+# using fakeroot version 1.23
+pkgname = pacman
+pkgbase = pacman
+pkgver = 5.1.3-1
+pkgdesc = A library-based package manager with dependency support
+url = https://www.archlinux.org/pacman/
+builddate = 1551404516
+packager = Allan McRae <allan@archlinux.org>
+size = 4825088
+arch = x86_64
+license = GPL
+group = base
+group = base-devel
+backup = etc/pacman.conf
+backup = etc/makepkg.conf
+depend = bash
+depend = glibc
+optdepend = perl-locale-gettext: translation support in makepkg-template
+makedepend = asciidoc
+checkdepend = python2
+xdata = pkgtype=pkg
+";
+
+    #[test]
+    fn test_parse() {
+        let info = PkgInfo::parse(PKGINFO).unwrap();
+        assert_eq!(info.name, "pacman");
+        assert_eq!(info.base.as_ref().unwrap(), "pacman");
+        assert_eq!(info.version, "5.1.3-1");
+        assert_eq!(info.build_date, 1551404516);
+        assert_eq!(info.size, 4825088);
+        assert_eq!(info.groups, vec!["base", "base-devel"]);
+        assert_eq!(info.depends, vec!["bash", "glibc"]);
+        assert_eq!(
+            info.optdepends,
+            vec!["perl-locale-gettext: translation support in makepkg-template"]
+        );
+        assert_eq!(info.xdata, vec!["pkgtype=pkg"]);
+    }
+
+    #[test]
+    fn test_display() {
+        let info = PkgInfo::parse(PKGINFO).unwrap();
+        assert_eq!(PkgInfo::parse(info.to_string()).unwrap(), info);
+    }
+
+    #[test]
+    fn test_parse_err() {
+        assert_eq!(
+            PkgInfo::parse("pkgname = a\nsize = x").unwrap_err().line(),
+            Some(2)
+        );
+        assert_eq!(
+            PkgInfo::parse("pkgname = a\nfoo").unwrap_err().line(),
+            Some(2)
+        );
+        assert_eq!(
+            PkgInfo::parse("pkgname = a").unwrap_err(),
+            ParseError::MissingField("pkgver".to_string())
+        );
+    }
+}
@@ -0,0 +1,504 @@
+use crate::archive::decompress;
+use crate::pkgbuilder::header;
+#[cfg(feature = "openpgp")]
+use crate::PgpSecretKey;
+use crate::{Keyring, PkgArchive, PkgInfo};
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use tar::{Archive, Builder, EntryType};
+
+/// A secret key to create detached signatures with.
+#[derive(Debug, Clone)]
+pub enum SigningKey {
+    /// A key in a gpg home directory, used through gpg.
+    Gpg { keyring: Keyring, key: String },
+    #[cfg(feature = "openpgp")]
+    Pgp(Box<PgpSecretKey>),
+}
+
+fn sig_path(path: &Path) -> PathBuf {
+    let mut sig = path.as_os_str().to_owned();
+    sig.push(".sig");
+    sig.into()
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    tmp.into()
+}
+
+// write to a temporary file and rename it over `path`, so that readers never
+// see a partly written file
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = tmp_path(path);
+    let ret = (|| {
+        let mut file = File::create(&tmp)?;
+        file.write_all(data)?;
+        file.sync_all()
+    })();
+    if let Err(e) = ret {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    fs::rename(&tmp, path)
+}
+
+impl SigningKey {
+    /// Create a binary detached signature of `path`.
+    pub fn sign(&self, path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        match self {
+            SigningKey::Gpg { keyring, key } => keyring.detach_sign(key, path),
+            #[cfg(feature = "openpgp")]
+            SigningKey::Pgp(key) => key.sign(File::open(path)?),
+        }
+    }
+
+    /// Like `sign` but also writes the signature to `path.sig`.
+    pub fn sign_file(&self, path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        let path = path.as_ref();
+        let sig = self.sign(path)?;
+        fs::write(sig_path(path), &sig)?;
+        Ok(sig)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RepoEntry {
+    filename: String,
+    info: PkgInfo,
+    csize: u64,
+    md5sum: String,
+    sha256sum: String,
+    pgpsig: Option<String>,
+}
+
+pub(crate) fn section<S: AsRef<str>>(
+    desc: &mut String,
+    key: &str,
+    values: impl IntoIterator<Item = S>,
+) {
+    let mut values = values.into_iter().peekable();
+    if values.peek().is_none() {
+        return;
+    }
+
+    desc.push('%');
+    desc.push_str(key);
+    desc.push_str("%\n");
+    for value in values {
+        desc.push_str(value.as_ref());
+        desc.push('\n');
+    }
+    desc.push('\n');
+}
+
+fn parse_num<T: std::str::FromStr>(key: &str, value: &str) -> io::Result<T> {
+    value.parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid %{}% in desc: {}", key, value),
+        )
+    })
+}
+
+impl RepoEntry {
+    // a desc file as written by `desc` or repo-add
+    fn parse(desc: &str) -> io::Result<RepoEntry> {
+        let mut entry = RepoEntry {
+            filename: String::new(),
+            info: PkgInfo::default(),
+            csize: 0,
+            md5sum: String::new(),
+            sha256sum: String::new(),
+            pgpsig: None,
+        };
+        let info = &mut entry.info;
+        let mut key = "";
+
+        for line in desc.lines() {
+            if line.is_empty() {
+                key = "";
+                continue;
+            }
+            if key.is_empty() && line.len() > 2 && line.starts_with('%') && line.ends_with('%') {
+                key = &line[1..line.len() - 1];
+                continue;
+            }
+
+            let value = line.to_string();
+            match key {
+                "FILENAME" => entry.filename = value,
+                "NAME" => info.name = value,
+                "BASE" => info.base = Some(value),
+                "VERSION" => info.version = value,
+                "DESC" => info.desc = Some(value),
+                "GROUPS" => info.groups.push(value),
+                "CSIZE" => entry.csize = parse_num(key, &value)?,
+                "ISIZE" => info.size = parse_num(key, &value)?,
+                "MD5SUM" => entry.md5sum = value,
+                "SHA256SUM" => entry.sha256sum = value,
+                "PGPSIG" => entry.pgpsig = Some(value),
+                "URL" => info.url = Some(value),
+                "LICENSE" => info.licenses.push(value),
+                "ARCH" => info.arch = Some(value),
+                "BUILDDATE" => info.build_date = parse_num(key, &value)?,
+                "PACKAGER" => info.packager = Some(value),
+                "REPLACES" => info.replaces.push(value),
+                "CONFLICTS" => info.conflicts.push(value),
+                "PROVIDES" => info.provides.push(value),
+                "DEPENDS" => info.depends.push(value),
+                "OPTDEPENDS" => info.optdepends.push(value),
+                "MAKEDEPENDS" => info.makedepends.push(value),
+                "CHECKDEPENDS" => info.checkdepends.push(value),
+                _ => (),
+            }
+        }
+
+        if entry.filename.is_empty() || entry.info.name.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "desc has no %FILENAME% or %NAME%",
+            ));
+        }
+        Ok(entry)
+    }
+
+    // the same fields and order as repo-add
+    fn desc(&self) -> String {
+        let info = &self.info;
+        let mut desc = String::new();
+
+        section(&mut desc, "FILENAME", Some(&self.filename));
+        section(&mut desc, "NAME", Some(&info.name));
+        section(&mut desc, "BASE", &info.base);
+        section(&mut desc, "VERSION", Some(&info.version));
+        section(&mut desc, "DESC", &info.desc);
+        section(&mut desc, "GROUPS", &info.groups);
+        section(&mut desc, "CSIZE", Some(self.csize.to_string()));
+        section(&mut desc, "ISIZE", Some(info.size.to_string()));
+        section(&mut desc, "MD5SUM", Some(&self.md5sum));
+        section(&mut desc, "SHA256SUM", Some(&self.sha256sum));
+        section(&mut desc, "PGPSIG", &self.pgpsig);
+        section(&mut desc, "URL", &info.url);
+        section(&mut desc, "LICENSE", &info.licenses);
+        section(&mut desc, "ARCH", &info.arch);
+        section(&mut desc, "BUILDDATE", Some(info.build_date.to_string()));
+        section(&mut desc, "PACKAGER", &info.packager);
+        section(&mut desc, "REPLACES", &info.replaces);
+        section(&mut desc, "CONFLICTS", &info.conflicts);
+        section(&mut desc, "PROVIDES", &info.provides);
+        section(&mut desc, "DEPENDS", &info.depends);
+        section(&mut desc, "OPTDEPENDS", &info.optdepends);
+        section(&mut desc, "MAKEDEPENDS", &info.makedepends);
+        section(&mut desc, "CHECKDEPENDS", &info.checkdepends);
+
+        desc
+    }
+}
+
+/// Writes a repository database from package files, like repo-add.
+#[derive(Debug, Clone)]
+pub struct RepoWriter {
+    dir: PathBuf,
+    name: String,
+    signing_key: Option<SigningKey>,
+    pkgs: BTreeMap<String, RepoEntry>,
+}
+
+fn replace_symlink(target: &str, link: &Path) -> io::Result<()> {
+    let tmp = tmp_path(link);
+    if tmp.symlink_metadata().is_ok() {
+        fs::remove_file(&tmp)?;
+    }
+    symlink(target, &tmp)?;
+    fs::rename(&tmp, link)
+}
+
+impl RepoWriter {
+    /// Start an empty database, which replaces any existing one on `write`.
+    /// Use `open` to add to an existing database.
+    pub fn new(dir: impl Into<PathBuf>, name: impl Into<String>) -> RepoWriter {
+        RepoWriter {
+            dir: dir.into(),
+            name: name.into(),
+            signing_key: None,
+            pkgs: BTreeMap::new(),
+        }
+    }
+
+    /// Load the packages of `<name>.db` in `dir`, or start an empty database
+    /// if there is none, like repo-add.
+    pub fn open(dir: impl Into<PathBuf>, name: impl Into<String>) -> io::Result<RepoWriter> {
+        let mut repo = RepoWriter::new(dir, name);
+        let path = repo.dir.join(format!("{}.db", repo.name));
+        let reader = match decompress(&path) {
+            Ok(reader) => reader,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(repo),
+            Err(e) => return Err(e),
+        };
+
+        let mut archive = Archive::new(reader);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if entry.path()?.file_name() != Some("desc".as_ref()) {
+                continue;
+            }
+
+            let mut desc = String::new();
+            entry.read_to_string(&mut desc)?;
+            let entry = RepoEntry::parse(&desc)?;
+            repo.pkgs.insert(entry.info.name.clone(), entry);
+        }
+
+        Ok(repo)
+    }
+
+    /// Sign the database and added packages that are not signed yet.
+    pub fn signing_key(mut self, key: SigningKey) -> RepoWriter {
+        self.signing_key = Some(key);
+        self
+    }
+
+    /// Add the package file at `path`, replacing any package of the same
+    /// name. The signature in `path.sig` is embedded in the database,
+    /// creating it first if there is none and a signing key is set.
+    pub fn add(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let info = PkgArchive::open(path)?.pkginfo()?;
+        let filename = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid file name"))?
+            .to_string();
+
+        let mut file = File::open(path)?;
+        let mut md5 = Md5::new();
+        let mut sha256 = Sha256::new();
+        let mut csize = 0;
+        let mut buf = [0; 64 * 1024];
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            md5.update(&buf[..n]);
+            sha256.update(&buf[..n]);
+            csize += n as u64;
+        }
+
+        let sig = match (fs::read(sig_path(path)), &self.signing_key) {
+            (Ok(sig), _) => Some(sig),
+            (Err(e), Some(key)) if e.kind() == io::ErrorKind::NotFound => {
+                Some(key.sign_file(path)?)
+            }
+            (Err(e), None) if e.kind() == io::ErrorKind::NotFound => None,
+            (Err(e), _) => return Err(e),
+        };
+
+        let entry = RepoEntry {
+            filename,
+            csize,
+            md5sum: format!("{:x}", md5.finalize()),
+            sha256sum: format!("{:x}", sha256.finalize()),
+            pgpsig: sig.map(base64::encode),
+            info,
+        };
+        self.pkgs.insert(entry.info.name.clone(), entry);
+        Ok(())
+    }
+
+    /// Returns false if there was no package named `name`.
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.pkgs.remove(name).is_some()
+    }
+
+    pub fn pkgs(&self) -> impl Iterator<Item = &PkgInfo> {
+        self.pkgs.values().map(|e| &e.info)
+    }
+
+    /// Write `<name>.db.tar.gz` and the `<name>.db` link to it, and their
+    /// signatures if a signing key is set. Files are replaced by renaming,
+    /// so readers see either the old or the new database.
+    pub fn write(&self) -> io::Result<()> {
+        let mtime = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+
+        let mut builder = Builder::new(GzEncoder::new(Vec::new(), Compression::best()));
+        for entry in self.pkgs.values() {
+            let dir = format!("{}-{}", entry.info.name, entry.info.version);
+            let mut dir_header = header(EntryType::Directory, 0o755, 0, mtime);
+            builder.append_data(&mut dir_header, &dir, io::empty())?;
+
+            let desc = entry.desc();
+            let mut desc_header = header(EntryType::Regular, 0o644, desc.len() as u64, mtime);
+            builder.append_data(&mut desc_header, format!("{}/desc", dir), desc.as_bytes())?;
+        }
+        let data = builder.into_inner()?.finish()?;
+
+        let db = format!("{}.db.tar.gz", self.name);
+        let path = self.dir.join(&db);
+        write_atomic(&path, &data)?;
+        replace_symlink(&db, &self.dir.join(format!("{}.db", self.name)))?;
+
+        let sig = self.dir.join(format!("{}.db.sig", self.name));
+        match self.signing_key {
+            Some(ref key) => {
+                write_atomic(&sig_path(&path), &key.sign(&path)?)?;
+                replace_symlink(&format!("{}.sig", db), &sig)?;
+            }
+            None => {
+                // an old signature would no longer match
+                for path in &[sig_path(&path), sig] {
+                    if path.symlink_metadata().is_ok() {
+                        fs::remove_file(path)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PkgBuilder;
+
+    use flate2::read::GzDecoder;
+    use tar::Archive;
+
+    fn build(dir: &Path, name: &str, version: &str) -> PathBuf {
+        let stage = tempfile::tempdir().unwrap();
+        fs::write(stage.path().join("file"), name).unwrap();
+        let info = PkgInfo {
+            name: name.to_string(),
+            version: version.to_string(),
+            arch: Some("any".to_string()),
+            depends: vec!["glibc".to_string()],
+            ..PkgInfo::default()
+        };
+
+        let path = dir.join(format!("{}-{}-any.pkg.tar.zst", name, version));
+        PkgBuilder::new(stage.path(), info)
+            .source_date_epoch(1551404516)
+            .build(&path)
+            .unwrap();
+        path
+    }
+
+    fn read_db(path: &Path) -> BTreeMap<String, String> {
+        let mut archive = Archive::new(GzDecoder::new(File::open(path).unwrap()));
+        let mut files = BTreeMap::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().into_owned();
+            let mut data = String::new();
+            entry.read_to_string(&mut data).unwrap();
+            files.insert(path, data);
+        }
+        files
+    }
+
+    #[test]
+    fn test_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let foo = build(dir.path(), "foo", "1.0-1");
+        build(dir.path(), "foo", "1.1-1");
+        let bar = build(dir.path(), "bar", "1.0-1");
+
+        let mut repo = RepoWriter::new(dir.path(), "test");
+        repo.add(&foo).unwrap();
+        repo.add(dir.path().join("foo-1.1-1-any.pkg.tar.zst"))
+            .unwrap();
+        repo.add(&bar).unwrap();
+        assert!(repo.remove("bar"));
+        assert!(!repo.remove("bar"));
+        repo.write().unwrap();
+
+        let files = read_db(&dir.path().join("test.db"));
+        assert_eq!(
+            files.keys().collect::<Vec<_>>(),
+            vec!["foo-1.1-1", "foo-1.1-1/desc"]
+        );
+        let desc = &files["foo-1.1-1/desc"];
+        assert!(desc.starts_with("%FILENAME%\nfoo-1.1-1-any.pkg.tar.zst\n\n%NAME%\nfoo\n\n"));
+        assert!(desc.contains("%ARCH%\nany\n\n"));
+        assert!(desc.ends_with("%DEPENDS%\nglibc\n\n"));
+        assert!(!desc.contains("%PGPSIG%"));
+        assert!(!dir.path().join("test.db.sig").exists());
+        assert_eq!(RepoEntry::parse(desc).unwrap().desc(), *desc);
+    }
+
+    #[test]
+    fn test_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let foo = build(dir.path(), "foo", "1.0-1");
+        let bar = build(dir.path(), "bar", "1.0-1");
+
+        let mut repo = RepoWriter::open(dir.path(), "test").unwrap();
+        assert_eq!(repo.pkgs().count(), 0);
+        repo.add(&foo).unwrap();
+        repo.write().unwrap();
+
+        let mut repo = RepoWriter::open(dir.path(), "test").unwrap();
+        repo.add(&bar).unwrap();
+        repo.write().unwrap();
+
+        let files = read_db(&dir.path().join("test.db"));
+        assert_eq!(
+            files.keys().collect::<Vec<_>>(),
+            vec!["bar-1.0-1", "bar-1.0-1/desc", "foo-1.0-1", "foo-1.0-1/desc"]
+        );
+        assert!(!dir.path().join("test.db.tar.gz.tmp").exists());
+
+        let repo = RepoWriter::open(dir.path(), "test").unwrap();
+        let foo = repo.pkgs().find(|p| p.name == "foo").unwrap();
+        assert_eq!(foo.depends, vec!["glibc"]);
+    }
+
+    #[cfg(feature = "openpgp")]
+    #[test]
+    fn test_sign() {
+        use crate::PgpKeyring;
+
+        let dir = tempfile::tempdir().unwrap();
+        let foo = build(dir.path(), "foo", "1.0-1");
+        let key = PgpSecretKey::load("tests/pgp/signer-ed.sec").unwrap();
+        let keyring = PgpKeyring::load("tests/pgp/signer-ed.sec").unwrap();
+
+        let mut repo =
+            RepoWriter::new(dir.path(), "test").signing_key(SigningKey::Pgp(Box::new(key)));
+        repo.add(&foo).unwrap();
+        repo.write().unwrap();
+
+        assert!(keyring.verify_file(&foo).unwrap().0);
+        assert!(keyring.verify_file(dir.path().join("test.db")).unwrap().0);
+
+        let files = read_db(&dir.path().join("test.db"));
+        let desc = &files["foo-1.0-1/desc"];
+        let sig = desc.split("%PGPSIG%\n").nth(1).unwrap();
+        let sig = sig.lines().next().unwrap();
+        let (valid, _) = keyring
+            .verify_base64(File::open(&foo).unwrap(), sig)
+            .unwrap();
+        assert!(valid);
+
+        RepoWriter::new(dir.path(), "test").write().unwrap();
+        assert!(!dir.path().join("test.db.sig").exists());
+        assert!(!dir.path().join("test.db.tar.gz.sig").exists());
+    }
+}
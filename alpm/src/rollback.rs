@@ -0,0 +1,291 @@
+use crate::{Alpm, CachedPkg, LogEvent, LogTransaction, SigLevel};
+
+use std::collections::BTreeMap;
+use std::io;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RollbackAction {
+    /// Replace version `from` with the earlier installed `to`. This may be an
+    /// upgrade if the transactions being rolled back downgraded the package.
+    Downgrade {
+        name: String,
+        from: String,
+        to: String,
+    },
+    Reinstall {
+        name: String,
+        version: String,
+    },
+    Remove {
+        name: String,
+        version: String,
+    },
+}
+
+impl RollbackAction {
+    pub fn name(&self) -> &str {
+        match self {
+            RollbackAction::Downgrade { name, .. } => name,
+            RollbackAction::Reinstall { name, .. } => name,
+            RollbackAction::Remove { name, .. } => name,
+        }
+    }
+
+    /// The version that has to be installed, if any.
+    pub fn target_version(&self) -> Option<&str> {
+        match self {
+            RollbackAction::Downgrade { to, .. } => Some(to),
+            RollbackAction::Reinstall { version, .. } => Some(version),
+            RollbackAction::Remove { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RollbackStep {
+    pub action: RollbackAction,
+    /// The cached package file needed for this step. `None` for removals and
+    /// for packages missing from the cache.
+    pub pkg: Option<CachedPkg>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct RollbackPlan {
+    pub steps: Vec<RollbackStep>,
+}
+
+impl RollbackPlan {
+    /// Compute the plan that undoes `transactions`, in the order they were
+    /// logged, using the package files found in `cache`.
+    pub fn new(transactions: &[LogTransaction], cache: &[CachedPkg]) -> RollbackPlan {
+        // name -> (version before the transactions, version after them)
+        let mut states: BTreeMap<&str, (Option<&str>, Option<&str>)> = BTreeMap::new();
+
+        for event in transactions.iter().flat_map(|t| t.changes()) {
+            let (name, before, after) = match event {
+                LogEvent::Installed { name, version } => (name, None, Some(version)),
+                LogEvent::Reinstalled { name, version } => (name, Some(version), Some(version)),
+                LogEvent::Removed { name, version } => (name, Some(version), None),
+                LogEvent::Upgraded {
+                    name,
+                    old_version,
+                    new_version,
+                }
+                | LogEvent::Downgraded {
+                    name,
+                    old_version,
+                    new_version,
+                } => (name, Some(old_version), Some(new_version)),
+                _ => continue,
+            };
+
+            let state = states
+                .entry(name.as_str())
+                .or_insert((before.map(|v| v.as_str()), None));
+            state.1 = after.map(|v| v.as_str());
+        }
+
+        let steps = states
+            .into_iter()
+            .filter_map(|(name, state)| {
+                let name = name.to_string();
+                let action = match state {
+                    (None, Some(version)) => RollbackAction::Remove {
+                        name,
+                        version: version.to_string(),
+                    },
+                    (Some(version), None) => RollbackAction::Reinstall {
+                        name,
+                        version: version.to_string(),
+                    },
+                    (Some(to), Some(from)) if to != from => RollbackAction::Downgrade {
+                        name,
+                        from: from.to_string(),
+                        to: to.to_string(),
+                    },
+                    _ => return None,
+                };
+
+                let pkg = action.target_version().and_then(|version| {
+                    cache
+                        .iter()
+                        .find(|p| p.name == action.name() && p.version == version)
+                        .cloned()
+                });
+
+                Some(RollbackStep { action, pkg })
+            })
+            .collect();
+
+        RollbackPlan { steps }
+    }
+
+    /// Steps that need a package file that is not in the cache.
+    pub fn missing(&self) -> impl Iterator<Item = &RollbackAction> {
+        self.steps
+            .iter()
+            .filter(|s| s.pkg.is_none() && s.action.target_version().is_some())
+            .map(|s| &s.action)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.missing().next().is_none()
+    }
+}
+
+impl Alpm {
+    /// Plan a rollback of every logged transaction started at or after `time`.
+    /// Transactions that did not complete are included as well, with the
+    /// changes they logged before they were interrupted.
+    pub fn rollback_plan_since(&self, time: i64) -> io::Result<RollbackPlan> {
+        let transactions = crate::log_transactions(&self.read_log()?);
+        let start = transactions
+            .iter()
+            .position(|t| t.start >= time)
+            .unwrap_or(transactions.len());
+
+        Ok(RollbackPlan::new(
+            &transactions[start..],
+            &self.cached_pkgs()?,
+        ))
+    }
+
+    /// Plan a rollback of a single transaction, as indexed by `log_transactions`.
+    /// An interrupted transaction is rolled back as far as it got.
+    pub fn rollback_plan_for(&self, id: usize) -> io::Result<RollbackPlan> {
+        let transactions = crate::log_transactions(&self.read_log()?);
+        let transaction = transactions.get(id).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no transaction with id {}", id),
+            )
+        })?;
+
+        Ok(RollbackPlan::new(
+            std::slice::from_ref(transaction),
+            &self.cached_pkgs()?,
+        ))
+    }
+
+    /// Add the steps of `plan` to the current transaction. Fails with
+    /// `Error::PkgNotFound` if a needed package file is missing.
+    pub fn trans_add_rollback(&self, plan: &RollbackPlan, level: SigLevel) -> crate::Result<()> {
+        if !plan.is_complete() {
+            return Err(crate::Error::PkgNotFound);
+        }
+
+        for step in &plan.steps {
+            match step.pkg {
+                Some(ref pkg) => {
+                    let path = pkg.path.to_str().ok_or(crate::Error::WrongArgs)?;
+                    let pkg = self.pkg_load(path, true, level)?;
+                    self.trans_add_pkg(pkg)?;
+                }
+                None => {
+                    let pkg = self.localdb().pkg(step.action.name())?;
+                    self.trans_remove_pkg(pkg)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{log_transactions, parse_log};
+
+    use std::fs;
+    use std::path::PathBuf;
+
+    const LOG: &str = "\
+[2019-06-10T10:00:00+0000] [ALPM] transaction started
+[2019-06-10T10:00:00+0000] [ALPM] installed vifm (0.10-1)
+[2019-06-10T10:00:00+0000] [ALPM] transaction completed
+[2019-06-11T10:00:00+0000] [ALPM] transaction started
+[2019-06-11T10:00:00+0000] [ALPM] upgraded linux (5.1.7.arch1-1 -> 5.1.8.arch1-1)
+[2019-06-11T10:00:00+0000] [ALPM] removed pacman-contrib (1.1.0-1)
+[2019-06-11T10:00:00+0000] [ALPM] installed foo (1.0-1)
+[2019-06-11T10:00:00+0000] [ALPM] reinstalled bash (5.0.007-1)
+[2019-06-11T10:00:00+0000] [ALPM] transaction completed
+[2019-06-12T10:00:00+0000] [ALPM] transaction started
+[2019-06-12T10:00:00+0000] [ALPM] upgraded linux (5.1.8.arch1-1 -> 5.1.9.arch1-1)
+[2019-06-12T10:00:00+0000] [ALPM] removed foo (1.0-1)
+[2019-06-12T10:00:00+0000] [ALPM] transaction completed
+[2019-06-13T10:00:00+0000] [ALPM] transaction started
+[2019-06-13T10:00:00+0000] [ALPM] installed vim (8.1-1)
+[2019-06-13T10:00:00+0000] [ALPM] transaction interrupted
+";
+
+    fn cached(filename: &str) -> CachedPkg {
+        CachedPkg::from_path(PathBuf::from("/var/cache/pacman/pkg").join(filename)).unwrap()
+    }
+
+    #[test]
+    fn test_rollback_plan() {
+        let trans = log_transactions(&parse_log(LOG));
+        let cache = vec![
+            cached("linux-5.1.7.arch1-1-x86_64.pkg.tar.xz"),
+            cached("linux-5.1.8.arch1-1-x86_64.pkg.tar.xz"),
+        ];
+
+        let plan = RollbackPlan::new(&trans[1..3], &cache);
+        let actions = plan.steps.iter().map(|s| &s.action).collect::<Vec<_>>();
+        assert_eq!(
+            actions,
+            vec![
+                &RollbackAction::Downgrade {
+                    name: "linux".into(),
+                    from: "5.1.9.arch1-1".into(),
+                    to: "5.1.7.arch1-1".into(),
+                },
+                &RollbackAction::Reinstall {
+                    name: "pacman-contrib".into(),
+                    version: "1.1.0-1".into(),
+                },
+            ]
+        );
+        assert_eq!(plan.steps[0].pkg, Some(cache[0].clone()));
+        assert_eq!(plan.missing().count(), 1);
+        assert!(!plan.is_complete());
+
+        let plan = RollbackPlan::new(&trans[..1], &cache);
+        assert_eq!(
+            plan.steps,
+            vec![RollbackStep {
+                action: RollbackAction::Remove {
+                    name: "vifm".into(),
+                    version: "0.10-1".into(),
+                },
+                pkg: None,
+            }]
+        );
+        assert!(plan.is_complete());
+    }
+
+    #[test]
+    fn test_rollback_interrupted() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("pacman.log");
+        fs::write(&log, LOG).unwrap();
+
+        let mut handle = Alpm::new("/", "tests/db").unwrap();
+        handle.set_logfile(log.to_str().unwrap()).unwrap();
+        handle.set_cachedirs(dir.path().to_str()).unwrap();
+
+        let vim = vec![RollbackStep {
+            action: RollbackAction::Remove {
+                name: "vim".into(),
+                version: "8.1-1".into(),
+            },
+            pkg: None,
+        }];
+        // 2019-06-13T10:00:00
+        let plan = handle.rollback_plan_since(1560420000).unwrap();
+        assert_eq!(plan.steps, vim);
+        let plan = handle.rollback_plan_for(3).unwrap();
+        assert_eq!(plan.steps, vim);
+    }
+}
@@ -0,0 +1,330 @@
+use crate::{Alpm, Error, Fixture, FixturePkg, FixtureRoot, PackageReason, SigLevel, TransFlag};
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The transaction a scenario runs.
+#[derive(Debug, Clone)]
+pub enum Action {
+    /// Install packages from the sync databases, `repo/name` or anything
+    /// satisfying a dependency, like `pacman -S`.
+    Sync(Vec<String>),
+    /// Upgrade every package, like `pacman -Su`.
+    Sysupgrade,
+    /// Install package files, like `pacman -U`.
+    Upgrade(Vec<FixturePkg>),
+    /// Remove installed packages, like `pacman -R`.
+    Remove(Vec<String>),
+}
+
+/// An expected result of a scenario, checked after the transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    Success,
+    /// The transaction failed with this error.
+    Fails(Error),
+    PkgInstalled(String),
+    PkgNotInstalled(String),
+    PkgVersion(String, String),
+    PkgReason(String, PackageReason),
+    FileExists(String),
+    FileMissing(String),
+    FileContents(String, Vec<u8>),
+    /// `path.pacnew` was created.
+    Pacnew(String),
+    /// `path.pacsave` was created.
+    Pacsave(String),
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rule::Success => f.write_str("SUCCESS"),
+            Rule::Fails(err) => write!(f, "FAILS={:?}", err),
+            Rule::PkgInstalled(name) => write!(f, "PKG_EXIST={}", name),
+            Rule::PkgNotInstalled(name) => write!(f, "!PKG_EXIST={}", name),
+            Rule::PkgVersion(name, ver) => write!(f, "PKG_VERSION={}|{}", name, ver),
+            Rule::PkgReason(name, reason) => write!(f, "PKG_REASON={}|{:?}", name, reason),
+            Rule::FileExists(path) => write!(f, "FILE_EXIST={}", path),
+            Rule::FileMissing(path) => write!(f, "!FILE_EXIST={}", path),
+            Rule::FileContents(path, _) => write!(f, "FILE_CONTENTS={}", path),
+            Rule::Pacnew(path) => write!(f, "FILE_PACNEW={}", path),
+            Rule::Pacsave(path) => write!(f, "FILE_PACSAVE={}", path),
+        }
+    }
+}
+
+/// A transaction test in the style of pacman's pactest: initial packages
+/// and files, a transaction and the expected results.
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    name: String,
+    fixture: Fixture,
+    files: Vec<(String, Vec<u8>)>,
+    flags: TransFlag,
+    action: Option<Action>,
+    rules: Vec<Rule>,
+}
+
+/// The outcome of `Scenario::run`.
+#[derive(Debug)]
+pub struct ScenarioResult {
+    pub name: String,
+    pub fixture: FixtureRoot,
+    /// The error the transaction failed with, if any.
+    pub error: Option<Error>,
+    pub rules: Vec<(Rule, bool)>,
+}
+
+impl ScenarioResult {
+    pub fn passed(&self) -> bool {
+        self.rules.iter().all(|(_, passed)| *passed)
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &Rule> {
+        self.rules
+            .iter()
+            .filter(|(_, passed)| !passed)
+            .map(|(rule, _)| rule)
+    }
+}
+
+impl fmt::Display for ScenarioResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "scenario: {}", self.name)?;
+        if let Some(err) = self.error {
+            writeln!(f, "error: {}", err)?;
+        }
+        for (rule, passed) in &self.rules {
+            let status = if *passed { "PASS" } else { "FAIL" };
+            writeln!(f, "[{}] {}", status, rule)?;
+        }
+        Ok(())
+    }
+}
+
+fn add_targets(handle: &Alpm, root: &FixtureRoot, action: &Action) -> crate::Result<()> {
+    match action {
+        Action::Sync(targets) => {
+            for target in targets {
+                let pkg = match target.find('/') {
+                    Some(i) => handle
+                        .syncdbs()
+                        .find(|db| db.name() == &target[..i])
+                        .and_then(|db| db.pkg(&target[i + 1..]).ok()),
+                    None => handle.syncdbs().find_satisfier(target),
+                };
+                handle.trans_add_pkg(pkg.ok_or(Error::PkgNotFound)?)?;
+            }
+        }
+        Action::Sysupgrade => handle.trans_sysupgrade(false)?,
+        Action::Upgrade(pkgs) => {
+            for pkg in pkgs {
+                let path = root.pkg_path(pkg);
+                let path = path.to_str().ok_or(Error::WrongArgs)?;
+                handle.trans_add_pkg(handle.pkg_load(path, true, SigLevel::NONE)?)?;
+            }
+        }
+        Action::Remove(names) => {
+            for name in names {
+                handle.trans_remove_pkg(handle.localdb().pkg(name.as_str())?)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn transact(
+    mut handle: Alpm,
+    root: &FixtureRoot,
+    flags: TransFlag,
+    action: &Action,
+) -> crate::Result<()> {
+    handle.trans_init(flags)?;
+    let ret = add_targets(&handle, root, action).and_then(|_| {
+        handle.trans_prepare().map_err(|(_, e)| e)?;
+        handle.trans_commit().map_err(|(_, e)| e)?;
+        Ok(())
+    });
+    let release = handle.trans_release();
+    ret?;
+    release
+}
+
+fn check(rule: &Rule, handle: Option<&Alpm>, root: &Path, error: Option<Error>) -> bool {
+    let pkg = |name: &str| handle.and_then(|h| h.localdb().pkg(name).ok());
+
+    match rule {
+        Rule::Success => error.is_none(),
+        Rule::Fails(err) => error == Some(*err),
+        Rule::PkgInstalled(name) => pkg(name).is_some(),
+        Rule::PkgNotInstalled(name) => handle.is_some() && pkg(name).is_none(),
+        Rule::PkgVersion(name, ver) => matches!(pkg(name), Some(p) if *p.version() == **ver),
+        Rule::PkgReason(name, reason) => matches!(pkg(name), Some(p) if p.reason() == *reason),
+        Rule::FileExists(path) => root.join(path).symlink_metadata().is_ok(),
+        Rule::FileMissing(path) => root.join(path).symlink_metadata().is_err(),
+        Rule::FileContents(path, data) => matches!(fs::read(root.join(path)), Ok(d) if d == *data),
+        Rule::Pacnew(path) => root.join(format!("{}.pacnew", path)).exists(),
+        Rule::Pacsave(path) => root.join(format!("{}.pacsave", path)).exists(),
+    }
+}
+
+impl Scenario {
+    pub fn new(name: impl Into<String>) -> Scenario {
+        Scenario {
+            name: name.into(),
+            fixture: Fixture::new(),
+            files: Vec::new(),
+            flags: TransFlag::NONE,
+            action: None,
+            rules: Vec::new(),
+        }
+    }
+
+    /// A package installed before the transaction.
+    pub fn local(mut self, pkg: FixturePkg) -> Scenario {
+        self.fixture = self.fixture.local(pkg);
+        self
+    }
+
+    pub fn sync(mut self, repo: impl Into<String>, pkg: FixturePkg) -> Scenario {
+        self.fixture = self.fixture.sync(repo, pkg);
+        self
+    }
+
+    /// A file in the root before the transaction, written after the local
+    /// packages are installed so it can replace their files.
+    pub fn filesystem(mut self, path: impl Into<String>, data: impl Into<Vec<u8>>) -> Scenario {
+        self.files.push((path.into(), data.into()));
+        self
+    }
+
+    pub fn flags(mut self, flags: TransFlag) -> Scenario {
+        self.flags = flags;
+        self
+    }
+
+    pub fn action(mut self, action: Action) -> Scenario {
+        if let Action::Upgrade(ref pkgs) = action {
+            for pkg in pkgs {
+                self.fixture = self.fixture.pkg(pkg.clone());
+            }
+        }
+        self.action = Some(action);
+        self
+    }
+
+    pub fn rule(mut self, rule: Rule) -> Scenario {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Build the fixture, run the transaction and check every rule. Only
+    /// fails if the fixture could not be built, libalpm errors are checked
+    /// by the rules.
+    pub fn run(&self) -> io::Result<ScenarioResult> {
+        let fixture = self.fixture.build()?;
+        let root = fixture.root();
+
+        for (path, data) in &self.files {
+            let path = root.join(path.trim_start_matches('/'));
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, data)?;
+        }
+
+        let ret = match self.action {
+            Some(ref action) => fixture
+                .handle()
+                .and_then(|handle| transact(handle, &fixture, self.flags, action)),
+            None => Ok(()),
+        };
+        let error = ret.err();
+
+        // read the result back from disk like a new pacman process would
+        let handle = fixture.handle().ok();
+        let rules = self
+            .rules
+            .iter()
+            .map(|rule| (rule.clone(), check(rule, handle.as_ref(), &root, error)))
+            .collect();
+        drop(handle);
+
+        Ok(ScenarioResult {
+            name: self.name.clone(),
+            fixture,
+            error,
+            rules,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rule_display() {
+        let rule = Rule::PkgVersion("foo".into(), "1.0-1".into());
+        assert_eq!(rule.to_string(), "PKG_VERSION=foo|1.0-1");
+        assert_eq!(
+            Rule::PkgNotInstalled("foo".into()).to_string(),
+            "!PKG_EXIST=foo"
+        );
+        assert_eq!(
+            Rule::Fails(Error::UnsatisfiedDeps).to_string(),
+            "FAILS=UnsatisfiedDeps"
+        );
+    }
+
+    #[test]
+    fn test_pacnew() {
+        let result = Scenario::new("upgrade a modified backup file")
+            .local(FixturePkg::new("foo", "1.0-1").backup("etc/foo.conf", "foo = 1\n"))
+            .sync(
+                "core",
+                FixturePkg::new("foo", "1.1-1").backup("etc/foo.conf", "foo = 2\n"),
+            )
+            .filesystem("etc/foo.conf", "foo = 3\n")
+            .action(Action::Sysupgrade)
+            .rule(Rule::Success)
+            .rule(Rule::PkgVersion("foo".into(), "1.1-1".into()))
+            .rule(Rule::FileContents(
+                "etc/foo.conf".into(),
+                b"foo = 3\n".to_vec(),
+            ))
+            .rule(Rule::Pacnew("etc/foo.conf".into()))
+            .run()
+            .unwrap();
+        assert!(result.passed(), "{}", result);
+    }
+
+    #[test]
+    fn test_unsatisfied_deps() {
+        let result = Scenario::new("install with a missing dependency")
+            .sync("core", FixturePkg::new("foo", "1.0-1").depends("bar"))
+            .action(Action::Sync(vec!["foo".into()]))
+            .rule(Rule::Fails(Error::UnsatisfiedDeps))
+            .rule(Rule::PkgNotInstalled("foo".into()))
+            .run()
+            .unwrap();
+        assert!(result.passed(), "{}", result);
+    }
+
+    #[test]
+    fn test_remove() {
+        let result = Scenario::new("remove a package")
+            .local(FixturePkg::new("foo", "1.0-1").file("usr/share/foo", "foo"))
+            .action(Action::Remove(vec!["foo".into()]))
+            .rule(Rule::Success)
+            .rule(Rule::PkgNotInstalled("foo".into()))
+            .rule(Rule::FileMissing("usr/share/foo".into()))
+            .run()
+            .unwrap();
+        assert!(result.passed(), "{}", result);
+    }
+}
@@ -0,0 +1,335 @@
+use crate::{PackageFrom, Pkg};
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Ord, PartialOrd, Hash)]
+pub enum ScriptletFunction {
+    PreInstall,
+    PostInstall,
+    PreUpgrade,
+    PostUpgrade,
+    PreRemove,
+    PostRemove,
+}
+
+impl ScriptletFunction {
+    pub const ALL: [ScriptletFunction; 6] = [
+        ScriptletFunction::PreInstall,
+        ScriptletFunction::PostInstall,
+        ScriptletFunction::PreUpgrade,
+        ScriptletFunction::PostUpgrade,
+        ScriptletFunction::PreRemove,
+        ScriptletFunction::PostRemove,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ScriptletFunction::PreInstall => "pre_install",
+            ScriptletFunction::PostInstall => "post_install",
+            ScriptletFunction::PreUpgrade => "pre_upgrade",
+            ScriptletFunction::PostUpgrade => "post_upgrade",
+            ScriptletFunction::PreRemove => "pre_remove",
+            ScriptletFunction::PostRemove => "post_remove",
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<ScriptletFunction> {
+        ScriptletFunction::ALL
+            .iter()
+            .copied()
+            .find(|f| f.name() == s)
+    }
+}
+
+impl fmt::Display for ScriptletFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Scriptlet {
+    content: String,
+}
+
+fn defined_function(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    let (keyword, line) = match line.strip_prefix("function ") {
+        Some(line) => (true, line.trim_start()),
+        None => (false, line),
+    };
+    let end = line
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(line.len());
+    let (name, rest) = line.split_at(end);
+
+    let rest = rest.trim_start();
+    if name.is_empty() || !(rest.starts_with("()") || keyword && rest.starts_with('{')) {
+        return None;
+    }
+
+    Some(name)
+}
+
+// a single quoted shell word
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+impl Scriptlet {
+    pub fn new(content: impl Into<String>) -> Scriptlet {
+        Scriptlet {
+            content: content.into(),
+        }
+    }
+
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    pub fn function_names(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        for name in self.content.lines().filter_map(defined_function) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    pub fn functions(&self) -> Vec<ScriptletFunction> {
+        let names = self.function_names();
+        ScriptletFunction::ALL
+            .iter()
+            .copied()
+            .filter(|f| names.contains(&f.name()))
+            .collect()
+    }
+
+    pub fn defines(&self, func: ScriptletFunction) -> bool {
+        self.function_names().contains(&func.name())
+    }
+
+    /// Run `func` in a sandbox chrooted to `root`, the way libalpm runs it
+    /// during a transaction. `args` are the new version and, for upgrades,
+    /// the old one.
+    ///
+    /// The scriptlet is run by unshare(1) in new user, mount, pid, network,
+    /// ipc and uts namespaces. It is root in its user namespace, which maps to
+    /// the calling user, or to nobody when called as root, so it can not
+    /// change anything the caller could not and nothing owned by root. This
+    /// needs unshare and setpriv from util-linux, unprivileged user
+    /// namespaces and a shell in `root`.
+    pub fn run(
+        &self,
+        root: impl AsRef<Path>,
+        func: ScriptletFunction,
+        args: &[&str],
+    ) -> io::Result<ExitStatus> {
+        let root = root.as_ref();
+        fs::create_dir_all(root.join("tmp"))?;
+        let tmpdir = tempfile::Builder::new()
+            .prefix("alpm_")
+            .tempdir_in(root.join("tmp"))?;
+        // nobody has to be able to read it
+        fs::set_permissions(tmpdir.path(), fs::Permissions::from_mode(0o755))?;
+        fs::write(tmpdir.path().join(".INSTALL"), &self.content)?;
+
+        let script = Path::new("/tmp")
+            .join(tmpdir.path().file_name().unwrap())
+            .join(".INSTALL");
+        let mut cmd = format!(". {}; {}", quote(&script.to_string_lossy()), func.name());
+        for arg in args {
+            cmd.push(' ');
+            cmd.push_str(&quote(arg));
+        }
+
+        // drop root before creating the namespaces so root in them maps to
+        // nobody instead of root
+        let mut unshare = if fs::metadata("/proc/self")?.uid() == 0 {
+            let mut setpriv = Command::new("setpriv");
+            setpriv.args([
+                "--reuid=65534",
+                "--regid=65534",
+                "--clear-groups",
+                "unshare",
+            ]);
+            setpriv
+        } else {
+            Command::new("unshare")
+        };
+        unshare.args([
+            "--user",
+            "--map-root-user",
+            "--mount",
+            "--pid",
+            "--fork",
+            "--kill-child",
+            "--net",
+            "--ipc",
+            "--uts",
+        ]);
+
+        let status = unshare
+            .arg("--root")
+            .arg(root)
+            .arg("/bin/sh")
+            .arg("-c")
+            .arg(cmd)
+            .env_clear()
+            .env("PATH", "/usr/local/sbin:/usr/local/bin:/usr/bin")
+            .status();
+        tmpdir.close()?;
+
+        status
+    }
+}
+
+impl<'a> Pkg<'a> {
+    pub fn scriptlet(&self) -> io::Result<Option<Scriptlet>> {
+        if !self.has_scriptlet() {
+            return Ok(None);
+        }
+
+        match self.origin() {
+            PackageFrom::LocalDb => {
+                let path = Path::new(self.handle.dbpath())
+                    .join("local")
+                    .join(format!("{}-{}", self.name(), self.version()))
+                    .join("install");
+                Ok(Some(Scriptlet::new(fs::read_to_string(path)?)))
+            }
+            #[cfg(feature = "archive")]
+            PackageFrom::File => Ok(self.archive()?.install()?.map(Scriptlet::new)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "scriptlet is not available for this package",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Alpm;
+
+    const INSTALL: &str = "post_upgrade() {
+  echo upgrade
+}
+
+function post_remove {
+  rm -f boot/initramfs-linux.img
+}
+
+pre_install () {
+  :
+}
+
+helper() { :; }
+";
+
+    #[test]
+    fn test_functions() {
+        let script = Scriptlet::new(INSTALL);
+        assert_eq!(
+            script.functions(),
+            vec![
+                ScriptletFunction::PreInstall,
+                ScriptletFunction::PostUpgrade,
+                ScriptletFunction::PostRemove
+            ]
+        );
+        assert_eq!(
+            script.function_names(),
+            vec!["post_upgrade", "post_remove", "pre_install", "helper"]
+        );
+        assert!(!script.defines(ScriptletFunction::PreRemove));
+    }
+
+    #[test]
+    fn test_local_scriptlet() {
+        let handle = Alpm::new("/", "tests/db").unwrap();
+        let db = handle.localdb();
+
+        let script = db.pkg("linux").unwrap().scriptlet().unwrap().unwrap();
+        assert_eq!(
+            script.functions(),
+            vec![
+                ScriptletFunction::PostUpgrade,
+                ScriptletFunction::PostRemove
+            ]
+        );
+
+        assert!(db.pkg("pacman").unwrap().scriptlet().unwrap().is_none());
+    }
+
+    #[cfg(feature = "archive")]
+    #[test]
+    fn test_file_scriptlet() {
+        use crate::{PkgBuilder, PkgInfo, SigLevel};
+
+        let stage = tempfile::tempdir().unwrap();
+        fs::write(stage.path().join(".INSTALL"), INSTALL).unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let path = out.path().join("foo-1.0-1-any.pkg.tar.zst");
+        let pkginfo = PkgInfo {
+            name: "foo".to_string(),
+            version: "1.0-1".to_string(),
+            ..PkgInfo::default()
+        };
+        PkgBuilder::new(stage.path(), pkginfo).build(&path).unwrap();
+
+        let handle = Alpm::new("/", "tests/db").unwrap();
+        let pkg = handle
+            .pkg_load(path.to_str().unwrap(), true, SigLevel::NONE)
+            .unwrap();
+        assert_eq!(
+            pkg.pkg().scriptlet().unwrap().unwrap(),
+            Scriptlet::new(INSTALL)
+        );
+    }
+
+    #[test]
+    fn test_run() {
+        // user namespaces may not be available to run the sandbox
+        let unshare = Command::new("unshare")
+            .args(["--user", "--map-root-user", "true"])
+            .status();
+        if !unshare.is_ok_and(|s| s.success()) {
+            eprintln!("skipping test_run: unshare does not work here");
+            return;
+        }
+
+        let script = Scriptlet::new(
+            "post_upgrade() {
+  [ \"$1\" = \"2.0-1\" ] && [ \"$2\" = \"it's\" ] || exit 1
+  # the first process of its own pid namespace
+  [ $$ = 1 ] || exit 2
+  # and not allowed to change what root owns
+  touch /etc/alpm-scriptlet-test 2>/dev/null && exit 3
+  exit 4
+}
+",
+        );
+        let status = script
+            .run("/", ScriptletFunction::PostUpgrade, &["2.0-1", "it's"])
+            .unwrap();
+        assert_eq!(status.code(), Some(4));
+        assert!(!Path::new("/etc/alpm-scriptlet-test").exists());
+
+        // the script is removed afterwards
+        let leftover = fs::read_dir("/tmp")
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().starts_with("alpm_"))
+            .filter(|e| e.path().join(".INSTALL").exists())
+            .count();
+        assert_eq!(leftover, 0);
+    }
+}
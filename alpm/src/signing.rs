@@ -1,5 +1,5 @@
 use crate::utils::*;
-use crate::{free, Alpm, AlpmList, Db, FreeMethod, Package, Result};
+use crate::{free, Alpm, AlpmList, Db, FreeMethod, Pkg, Result};
 
 use alpm_sys::_alpm_sigstatus_t::*;
 use alpm_sys::_alpm_sigvalidity_t::*;
@@ -91,6 +91,7 @@ impl PgpKey {
 }
 
 #[derive(Debug)]
+#[repr(transparent)]
 pub struct SigResult {
     inner: alpm_sigresult_t,
 }
@@ -114,14 +115,34 @@ impl SigResult {
 #[derive(Debug)]
 pub struct SigList {
     inner: alpm_siglist_t,
+    // lists built in rust own their results and strings instead of libalpm
+    owned: Option<(Vec<alpm_sigresult_t>, Vec<CString>)>,
 }
 
 impl Drop for SigList {
     fn drop(&mut self) {
-        unsafe { alpm_siglist_cleanup(&mut self.inner) };
+        if self.owned.is_none() {
+            unsafe { alpm_siglist_cleanup(&mut self.inner) };
+        }
     }
 }
 
+#[cfg(feature = "openpgp")]
+#[derive(Debug, Clone)]
+pub(crate) struct OwnedSigResult {
+    pub fingerprint: String,
+    pub uid: String,
+    pub name: String,
+    pub email: String,
+    pub created: i64,
+    pub expires: i64,
+    pub length: u32,
+    pub revoked: bool,
+    pub pubkey_algo: u8,
+    pub status: SigStatus,
+    pub validity: SigValidity,
+}
+
 impl Default for SigList {
     fn default() -> SigList {
         Self::new()
@@ -135,6 +156,46 @@ impl SigList {
                 count: 0,
                 results: ptr::null_mut(),
             },
+            owned: None,
+        }
+    }
+
+    #[cfg(feature = "openpgp")]
+    pub(crate) fn from_owned(results: Vec<OwnedSigResult>) -> SigList {
+        let mut strings = Vec::new();
+        let mut string = |s: String| {
+            let s = CString::new(s).unwrap_or_default();
+            let ptr = s.as_ptr() as *mut _;
+            strings.push(s);
+            ptr
+        };
+
+        let mut results = results
+            .into_iter()
+            .map(|r| alpm_sigresult_t {
+                key: alpm_pgpkey_t {
+                    data: ptr::null_mut(),
+                    fingerprint: string(r.fingerprint),
+                    uid: string(r.uid),
+                    name: string(r.name),
+                    email: string(r.email),
+                    created: r.created,
+                    expires: r.expires,
+                    length: r.length,
+                    revoked: r.revoked as u32,
+                    pubkey_algo: r.pubkey_algo as _,
+                },
+                status: unsafe { transmute::<SigStatus, alpm_sigstatus_t>(r.status) },
+                validity: unsafe { transmute::<SigValidity, alpm_sigvalidity_t>(r.validity) },
+            })
+            .collect::<Vec<_>>();
+
+        SigList {
+            inner: alpm_siglist_t {
+                count: results.len(),
+                results: results.as_mut_ptr(),
+            },
+            owned: Some((results, strings)),
         }
     }
 
@@ -143,7 +204,7 @@ impl SigList {
     }
 }
 
-impl<'a> Package<'a> {
+impl<'a> Pkg<'a> {
     pub fn check_signature(&self) -> Result<(bool, SigList)> {
         let mut siglist = SigList::new();
         let ret = unsafe { alpm_pkg_check_pgp_signature(self.pkg, &mut siglist.inner) };
@@ -0,0 +1,330 @@
+use crate::utils::{civil_from_days, days_from_civil};
+use crate::{Alpm, Result};
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Ord, PartialOrd, Hash)]
+pub struct Snapshot {
+    year: u16,
+    month: u8,
+    day: u8,
+}
+
+impl Snapshot {
+    pub fn new(year: u16, month: u8, day: u8) -> Option<Snapshot> {
+        let days = days_from_civil(year.into(), month.into(), day.into());
+        let snapshot = Snapshot { year, month, day };
+        Some(snapshot).filter(|_| Snapshot::from_time(days * 86400) == snapshot)
+    }
+
+    /// The snapshot of the day `time` falls on, in UTC.
+    pub fn from_time(time: i64) -> Snapshot {
+        let (year, month, day) = civil_from_days(time.div_euclid(86400));
+        Snapshot {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+        }
+    }
+
+    /// Parse `YYYY/MM/DD` or `YYYY-MM-DD`.
+    pub fn parse(s: &str) -> Option<Snapshot> {
+        let mut split = s.split(&['/', '-'][..]);
+        let year = split.next()?;
+        let month = split.next()?;
+        let day = split.next()?;
+        if split.next().is_some() || year.len() != 4 || month.len() != 2 || day.len() != 2 {
+            return None;
+        }
+        Snapshot::new(year.parse().ok()?, month.parse().ok()?, day.parse().ok()?)
+    }
+
+    pub fn year(&self) -> u16 {
+        self.year
+    }
+
+    pub fn month(&self) -> u8 {
+        self.month
+    }
+
+    pub fn day(&self) -> u8 {
+        self.day
+    }
+
+    /// Midnight UTC of the snapshot's day.
+    pub fn time(&self) -> i64 {
+        days_from_civil(self.year.into(), self.month.into(), self.day.into()) * 86400
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}/{:02}/{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// Server layout of a dated repository archive. `$date`, `$repo` and `$arch`
+/// are replaced in the template.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SnapshotArchive {
+    template: String,
+}
+
+impl Default for SnapshotArchive {
+    fn default() -> SnapshotArchive {
+        SnapshotArchive::new("https://archive.archlinux.org/repos/$date/$repo/os/$arch")
+    }
+}
+
+impl SnapshotArchive {
+    pub fn new(template: impl Into<String>) -> SnapshotArchive {
+        SnapshotArchive {
+            template: template.into(),
+        }
+    }
+
+    pub fn server(&self, snapshot: Snapshot, repo: &str, arch: &str) -> String {
+        self.template
+            .replace("$date", &snapshot.to_string())
+            .replace("$repo", repo)
+            .replace("$arch", arch)
+    }
+}
+
+/// Downloaded sync databases kept per snapshot, in `<dir>/YYYY-MM-DD/`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SnapshotStore {
+    dir: PathBuf,
+}
+
+fn is_db_file(path: &Path) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    [".db", ".db.sig", ".files", ".files.sig"]
+        .iter()
+        .any(|ext| name.ends_with(ext))
+}
+
+fn copy_dbs(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+
+    for entry in fs::read_dir(from)? {
+        let path = entry?.path();
+        if path.is_file() && is_db_file(&path) {
+            fs::copy(&path, to.join(path.file_name().unwrap()))?;
+        }
+    }
+
+    Ok(())
+}
+
+impl SnapshotStore {
+    pub fn new(dir: impl Into<PathBuf>) -> SnapshotStore {
+        SnapshotStore { dir: dir.into() }
+    }
+
+    pub fn path(&self, snapshot: Snapshot) -> PathBuf {
+        self.dir.join(format!(
+            "{:04}-{:02}-{:02}",
+            snapshot.year, snapshot.month, snapshot.day
+        ))
+    }
+
+    /// Stored snapshots, oldest first.
+    pub fn generations(&self) -> io::Result<Vec<Snapshot>> {
+        let mut generations = Vec::new();
+
+        let dir = match fs::read_dir(&self.dir) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(generations),
+            Err(e) => return Err(e),
+        };
+
+        for entry in dir {
+            let entry = entry?;
+            if let Some(snapshot) = entry.file_name().to_str().and_then(Snapshot::parse) {
+                if entry.file_type()?.is_dir() {
+                    generations.push(snapshot);
+                }
+            }
+        }
+
+        generations.sort();
+        Ok(generations)
+    }
+
+    /// Copy the sync databases in `dbpath` into the store as `snapshot`.
+    pub fn save(&self, snapshot: Snapshot, dbpath: impl AsRef<Path>) -> io::Result<()> {
+        let path = self.path(snapshot);
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+        copy_dbs(&dbpath.as_ref().join("sync"), &path)
+    }
+
+    /// Replace the sync databases in `dbpath` with the ones stored for
+    /// `snapshot`. Handles using `dbpath` need `Alpm::reload_syncdbs`
+    /// afterwards.
+    pub fn restore(&self, snapshot: Snapshot, dbpath: impl AsRef<Path>) -> io::Result<()> {
+        let path = self.path(snapshot);
+        if !path.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no snapshot {}", snapshot),
+            ));
+        }
+
+        let sync = dbpath.as_ref().join("sync");
+        if sync.is_dir() {
+            for entry in fs::read_dir(&sync)? {
+                let path = entry?.path();
+                if path.is_file() && is_db_file(&path) {
+                    fs::remove_file(path)?;
+                }
+            }
+        }
+        copy_dbs(&path, &sync)
+    }
+
+    pub fn remove(&self, snapshot: Snapshot) -> io::Result<()> {
+        fs::remove_dir_all(self.path(snapshot))
+    }
+}
+
+impl Alpm {
+    /// Point every sync database at `snapshot` in `archive`.
+    pub fn pin_snapshot(&mut self, archive: &SnapshotArchive, snapshot: Snapshot) -> Result<()> {
+        let arch = match self.arch() {
+            "" => std::env::consts::ARCH.to_string(),
+            arch => arch.to_string(),
+        };
+
+        for db in self.syncdbs_mut() {
+            db.set_servers(Some(archive.server(snapshot, db.name(), &arch)))?;
+        }
+
+        Ok(())
+    }
+
+    /// Register all sync databases again so that changes to the database
+    /// files on disk are picked up.
+    pub fn reload_syncdbs(&mut self) -> Result<()> {
+        let mut dbs = Vec::new();
+        for db in self.syncdbs() {
+            let servers = db.servers().map(|s| s.to_string()).collect::<Vec<_>>();
+            dbs.push((db.name().to_string(), db.siglevel(), db.usage()?, servers));
+        }
+
+        self.unregister_all_syncdbs()?;
+
+        for (name, siglevel, usage, servers) in dbs {
+            let db = self.register_syncdb_mut(name, siglevel)?;
+            db.set_usage(usage)?;
+            db.set_servers(servers)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SigLevel;
+
+    #[test]
+    fn test_snapshot() {
+        let snapshot = Snapshot::parse("2019/06/11").unwrap();
+        assert_eq!(snapshot, Snapshot::new(2019, 6, 11).unwrap());
+        assert_eq!(Snapshot::parse("2019-06-11"), Some(snapshot));
+        assert_eq!(snapshot.to_string(), "2019/06/11");
+        assert_eq!(snapshot.time(), 1560211200);
+        assert_eq!(Snapshot::from_time(1560240901), snapshot);
+
+        assert_eq!(Snapshot::new(2019, 2, 29), None);
+        assert_eq!(Snapshot::parse("2019/6/11"), None);
+        assert_eq!(Snapshot::parse("2019/06/11/core"), None);
+
+        assert_eq!(
+            SnapshotArchive::default().server(snapshot, "core", "x86_64"),
+            "https://archive.archlinux.org/repos/2019/06/11/core/os/x86_64"
+        );
+    }
+
+    #[test]
+    fn test_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let dbpath = dir.path().join("db");
+        let store = SnapshotStore::new(dir.path().join("snapshots"));
+        let old = Snapshot::new(2019, 6, 10).unwrap();
+        let new = Snapshot::new(2019, 6, 11).unwrap();
+
+        assert!(store.generations().unwrap().is_empty());
+
+        fs::create_dir_all(dbpath.join("sync")).unwrap();
+        fs::write(dbpath.join("sync/core.db"), "old core").unwrap();
+        fs::write(dbpath.join("sync/extra.db"), "old extra").unwrap();
+        store.save(old, &dbpath).unwrap();
+
+        fs::write(dbpath.join("sync/core.db"), "new core").unwrap();
+        fs::write(dbpath.join("sync/core.db.sig"), "sig").unwrap();
+        fs::remove_file(dbpath.join("sync/extra.db")).unwrap();
+        store.save(new, &dbpath).unwrap();
+
+        assert_eq!(store.generations().unwrap(), vec![old, new]);
+
+        store.restore(old, &dbpath).unwrap();
+        assert_eq!(
+            fs::read_to_string(dbpath.join("sync/core.db")).unwrap(),
+            "old core"
+        );
+        assert!(dbpath.join("sync/extra.db").exists());
+        assert!(!dbpath.join("sync/core.db.sig").exists());
+
+        store.restore(new, &dbpath).unwrap();
+        assert!(!dbpath.join("sync/extra.db").exists());
+        assert!(dbpath.join("sync/core.db.sig").exists());
+
+        store.remove(old).unwrap();
+        assert_eq!(store.generations().unwrap(), vec![new]);
+        assert!(store.restore(old, &dbpath).is_err());
+    }
+
+    #[test]
+    fn test_pin_snapshot() {
+        let archive = tempfile::tempdir().unwrap();
+        let dbpath = tempfile::tempdir().unwrap();
+        let snapshot = Snapshot::new(2019, 6, 11).unwrap();
+        let template = format!("file://{}/$date/$repo/os/$arch", archive.path().display());
+        let root = archive.path();
+        let archive = SnapshotArchive::new(template);
+
+        let mut handle = Alpm::new("/", dbpath.path().to_str().unwrap()).unwrap();
+        handle.register_syncdb_mut("core", SigLevel::NONE).unwrap();
+        handle.pin_snapshot(&archive, snapshot).unwrap();
+        handle.reload_syncdbs().unwrap();
+
+        let arch = match handle.arch() {
+            "" => std::env::consts::ARCH,
+            arch => arch,
+        };
+        let db = handle.syncdbs().find(|db| db.name() == "core").unwrap();
+        assert_eq!(
+            db.servers().collect::<Vec<_>>(),
+            vec![archive.server(snapshot, "core", arch)]
+        );
+
+        let server = root.join("2019/06/11/core/os").join(arch);
+        fs::create_dir_all(&server).unwrap();
+        fs::copy("tests/db/sync/core.db", server.join("core.db")).unwrap();
+
+        for mut db in handle.syncdbs_mut() {
+            db.update(false).unwrap();
+        }
+        let db = handle.syncdbs().find(|db| db.name() == "core").unwrap();
+        assert!(db.pkg("linux").is_ok());
+        assert!(dbpath.path().join("sync/core.db").exists());
+    }
+}
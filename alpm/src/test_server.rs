@@ -0,0 +1,81 @@
+use crate::utils::http_date;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread;
+
+pub(crate) fn serve(files: Vec<(&'static str, Vec<u8>)>) -> String {
+    serve_with(files, None)
+}
+
+// A minimal HTTP/1.0 server that serves `files` and honours Range requests,
+// for the downloader and mirror tests.
+pub(crate) fn serve_with(
+    files: Vec<(&'static str, Vec<u8>)>,
+    last_modified: Option<i64>,
+) -> String {
+    let headers = match last_modified {
+        Some(time) => format!("Last-Modified: {}\r\n", http_date(time)),
+        None => String::new(),
+    };
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut path = String::new();
+            let mut range = 0;
+
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let Some(p) = line.split_whitespace().nth(1) {
+                path = p.to_string();
+            }
+            loop {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some(r) = line.strip_prefix("Range: bytes=") {
+                    range = r.trim().trim_end_matches('-').parse().unwrap();
+                }
+            }
+
+            let file = files.iter().find(|(name, _)| *name == path);
+            let res = match file {
+                Some((_, data)) if range > 0 => {
+                    let mut res = format!(
+                        "HTTP/1.0 206 Partial Content\r\n{}Content-Length: {}\r\n\r\n",
+                        headers,
+                        data.len() - range
+                    )
+                    .into_bytes();
+                    res.extend(&data[range..]);
+                    res
+                }
+                Some((_, data)) => {
+                    let mut res = format!(
+                        "HTTP/1.0 200 OK\r\n{}Content-Length: {}\r\n\r\n",
+                        headers,
+                        data.len()
+                    )
+                    .into_bytes();
+                    res.extend(data);
+                    res
+                }
+                None => b"HTTP/1.0 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec(),
+            };
+            let _ = stream.write_all(&res);
+        }
+    });
+
+    format!("http://{}", addr)
+}
+
+// `n` bytes of test data
+pub(crate) fn data(n: usize) -> Vec<u8> {
+    (0..n).map(|i| i as u8).collect()
+}
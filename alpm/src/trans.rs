@@ -115,10 +115,10 @@ impl Alpm {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "fixtures"))]
 mod tests {
     use super::*;
-    use crate::{set_eventcb, set_logcb, Error, Event, LogLevel, SigLevel};
+    use crate::{set_eventcb, set_logcb, Event, Fixture, FixturePkg, LogLevel};
 
     fn logcb(_level: LogLevel, msg: &str) {
         print!("{}", msg);
@@ -133,23 +133,29 @@ mod tests {
 
     #[test]
     fn test_trans() {
-        let mut handle = Alpm::new("/", "tests/db").unwrap();
+        let fixture = Fixture::new()
+            .sync(
+                "core",
+                FixturePkg::new("filesystem", "2019.05-2").file("etc/fstab", ""),
+            )
+            .build()
+            .unwrap();
+        let mut handle = fixture.handle().unwrap();
         let flags = TransFlag::DB_ONLY;
 
         set_logcb!(handle, logcb);
         set_eventcb!(handle, eventcb);
 
-        let db = handle.register_syncdb_mut("core", SigLevel::NONE).unwrap();
-        db.add_server("https://ftp.rnl.tecnico.ulisboa.pt/pub/archlinux/core/os/x86_64")
-            .unwrap();
         let db = handle.syncdbs().find(|db| db.name() == "core").unwrap();
         let pkg = db.pkg("filesystem").unwrap();
 
         handle.trans_init(flags).unwrap();
         handle.trans_add_pkg(pkg).unwrap();
         handle.trans_prepare().unwrap();
-        // Due to age the mirror now returns 404 for the package.
-        // But we're only testing that the function is called corectly anyway.
-        assert!(handle.trans_commit().unwrap_err().1 == Error::Retrieve);
+        handle.trans_commit().unwrap();
+        handle.trans_release().unwrap();
+
+        assert!(handle.localdb().pkg("filesystem").is_ok());
+        assert!(!fixture.root().join("etc/fstab").exists());
     }
 }
@@ -0,0 +1,495 @@
+use crate::{
+    decode_signature, Alpm, Error, KeyTrust, KeyringKey, SigLevel, SigList, SigStatus, SigValidity,
+};
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Ord, PartialOrd, Hash)]
+pub enum SigTarget {
+    Package,
+    Database,
+}
+
+impl fmt::Display for SigTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SigTarget::Package => f.write_str("package"),
+            SigTarget::Database => f.write_str("database"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TrustIssue {
+    /// The siglevel is `SigLevel::NONE`.
+    NoVerification,
+    NotChecked(SigTarget),
+    Optional(SigTarget),
+    UnknownOk(SigTarget),
+    MarginalOk(SigTarget),
+    Unsigned,
+    KeyUnknown(String),
+    KeyExpired(String),
+    SigExpired(String),
+    KeyDisabled(String),
+    KeyRevoked(String),
+    InvalidSignature(String),
+    MarginalTrust(String),
+    Untrusted(String),
+    CheckFailed(String),
+}
+
+impl fmt::Display for TrustIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrustIssue::NoVerification => f.write_str("signatures are not checked"),
+            TrustIssue::NotChecked(t) => write!(f, "{} signatures are not checked", t),
+            TrustIssue::Optional(t) => write!(f, "{} signatures are optional", t),
+            TrustIssue::UnknownOk(t) => write!(f, "{} signatures by unknown keys are accepted", t),
+            TrustIssue::MarginalOk(t) => write!(
+                f,
+                "{} signatures by marginally trusted keys are accepted",
+                t
+            ),
+            TrustIssue::Unsigned => f.write_str("not signed"),
+            TrustIssue::KeyUnknown(k) => write!(f, "signed by unknown key {}", k),
+            TrustIssue::KeyExpired(k) => write!(f, "signed by expired key {}", k),
+            TrustIssue::SigExpired(k) => write!(f, "signature by {} has expired", k),
+            TrustIssue::KeyDisabled(k) => write!(f, "signed by disabled key {}", k),
+            TrustIssue::KeyRevoked(k) => write!(f, "signed by revoked key {}", k),
+            TrustIssue::InvalidSignature(k) => write!(f, "invalid signature by {}", k),
+            TrustIssue::MarginalTrust(k) => write!(f, "signed by marginally trusted key {}", k),
+            TrustIssue::Untrusted(k) => write!(f, "signed by untrusted key {}", k),
+            TrustIssue::CheckFailed(e) => write!(f, "signature could not be checked: {}", e),
+        }
+    }
+}
+
+/// The issues with accepting signatures of `targets` according to `level`.
+pub fn siglevel_issues(level: SigLevel, targets: &[SigTarget]) -> Vec<TrustIssue> {
+    if level == SigLevel::NONE {
+        return vec![TrustIssue::NoVerification];
+    }
+
+    let mut issues = Vec::new();
+    for &target in targets {
+        let (required, optional, marginal_ok, unknown_ok) = match target {
+            SigTarget::Package => (
+                SigLevel::PACKAGE,
+                SigLevel::PACKAGE_OPTIONAL,
+                SigLevel::PACKAGE_MARGINAL_OK,
+                SigLevel::PACKAGE_UNKNOWN_OK,
+            ),
+            SigTarget::Database => (
+                SigLevel::DATABASE,
+                SigLevel::DATABASE_OPTIONAL,
+                SigLevel::DATABASE_MARGINAL_OK,
+                SigLevel::DATABASE_UNKNOWN_OK,
+            ),
+        };
+
+        if !level.contains(required) {
+            issues.push(TrustIssue::NotChecked(target));
+            continue;
+        }
+        if level.contains(optional) {
+            issues.push(TrustIssue::Optional(target));
+        }
+        if level.contains(unknown_ok) {
+            issues.push(TrustIssue::UnknownOk(target));
+        }
+        if level.contains(marginal_ok) {
+            issues.push(TrustIssue::MarginalOk(target));
+        }
+    }
+
+    issues
+}
+
+fn result_issue(
+    status: SigStatus,
+    validity: SigValidity,
+    revoked: bool,
+    key: &str,
+) -> Option<TrustIssue> {
+    let key = key.to_string();
+    match status {
+        _ if revoked => Some(TrustIssue::KeyRevoked(key)),
+        SigStatus::Valid => match validity {
+            SigValidity::Full => None,
+            SigValidity::Marginal => Some(TrustIssue::MarginalTrust(key)),
+            SigValidity::Never | SigValidity::Unknown => Some(TrustIssue::Untrusted(key)),
+        },
+        SigStatus::KeyExpired => Some(TrustIssue::KeyExpired(key)),
+        SigStatus::SigExpired => Some(TrustIssue::SigExpired(key)),
+        SigStatus::KeyUnknown => Some(TrustIssue::KeyUnknown(key)),
+        SigStatus::KeyDisabled => Some(TrustIssue::KeyDisabled(key)),
+        SigStatus::Invalid => Some(TrustIssue::InvalidSignature(key)),
+    }
+}
+
+/// The issues with the results of `check_signature`.
+pub fn siglist_issues(siglist: &SigList) -> Vec<TrustIssue> {
+    if siglist.results().is_empty() {
+        return vec![TrustIssue::Unsigned];
+    }
+
+    siglist
+        .results()
+        .iter()
+        .filter_map(|r| {
+            let key = r.key();
+            result_issue(
+                r.status(),
+                r.validity(),
+                key.revoked() != 0,
+                key.fingerprint(),
+            )
+        })
+        .collect()
+}
+
+// `keyid` is a long key id or fingerprint, `key` its entry in the keyring
+fn key_issue(keyid: &str, key: Option<&KeyringKey>) -> Option<TrustIssue> {
+    let key = match key {
+        Some(key) => key,
+        None => return Some(TrustIssue::KeyUnknown(keyid.to_string())),
+    };
+
+    // a subkey can be revoked or expire before its primary key
+    let keyid = keyid.to_uppercase();
+    let subkey = key.subkeys.iter().find(|s| s.fingerprint.ends_with(&keyid));
+    let validity = match subkey.map(|s| s.validity) {
+        Some(validity @ KeyTrust::Revoked) | Some(validity @ KeyTrust::Expired) => validity,
+        _ => key.validity,
+    };
+
+    let fingerprint = key.fingerprint.clone();
+    match validity {
+        _ if key.disabled => Some(TrustIssue::KeyDisabled(fingerprint)),
+        KeyTrust::Revoked => Some(TrustIssue::KeyRevoked(fingerprint)),
+        KeyTrust::Expired => Some(TrustIssue::KeyExpired(fingerprint)),
+        KeyTrust::Marginal => Some(TrustIssue::MarginalTrust(fingerprint)),
+        KeyTrust::Full | KeyTrust::Ultimate => None,
+        _ => Some(TrustIssue::Untrusted(fingerprint)),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PkgTrust {
+    pub name: String,
+    pub version: String,
+    pub issues: Vec<TrustIssue>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RepoTrust {
+    pub repo: String,
+    pub siglevel: SigLevel,
+    /// Issues with the siglevel and the database signature.
+    pub issues: Vec<TrustIssue>,
+    /// Packages with issues.
+    pub pkgs: Vec<PkgTrust>,
+    /// Packages without an embedded signature that are not in the cache.
+    pub unchecked: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TrustReport {
+    pub default_siglevel: SigLevel,
+    pub local_file_siglevel: SigLevel,
+    pub remote_file_siglevel: SigLevel,
+    pub local_file_issues: Vec<TrustIssue>,
+    pub remote_file_issues: Vec<TrustIssue>,
+    pub repos: Vec<RepoTrust>,
+}
+
+impl TrustReport {
+    pub fn is_empty(&self) -> bool {
+        self.local_file_issues.is_empty()
+            && self.remote_file_issues.is_empty()
+            && self
+                .repos
+                .iter()
+                .all(|r| r.issues.is_empty() && r.pkgs.is_empty())
+    }
+}
+
+impl fmt::Display for TrustReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for issue in &self.local_file_issues {
+            writeln!(f, "local files: {}", issue)?;
+        }
+        for issue in &self.remote_file_issues {
+            writeln!(f, "remote files: {}", issue)?;
+        }
+        for repo in &self.repos {
+            for issue in &repo.issues {
+                writeln!(f, "{}: {}", repo.repo, issue)?;
+            }
+            for pkg in &repo.pkgs {
+                for issue in &pkg.issues {
+                    writeln!(f, "{}/{} {}: {}", repo.repo, pkg.name, pkg.version, issue)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn resolve(level: SigLevel, default: SigLevel) -> SigLevel {
+    if level.contains(SigLevel::USE_DEFAULT) {
+        default
+    } else {
+        level
+    }
+}
+
+fn alpm_error(what: &str, e: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", what, e))
+}
+
+impl Alpm {
+    /// Audit the signature policy of every sync database and the signatures
+    /// of its packages. Packages are verified against their cached package
+    /// file and `.sig` if there is one, otherwise the keys of their embedded
+    /// signature are looked up in the keyring.
+    pub fn trust_report(&self) -> io::Result<TrustReport> {
+        let default = self.default_siglevel();
+        let local_file = resolve(self.local_file_siglevel(), default);
+        let remote_file = resolve(self.remote_file_siglevel(), default);
+
+        let keyring = self.keyring();
+        let keys = if keyring.dir().is_dir() {
+            keyring.keys()?
+        } else {
+            Vec::new()
+        };
+        // packages are often signed with a subkey
+        let lookup = |keyid: &str| {
+            let keyid = keyid.to_uppercase();
+            keys.iter().find(|k| {
+                k.fingerprint.ends_with(&keyid)
+                    || k.subkeys.iter().any(|s| s.fingerprint.ends_with(&keyid))
+            })
+        };
+
+        let mut repos = Vec::new();
+
+        for db in self.syncdbs() {
+            let siglevel = resolve(db.siglevel(), default);
+            let mut issues = siglevel_issues(siglevel, &[SigTarget::Database, SigTarget::Package]);
+
+            match db.check_signature() {
+                Ok((_, siglist)) => issues.extend(siglist_issues(&siglist)),
+                Err(Error::SigMissing) => issues.push(TrustIssue::Unsigned),
+                Err(e) => issues.push(TrustIssue::CheckFailed(e.to_string())),
+            }
+
+            let mut pkgs = Vec::new();
+            let mut unchecked = 0;
+
+            for pkg in db.pkgs().map_err(|e| alpm_error(db.name(), e))? {
+                let cached = self
+                    .cachedirs()
+                    .map(|dir| Path::new(dir).join(pkg.filename()))
+                    .find(|path| path.exists());
+                let signed = cached.as_ref().filter(|path| {
+                    let mut sig = path.as_os_str().to_owned();
+                    sig.push(".sig");
+                    Path::new(&sig).exists()
+                });
+
+                let issues = match (signed, pkg.base64_sig()) {
+                    (Some(path), _) => {
+                        let checked = self
+                            .pkg_load(path.to_str().unwrap_or(""), false, SigLevel::NONE)
+                            .and_then(|loaded| loaded.pkg().check_signature());
+                        match checked {
+                            Ok((_, siglist)) => siglist_issues(&siglist),
+                            Err(e) => vec![TrustIssue::CheckFailed(e.to_string())],
+                        }
+                    }
+                    (None, Some(sig)) => {
+                        let keyids = decode_signature(sig)
+                            .map_err(|_| Error::SigInvalid)
+                            .and_then(|sig| self.extract_keyid(pkg.name(), &sig));
+                        match keyids {
+                            Ok(keyids) => keyids
+                                .filter_map(|keyid| key_issue(&keyid, lookup(&keyid)))
+                                .collect(),
+                            Err(e) => vec![TrustIssue::CheckFailed(e.to_string())],
+                        }
+                    }
+                    (None, None) if cached.is_some() => vec![TrustIssue::Unsigned],
+                    (None, None) => {
+                        unchecked += 1;
+                        continue;
+                    }
+                };
+
+                if !issues.is_empty() {
+                    pkgs.push(PkgTrust {
+                        name: pkg.name().to_string(),
+                        version: pkg.version().to_string(),
+                        issues,
+                    });
+                }
+            }
+
+            repos.push(RepoTrust {
+                repo: db.name().to_string(),
+                siglevel,
+                issues,
+                pkgs,
+                unchecked,
+            });
+        }
+
+        Ok(TrustReport {
+            default_siglevel: default,
+            local_file_siglevel: local_file,
+            remote_file_siglevel: remote_file,
+            local_file_issues: siglevel_issues(local_file, &[SigTarget::Package]),
+            remote_file_issues: siglevel_issues(remote_file, &[SigTarget::Package]),
+            repos,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyringSubkey;
+
+    #[test]
+    fn test_siglevel_issues() {
+        let both = &[SigTarget::Database, SigTarget::Package];
+        assert_eq!(
+            siglevel_issues(SigLevel::NONE, both),
+            vec![TrustIssue::NoVerification]
+        );
+        assert!(siglevel_issues(SigLevel::PACKAGE | SigLevel::DATABASE, both).is_empty());
+        assert_eq!(
+            siglevel_issues(
+                SigLevel::PACKAGE | SigLevel::PACKAGE_MARGINAL_OK | SigLevel::PACKAGE_UNKNOWN_OK,
+                both
+            ),
+            vec![
+                TrustIssue::NotChecked(SigTarget::Database),
+                TrustIssue::UnknownOk(SigTarget::Package),
+                TrustIssue::MarginalOk(SigTarget::Package),
+            ]
+        );
+        assert_eq!(
+            siglevel_issues(
+                SigLevel::PACKAGE | SigLevel::DATABASE | SigLevel::DATABASE_OPTIONAL,
+                both
+            ),
+            vec![TrustIssue::Optional(SigTarget::Database)]
+        );
+        assert!(siglevel_issues(SigLevel::PACKAGE, &[SigTarget::Package]).is_empty());
+    }
+
+    #[test]
+    fn test_result_issue() {
+        let fpr = "F043329F98FB507DE8A774AFF20A2BC8A18AF47D";
+        assert_eq!(
+            result_issue(SigStatus::Valid, SigValidity::Full, false, fpr),
+            None
+        );
+        assert_eq!(
+            result_issue(SigStatus::Valid, SigValidity::Marginal, false, fpr),
+            Some(TrustIssue::MarginalTrust(fpr.into()))
+        );
+        assert_eq!(
+            result_issue(SigStatus::Valid, SigValidity::Full, true, fpr),
+            Some(TrustIssue::KeyRevoked(fpr.into()))
+        );
+        assert_eq!(
+            result_issue(SigStatus::KeyUnknown, SigValidity::Unknown, false, fpr),
+            Some(TrustIssue::KeyUnknown(fpr.into()))
+        );
+        assert_eq!(
+            result_issue(SigStatus::KeyExpired, SigValidity::Full, false, fpr),
+            Some(TrustIssue::KeyExpired(fpr.into()))
+        );
+    }
+
+    #[test]
+    fn test_key_issue() {
+        let mut key = KeyringKey {
+            fingerprint: "F043329F98FB507DE8A774AFF20A2BC8A18AF47D".into(),
+            uids: vec!["Packager <packager@example.org>".into()],
+            subkeys: vec![KeyringSubkey {
+                fingerprint: "3B8A6A4E0D2F3C8E63D2A1C5E6B2F1A0C9D8E7F6".into(),
+                created: 0,
+                expires: None,
+                validity: KeyTrust::Revoked,
+            }],
+            created: 0,
+            expires: None,
+            validity: KeyTrust::Full,
+            owner_trust: KeyTrust::Unknown,
+            disabled: false,
+            secret: false,
+        };
+
+        assert_eq!(key_issue("F20A2BC8A18AF47D", Some(&key)), None);
+        assert_eq!(
+            key_issue("e6b2f1a0c9d8e7f6", Some(&key)),
+            Some(TrustIssue::KeyRevoked(key.fingerprint.clone()))
+        );
+        assert_eq!(
+            key_issue("F20A2BC8A18AF47D", None),
+            Some(TrustIssue::KeyUnknown("F20A2BC8A18AF47D".into()))
+        );
+        key.validity = KeyTrust::Marginal;
+        assert_eq!(
+            key_issue("F20A2BC8A18AF47D", Some(&key)),
+            Some(TrustIssue::MarginalTrust(key.fingerprint.clone()))
+        );
+        key.validity = KeyTrust::Expired;
+        assert_eq!(
+            key_issue("F20A2BC8A18AF47D", Some(&key)),
+            Some(TrustIssue::KeyExpired(key.fingerprint.clone()))
+        );
+        key.disabled = true;
+        assert_eq!(
+            key_issue("F20A2BC8A18AF47D", Some(&key)),
+            Some(TrustIssue::KeyDisabled(key.fingerprint.clone()))
+        );
+    }
+
+    #[test]
+    fn test_report_display() {
+        let report = TrustReport {
+            default_siglevel: SigLevel::NONE,
+            local_file_siglevel: SigLevel::NONE,
+            remote_file_siglevel: SigLevel::PACKAGE,
+            local_file_issues: vec![TrustIssue::NoVerification],
+            remote_file_issues: Vec::new(),
+            repos: vec![RepoTrust {
+                repo: "core".into(),
+                siglevel: SigLevel::PACKAGE,
+                issues: vec![TrustIssue::NotChecked(SigTarget::Database)],
+                pkgs: vec![PkgTrust {
+                    name: "foo".into(),
+                    version: "1.0-1".into(),
+                    issues: vec![TrustIssue::KeyUnknown("F20A2BC8A18AF47D".into())],
+                }],
+                unchecked: 0,
+            }],
+        };
+
+        assert!(!report.is_empty());
+        assert_eq!(
+            report.to_string(),
+            "local files: signatures are not checked\n\
+             core: database signatures are not checked\n\
+             core/foo 1.0-1: signed by unknown key F20A2BC8A18AF47D\n"
+        );
+    }
+}
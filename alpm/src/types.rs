@@ -564,8 +564,8 @@ pub struct SelectProviderQuestion {
 
 #[derive(Debug)]
 pub struct ImportKeyQuestion {
-    handle: Alpm,
-    inner: *mut alpm_question_import_key_t,
+    pub(crate) handle: Alpm,
+    pub(crate) inner: *mut alpm_question_import_key_t,
 }
 
 #[derive(Debug)]
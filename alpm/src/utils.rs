@@ -29,3 +29,138 @@ pub fn to_strlist<S: AsRef<str>, I: IntoIterator<Item = S>>(list: I) -> *mut alp
 
     alpmlist
 }
+
+fn fnmatch_class(class: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 0;
+    let negate = matches!(class.first(), Some('!') | Some('^'));
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+
+    while i < class.len() {
+        if class[i] == ']' && !first {
+            return Some((matched != negate, i + 1));
+        }
+        first = false;
+
+        let lo = class[i];
+        if i + 2 < class.len() && class[i + 1] == '-' && class[i + 2] != ']' {
+            matched |= lo <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            matched |= lo == c;
+            i += 1;
+        }
+    }
+
+    None
+}
+
+fn fnmatch_chars(pattern: &[char], s: &[char]) -> bool {
+    match pattern.first() {
+        None => s.is_empty(),
+        Some('*') => (0..=s.len()).any(|i| fnmatch_chars(&pattern[1..], &s[i..])),
+        Some('?') => !s.is_empty() && fnmatch_chars(&pattern[1..], &s[1..]),
+        Some('[') if !s.is_empty() => match fnmatch_class(&pattern[1..], s[0]) {
+            Some((matched, len)) => matched && fnmatch_chars(&pattern[len + 1..], &s[1..]),
+            None => s[0] == '[' && fnmatch_chars(&pattern[1..], &s[1..]),
+        },
+        Some('\\') if pattern.len() > 1 => {
+            s.first() == Some(&pattern[1]) && fnmatch_chars(&pattern[2..], &s[1..])
+        }
+        Some(&c) => s.first() == Some(&c) && fnmatch_chars(&pattern[1..], &s[1..]),
+    }
+}
+
+// fnmatch(3) without any flags, so '*' also matches '/'
+pub(crate) fn fnmatch(pattern: &str, s: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let s = s.chars().collect::<Vec<_>>();
+    fnmatch_chars(&pattern, &s)
+}
+
+// the last matching pattern wins, patterns starting with '!' invert the match
+pub(crate) fn fnmatch_patterns<S: AsRef<str>>(patterns: &[S], s: &str) -> Option<bool> {
+    patterns.iter().rev().find_map(|pattern| {
+        let pattern = pattern.as_ref();
+        match pattern.strip_prefix('!') {
+            Some(pattern) => Some(false).filter(|_| fnmatch(pattern, s)),
+            None => Some(true).filter(|_| fnmatch(pattern, s)),
+        }
+    })
+}
+
+// days since the epoch of a date in the proleptic Gregorian calendar, shared
+// by the log, HTTP date and snapshot date parsers
+pub(crate) fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = (m + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+// the inverse of days_from_civil, as (year, month, day)
+pub(crate) fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+
+    (y, m, d)
+}
+
+#[cfg(feature = "downloader")]
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+#[cfg(feature = "downloader")]
+pub(crate) fn http_date(time: i64) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+    let days = time.div_euclid(86400);
+    let secs = time.rem_euclid(86400);
+    let (y, m, d) = civil_from_days(days);
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[days.rem_euclid(7) as usize],
+        d,
+        MONTHS[m as usize - 1],
+        y,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(feature = "downloader")]
+// Tue, 11 Jun 2019 08:15:01 GMT
+pub(crate) fn parse_http_date(s: &str) -> Option<i64> {
+    let mut split = s.split_whitespace().skip(1);
+    let d = split.next()?.parse::<i64>().ok()?;
+    let month = split.next()?;
+    let m = MONTHS.iter().position(|&m| m == month)? as i64 + 1;
+    let y = split.next()?.parse::<i64>().ok()?;
+
+    let mut time = split.next()?.split(':').map(|n| n.parse::<i64>().ok());
+    let hh = time.next()??;
+    let mm = time.next()??;
+    let ss = time.next()??;
+    if split.next()? != "GMT" {
+        return None;
+    }
+
+    Some(days_from_civil(y, m, d) * 86400 + hh * 3600 + mm * 60 + ss)
+}
@@ -0,0 +1,233 @@
+use crate::{
+    compute_md5sum, compute_sha256sum, Alpm, Error, ImportKeyQuestion, Keyring, Result, SigLevel,
+    SigList, SigStatus,
+};
+
+use alpm_sys::_alpm_question_type_t::*;
+use alpm_sys::*;
+
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ChecksumStatus {
+    Ok,
+    Mismatch,
+    /// No sync database has this version of the package.
+    Unknown,
+}
+
+#[derive(Debug)]
+pub struct PkgFileVerification {
+    pub path: PathBuf,
+    pub name: String,
+    pub version: String,
+    /// Compared against the sync database entry of the same version.
+    pub checksum: ChecksumStatus,
+    /// Whether the package would be accepted with the given siglevel.
+    pub accepted: bool,
+    /// Why the package would not be accepted.
+    pub error: Option<Error>,
+    /// `None` if the package is not signed.
+    pub sigs: Option<SigList>,
+    /// The key ids the signature was made with.
+    pub keyids: Vec<String>,
+}
+
+impl PkgFileVerification {
+    pub fn signed(&self) -> bool {
+        self.sigs.is_some()
+    }
+
+    /// Key ids of signatures made by keys that are not in the keyring.
+    pub fn missing_keys(&self) -> Vec<String> {
+        let sigs = match self.sigs {
+            Some(ref sigs) => sigs,
+            None => return Vec::new(),
+        };
+
+        let unknown = sigs
+            .results()
+            .iter()
+            .filter(|r| r.status() == SigStatus::KeyUnknown)
+            .map(|r| r.key().fingerprint().to_string())
+            .collect::<Vec<_>>();
+        // libalpm does not always know the fingerprint of unknown keys
+        if !unknown.is_empty() && unknown.iter().all(|fpr| fpr.is_empty()) {
+            return self.keyids.clone();
+        }
+        unknown.into_iter().filter(|fpr| !fpr.is_empty()).collect()
+    }
+}
+
+// split "Name <email>" the way gpgme does
+fn split_uid(uid: &str) -> (&str, &str) {
+    match (uid.rfind(" <"), uid.ends_with('>')) {
+        (Some(i), true) => (&uid[..i], &uid[i + 2..uid.len() - 1]),
+        _ => (uid, ""),
+    }
+}
+
+impl Alpm {
+    /// Check the checksum and signature of the package file at `path`
+    /// without adding it to a transaction.
+    pub fn verify_package_file(
+        &self,
+        path: impl AsRef<Path>,
+        siglevel: SigLevel,
+    ) -> Result<PkgFileVerification> {
+        let path = path.as_ref();
+        let filename = path.to_str().ok_or(Error::WrongArgs)?;
+
+        let loaded = self.pkg_load(filename, false, SigLevel::NONE)?;
+        let pkg = loaded.pkg();
+        let name = pkg.name().to_string();
+        let version = pkg.version().to_string();
+
+        let syncpkg = self
+            .syncdbs()
+            .find_map(|db| db.pkg(&name).ok())
+            .filter(|p| *p.version() == *version);
+        let matches = match syncpkg.map(|p| (p.sha256sum(), p.md5sum())) {
+            Some((Some(sum), _)) => Some(compute_sha256sum(filename).ok().as_deref() == Some(sum)),
+            Some((None, Some(sum))) => Some(compute_md5sum(filename).ok().as_deref() == Some(sum)),
+            _ => None,
+        };
+        let checksum = match matches {
+            Some(true) => ChecksumStatus::Ok,
+            Some(false) => ChecksumStatus::Mismatch,
+            None => ChecksumStatus::Unknown,
+        };
+
+        let sigs = match pkg.check_signature() {
+            Ok((_, sigs)) => Some(sigs),
+            Err(Error::SigMissing) => None,
+            Err(e) => return Err(e),
+        };
+
+        let mut sigpath = path.as_os_str().to_owned();
+        sigpath.push(".sig");
+        let keyids = match fs::read(sigpath) {
+            Ok(sig) => self
+                .extract_keyid(&name, &sig)
+                .map(|keyids| keyids.collect())
+                .unwrap_or_default(),
+            Err(_) => Vec::new(),
+        };
+
+        let error = self.pkg_load(filename, false, siglevel).err();
+
+        Ok(PkgFileVerification {
+            path: path.to_path_buf(),
+            name,
+            version,
+            checksum,
+            accepted: error.is_none(),
+            error,
+            sigs,
+            keyids,
+        })
+    }
+
+    /// Offer the keys missing to verify `verification` for import from
+    /// `source`, such as a user's gpg home directory. `ask` is called with an
+    /// `ImportKeyQuestion` for every missing key found in `source` and the
+    /// keys it answers yes to are imported into the handle's keyring.
+    /// Returns the fingerprints of the imported keys.
+    pub fn import_missing_keys<F: FnMut(&mut ImportKeyQuestion)>(
+        &self,
+        verification: &PkgFileVerification,
+        source: &Keyring,
+        mut ask: F,
+    ) -> io::Result<Vec<String>> {
+        let mut imported = Vec::new();
+
+        for keyid in verification.missing_keys() {
+            let key = match source.key(&keyid) {
+                Ok(key) => key,
+                Err(_) => continue,
+            };
+
+            let uid = key.uids.first().map(|u| u.as_str()).unwrap_or("");
+            let (name, email) = split_uid(uid);
+            let strings = [key.fingerprint.as_str(), uid, name, email]
+                .iter()
+                .map(|s| CString::new(*s).unwrap_or_default())
+                .collect::<Vec<_>>();
+
+            let mut pgpkey = alpm_pgpkey_t {
+                data: ptr::null_mut(),
+                fingerprint: strings[0].as_ptr() as *mut _,
+                uid: strings[1].as_ptr() as *mut _,
+                name: strings[2].as_ptr() as *mut _,
+                email: strings[3].as_ptr() as *mut _,
+                created: key.created,
+                expires: key.expires.unwrap_or(0),
+                length: 0,
+                revoked: 0,
+                pubkey_algo: 0,
+            };
+            let mut inner = alpm_question_import_key_t {
+                type_: ALPM_QUESTION_IMPORT_KEY,
+                import: 0,
+                key: &mut pgpkey,
+            };
+            let mut question = ImportKeyQuestion {
+                handle: Alpm {
+                    handle: self.handle,
+                    drop: false,
+                },
+                inner: &mut inner,
+            };
+
+            ask(&mut question);
+            if !question.import() {
+                continue;
+            }
+
+            self.keyring()
+                .import_data(&source.export(&key.fingerprint)?)?;
+            imported.push(key.fingerprint);
+        }
+
+        Ok(imported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_uid() {
+        assert_eq!(
+            split_uid("Foo Bar <foo@example.org>"),
+            ("Foo Bar", "foo@example.org")
+        );
+        assert_eq!(split_uid("Foo Bar"), ("Foo Bar", ""));
+    }
+
+    #[test]
+    fn test_verify_package_file() {
+        let handle = Alpm::new("/", "tests/db").unwrap();
+        handle.register_syncdb("core", SigLevel::NONE).unwrap();
+
+        let verification = handle
+            .verify_package_file("tests/pacman-5.1.3-1-x86_64.pkg.tar.xz", SigLevel::NONE)
+            .unwrap();
+        assert_eq!(verification.name, "pacman");
+        assert_eq!(verification.version, "5.1.3-1");
+        assert!(verification.accepted);
+        assert!(!verification.signed());
+        assert!(verification.missing_keys().is_empty());
+
+        let verification = handle
+            .verify_package_file("tests/pacman-5.1.3-1-x86_64.pkg.tar.xz", SigLevel::PACKAGE)
+            .unwrap();
+        assert!(!verification.accepted);
+        assert!(verification.error.is_some());
+    }
+}
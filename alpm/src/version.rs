@@ -88,7 +88,7 @@ impl PartialOrd<Version> for &Ver {
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Hash)]
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
 pub struct Version(CString);
 
 impl Version {